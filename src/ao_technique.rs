@@ -0,0 +1,51 @@
+use wgpu::{CommandEncoder, TextureView};
+
+use crate::{
+    resource_manager::{Handle, ResourceManager},
+    scene::Scene,
+};
+
+/// A screen space AO implementation the renderer can switch between.
+pub trait AoTechnique {
+    fn name(&self) -> &'static str;
+
+    /// The technique's parameters, shown while it's selected.
+    fn ui(&mut self, rm: &mut ResourceManager, ui: &mut egui::Ui);
+
+    fn resize(&self, rm: &mut ResourceManager, dimensions: (u32, u32));
+
+    /// Filled by `occlusion` and read by `composite`, one AO value per pixel.
+    fn ao_texture(&self) -> Handle;
+
+    /// Renders the AO into `ao_texture`.
+    fn occlusion(&self, rm: &ResourceManager, encoder: &mut CommandEncoder, scene: &Scene);
+
+    /// Writes the AO to `view`, multiplying it into what's there if `multiply`.
+    fn composite(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        multiply: bool,
+    );
+
+    /// Intermediate textures worth inspecting in the debug views, with their names.
+    fn debug_targets(&self) -> Vec<(&'static str, Handle)> {
+        vec![("AO", self.ao_texture())]
+    }
+
+    /// Whether "AO only" should show `debug_pass` instead of the AO.
+    fn shows_debug_output(&self) -> bool {
+        false
+    }
+
+    /// Writes technique specific debug output to `view`.
+    fn debug_pass(
+        &self,
+        _rm: &ResourceManager,
+        _encoder: &mut CommandEncoder,
+        _scene: &Scene,
+        _view: &TextureView,
+    ) {
+    }
+}
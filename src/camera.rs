@@ -1,5 +1,8 @@
-use glam::{vec3, vec4, Mat4, Vec3, Vec4};
-use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+use glam::{vec3, vec4, Mat4, Vec2, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
 
 use crate::scene::SceneUniformData;
 
@@ -8,10 +11,13 @@ pub struct Camera {
     front: Vec3,
     up: Vec3,
 
-    fov_y_radians: f32,
+    fov_y_degrees: f32,
     aspect_ratio: f32,
     z_near: f32,
     z_far: f32,
+    /// Maps the near plane to depth 1 and the far plane to 0, spreading float precision evenly
+    /// over distance. The depth buffer must be cleared to 0 and tested with `Greater`.
+    pub reverse_z: bool,
 }
 
 impl Default for Camera {
@@ -21,21 +27,77 @@ impl Default for Camera {
             front: vec3(0.0, 0.0, 1.0),
             up: vec3(0.0, 1.0, 0.0),
 
-            fov_y_radians: 90.0,
+            fov_y_degrees: 90.0,
             aspect_ratio: 1600.0 / 900.0,
             z_near: 0.01,
             z_far: 100.0,
+            reverse_z: false,
         }
     }
 }
 
+/// The viewpoint, saved to and loaded from JSON files. Yaw and pitch come from the controller.
+#[derive(Serialize, Deserialize)]
+pub struct CameraState {
+    pub eye: [f32; 3],
+    pub front: [f32; 3],
+    pub up: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov_y_degrees: f32,
+    pub z_near: f32,
+    pub z_far: f32,
+}
+
 impl Camera {
+    pub fn state(&self, controller: &dyn CameraController) -> CameraState {
+        let (yaw, pitch) = controller.orientation();
+        CameraState {
+            eye: self.eye.to_array(),
+            front: self.front.to_array(),
+            up: self.up.to_array(),
+            yaw,
+            pitch,
+            fov_y_degrees: self.fov_y_degrees,
+            z_near: self.z_near,
+            z_far: self.z_far,
+        }
+    }
+
+    pub fn restore(&mut self, controller: &mut dyn CameraController, state: &CameraState) {
+        self.eye = Vec3::from_array(state.eye);
+        self.front = Vec3::from_array(state.front);
+        self.up = Vec3::from_array(state.up);
+        self.fov_y_degrees = state.fov_y_degrees;
+        self.z_near = state.z_near;
+        self.z_far = state.z_far;
+        controller.set_orientation(self, state.yaw, state.pitch);
+    }
+
+    /// Moves the camera back along its front until the sphere fits in view, pushing the far
+    /// plane out if it would clip the sphere.
+    pub fn frame(&mut self, center: Vec3, radius: f32) {
+        let distance = fit_distance(radius, self.fov_y_degrees, self.aspect_ratio);
+        self.eye = center - self.front.normalize() * distance;
+        self.z_far = self.z_far.max(distance + radius);
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        self.aspect_ratio = aspect_ratio;
+    }
+
     pub fn build_uniforms(&self) -> SceneUniformData {
+        // Swapping the planes flips the depth range
+        let (depth_0, depth_1) = if self.reverse_z {
+            (self.z_far, self.z_near)
+        } else {
+            (self.z_near, self.z_far)
+        };
         let perspective = Mat4::perspective_lh(
-            self.fov_y_radians.to_radians(),
+            self.fov_y_degrees.to_radians(),
             self.aspect_ratio,
-            self.z_near,
-            self.z_far,
+            depth_0,
+            depth_1,
         );
         let inverse_perspective = perspective.inverse();
 
@@ -49,19 +111,59 @@ impl Camera {
             inverse_view,
             camera_position: self.eye,
             aspect_ratio: self.aspect_ratio,
+            z_near: self.z_near,
+            z_far: self.z_far,
+            // The light fields are filled in by DirectionalLight::apply
+            ..Default::default()
         }
     }
 }
 
+/// Mirrors `view_position_from_depth` in common.wgsl. `uv` has its origin at the top left, `depth`
+/// is the hardware depth in either Z convention.
+pub fn view_position_from_depth(uv: Vec2, depth: f32, inverse_perspective: Mat4) -> Vec3 {
+    let position = inverse_perspective * vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    position.truncate() / position.w
+}
+
+/// How far from a sphere's center the camera has to be for the whole sphere to be in view, using
+/// whichever of the vertical and horizontal field of view is narrower.
+pub fn fit_distance(radius: f32, fov_y_degrees: f32, aspect_ratio: f32) -> f32 {
+    let half_fov_y = fov_y_degrees.to_radians() * 0.5;
+    let half_fov_x = (half_fov_y.tan() * aspect_ratio).atan();
+    radius / half_fov_y.min(half_fov_x).sin()
+}
+
+const FOV_RANGE: std::ops::RangeInclusive<f32> = 10.0..=140.0;
+// Per notch of the mouse wheel
+const ZOOM_STEP_DEGREES: f32 = 5.0;
+const DOLLY_STEP: f32 = 0.5;
+// Trackpads report pixels, roughly this many make up one wheel notch
+const PIXELS_PER_NOTCH: f32 = 50.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WheelMode {
+    /// Narrows or widens the field of view
+    Zoom,
+    /// Moves the camera along its front
+    Dolly,
+}
+
 pub trait CameraController {
     fn input(&mut self, event: &WindowEvent);
-    fn update(&mut self, camera: &mut Camera);
+    /// `dt` is the frame time in seconds, movement should scale with it.
+    fn update(&mut self, camera: &mut Camera, dt: f32);
     fn ui(&mut self, camera: &mut Camera, ui: &mut egui::Ui);
+    /// Yaw and pitch in degrees
+    fn orientation(&self) -> (f32, f32);
+    /// Called after the camera is restored, so the next update keeps looking along its front.
+    fn set_orientation(&mut self, camera: &Camera, yaw: f32, pitch: f32);
 }
 
 pub struct FlyCamera {
     camera_dir: Vec3,
     movement_dir: Vec3,
+    // Units per second
     max_speed: f32,
 
     right_click: bool,
@@ -70,6 +172,10 @@ pub struct FlyCamera {
     last_y: f32,
     pub pitch: f32,
     pub yaw: f32,
+    pub invert_y: bool,
+    pub wheel_mode: WheelMode,
+    // Wheel notches since the last update, positive away from the user
+    scroll: f32,
 }
 
 impl FlyCamera {
@@ -85,6 +191,9 @@ impl FlyCamera {
             last_y: 0.0,
             pitch: 0.0,
             yaw: 90.0,
+            invert_y: false,
+            wheel_mode: WheelMode::Zoom,
+            scroll: 0.0,
         }
     }
 }
@@ -135,6 +244,12 @@ impl CameraController for FlyCamera {
                     self.right_click = false;
                 }
             }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_NOTCH,
+                };
+            }
             WindowEvent::CursorMoved {
                 device_id: _,
                 position,
@@ -161,7 +276,11 @@ impl CameraController for FlyCamera {
                 y_offset *= sensitivity;
                 self.yaw -= x_offset;
                 self.yaw = self.yaw % 360.0;
-                self.pitch -= y_offset;
+                if self.invert_y {
+                    self.pitch += y_offset;
+                } else {
+                    self.pitch -= y_offset;
+                }
 
                 self.pitch = self.pitch.clamp(-89.0, 89.0);
                 self.camera_dir = vec3(
@@ -174,18 +293,37 @@ impl CameraController for FlyCamera {
         }
     }
 
-    fn update(&mut self, camera: &mut Camera) {
+    fn orientation(&self) -> (f32, f32) {
+        (self.yaw, self.pitch)
+    }
+
+    fn set_orientation(&mut self, camera: &Camera, yaw: f32, pitch: f32) {
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.camera_dir = camera.front;
+    }
+
+    fn update(&mut self, camera: &mut Camera, dt: f32) {
         camera.front = self.camera_dir;
 
-        camera.eye += camera.front * self.movement_dir.z * (self.max_speed / 144.0);
-        camera.eye += Vec3::normalize(Vec3::cross(camera.up, camera.front))
-            * self.movement_dir.x
-            * (self.max_speed / 144.0);
+        let scroll = std::mem::take(&mut self.scroll);
+        match self.wheel_mode {
+            WheelMode::Zoom => {
+                camera.fov_y_degrees = (camera.fov_y_degrees - scroll * ZOOM_STEP_DEGREES)
+                    .clamp(*FOV_RANGE.start(), *FOV_RANGE.end());
+            }
+            WheelMode::Dolly => camera.eye += camera.front * scroll * DOLLY_STEP,
+        }
+
+        let distance = self.max_speed * dt;
+
+        camera.eye += camera.front * self.movement_dir.z * distance;
+        camera.eye +=
+            Vec3::normalize(Vec3::cross(camera.up, camera.front)) * self.movement_dir.x * distance;
 
         let right = Vec3::normalize(Vec3::cross(camera.up, camera.front));
-        camera.eye += Vec3::normalize(Vec3::cross(camera.front, right))
-            * self.movement_dir.y
-            * (self.max_speed / 144.0);
+        camera.eye +=
+            Vec3::normalize(Vec3::cross(camera.front, right)) * self.movement_dir.y * distance;
     }
 
     fn ui(&mut self, camera: &mut Camera, ui: &mut egui::Ui) {
@@ -201,9 +339,17 @@ impl CameraController for FlyCamera {
                     .show_value(true),
             );
 
+            ui.checkbox(&mut self.invert_y, "Invert Y");
+
+            ui.horizontal(|ui| {
+                ui.label("Mouse wheel");
+                ui.selectable_value(&mut self.wheel_mode, WheelMode::Zoom, "Zoom");
+                ui.selectable_value(&mut self.wheel_mode, WheelMode::Dolly, "Dolly");
+            });
+
             ui.add(
-                egui::Slider::new(&mut camera.fov_y_radians, 10.0..=140.0)
-                    .text("FOV (y rad)")
+                egui::Slider::new(&mut camera.fov_y_degrees, FOV_RANGE)
+                    .text("FOV (y degrees)")
                     .show_value(true),
             );
 
@@ -227,3 +373,219 @@ impl CameraController for FlyCamera {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_state_round_trips_through_json() {
+        let camera = Camera {
+            eye: vec3(1.5, -2.25, 3.125),
+            front: vec3(0.6, 0.0, 0.8),
+            up: vec3(0.0, 1.0, 0.0),
+            fov_y_degrees: 65.0,
+            z_near: 0.05,
+            z_far: 250.0,
+            ..Default::default()
+        };
+        let mut controller = FlyCamera::new();
+        controller.yaw = 53.13;
+        controller.pitch = -12.5;
+
+        let json = serde_json::to_string_pretty(&camera.state(&controller)).unwrap();
+        let state = serde_json::from_str::<CameraState>(&json).unwrap();
+
+        let mut restored = Camera::default();
+        let mut restored_controller = FlyCamera::new();
+        restored.restore(&mut restored_controller, &state);
+
+        assert_eq!(restored.eye, camera.eye);
+        assert_eq!(restored.front, camera.front);
+        assert_eq!(restored.up, camera.up);
+        assert_eq!(restored.fov_y_degrees, camera.fov_y_degrees);
+        assert_eq!(restored.z_near, camera.z_near);
+        assert_eq!(restored.z_far, camera.z_far);
+        assert_eq!(restored_controller.orientation(), controller.orientation());
+
+        // The controller keeps looking where the restored camera does
+        restored_controller.update(&mut restored, 0.0);
+        assert_eq!(restored.front, camera.front);
+    }
+
+    #[test]
+    fn fit_distance_uses_the_narrower_fov() {
+        // A unit sphere seen through a 90 degree cone touches it at sqrt(2)
+        let distance = fit_distance(1.0, 90.0, 1.0);
+        assert!((distance - 2f32.sqrt()).abs() < 1e-5);
+
+        // Wider than tall, the vertical fov still limits
+        let distance = fit_distance(2.0, 60.0, 2.0);
+        assert!((distance - 4.0).abs() < 1e-5);
+
+        // Taller than wide, half the horizontal fov is atan(tan(45) * 0.5)
+        let half_fov_x = 0.5f32.atan();
+        let distance = fit_distance(1.0, 90.0, 0.5);
+        assert!((distance - 1.0 / half_fov_x.sin()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn framed_sphere_is_in_front_of_the_camera() {
+        let mut camera = Camera::default();
+        camera.frame(vec3(10.0, 0.0, 0.0), 50.0);
+
+        let distance = fit_distance(50.0, camera.fov_y_degrees, camera.aspect_ratio);
+        assert!((camera.eye - vec3(10.0, 0.0, -distance)).length() < 1e-3);
+        assert!(camera.z_far >= distance + 50.0);
+    }
+
+    #[test]
+    fn movement_scales_with_frame_time() {
+        let mut camera = Camera::default();
+        let start = camera.eye;
+        let mut controller = FlyCamera::new();
+        controller.set_orientation(&camera, 90.0, 0.0);
+        controller.max_speed = 10.0;
+        controller.movement_dir = vec3(0.0, 0.0, 1.0);
+
+        controller.update(&mut camera, 0.1);
+
+        assert!(((camera.eye - start).length() - 1.0).abs() < 1e-5);
+        assert!((camera.eye - start).normalize().dot(camera.front) > 0.9999);
+    }
+
+    #[allow(deprecated)]
+    fn key(keycode: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            // Never handed to winit
+            device_id: unsafe { winit::event::DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(keycode),
+                modifiers: Default::default(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn cursor_moved(x: f64, y: f64) -> WindowEvent<'static> {
+        WindowEvent::CursorMoved {
+            device_id: unsafe { winit::event::DeviceId::dummy() },
+            position: winit::dpi::PhysicalPosition::new(x, y),
+            modifiers: Default::default(),
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn releasing_a_key_keeps_the_look_direction() {
+        let mut camera = Camera::default();
+        let mut controller = FlyCamera::new();
+
+        controller.input(&WindowEvent::MouseInput {
+            device_id: unsafe { winit::event::DeviceId::dummy() },
+            state: ElementState::Pressed,
+            button: MouseButton::Right,
+            modifiers: Default::default(),
+        });
+        controller.input(&cursor_moved(100.0, 100.0));
+        controller.input(&cursor_moved(150.0, 80.0));
+        controller.update(&mut camera, 0.0);
+        let front = camera.front;
+        assert!(front != vec3(0.0, 0.0, 1.0));
+
+        controller.input(&key(VirtualKeyCode::W, ElementState::Pressed));
+        controller.update(&mut camera, 0.1);
+        controller.input(&key(VirtualKeyCode::W, ElementState::Released));
+        controller.update(&mut camera, 0.1);
+
+        assert_eq!(camera.front, front);
+    }
+
+    #[test]
+    fn fov_is_converted_from_degrees() {
+        // A 90 degree vertical fov has a focal length of 1 / tan(45) = 1
+        let camera = Camera::default();
+        assert_eq!(camera.fov_y_degrees, 90.0);
+
+        let perspective = camera.build_uniforms().perspective;
+        assert!((perspective.y_axis.y - 1.0).abs() < 1e-5);
+        assert!((perspective.x_axis.x - 1.0 / camera.aspect_ratio).abs() < 1e-5);
+    }
+
+    #[test]
+    fn scrolling_zooms_in_steps_down_to_the_minimum() {
+        let mut camera = Camera::default();
+        let mut controller = FlyCamera::new();
+        controller.set_orientation(&camera, 90.0, 0.0);
+
+        controller.scroll = 3.0;
+        controller.update(&mut camera, 0.0);
+        assert_eq!(camera.fov_y_degrees, 90.0 - 3.0 * ZOOM_STEP_DEGREES);
+
+        controller.scroll = 100.0;
+        controller.update(&mut camera, 0.0);
+        assert_eq!(camera.fov_y_degrees, *FOV_RANGE.start());
+    }
+
+    #[test]
+    fn view_position_round_trips_through_clip_space() {
+        let mut camera = Camera::default();
+        let view_position = vec3(-1.5, 0.75, 12.0);
+
+        for reverse_z in [false, true] {
+            camera.reverse_z = reverse_z;
+            let uniforms = camera.build_uniforms();
+
+            let clip = uniforms.perspective * view_position.extend(1.0);
+            let ndc = clip.truncate() / clip.w;
+            let uv = Vec2::new(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+
+            let reconstructed = view_position_from_depth(uv, ndc.z, uniforms.inverse_perspective);
+            assert!((reconstructed - view_position).length() < 1e-3);
+        }
+    }
+
+    /// Hardware depth of a point `z` in front of the camera.
+    fn depth_at(camera: &Camera, z: f32) -> f32 {
+        let clip = camera.build_uniforms().perspective * vec4(0.0, 0.0, z, 1.0);
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn reverse_z_maps_the_planes_the_other_way_around() {
+        let mut camera = Camera::default();
+        assert!(depth_at(&camera, camera.z_near).abs() < 1e-5);
+        assert!((depth_at(&camera, camera.z_far) - 1.0).abs() < 1e-5);
+
+        camera.reverse_z = true;
+        assert!((depth_at(&camera, camera.z_near) - 1.0).abs() < 1e-5);
+        assert!(depth_at(&camera, camera.z_far).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reverse_z_keeps_far_depths_apart() {
+        // Representable depths between two points a centimeter apart, far from the camera
+        let steps = |camera: &Camera| {
+            let a = depth_at(camera, 50.0);
+            let b = depth_at(camera, 50.01);
+            (a.to_bits() as i64 - b.to_bits() as i64).abs()
+        };
+
+        let mut camera = Camera::default();
+        let standard = steps(&camera);
+        camera.reverse_z = true;
+        let reversed = steps(&camera);
+
+        assert!(standard <= 1);
+        assert!(reversed > 100);
+    }
+
+    #[test]
+    fn malformed_camera_state_is_an_error() {
+        assert!(serde_json::from_str::<CameraState>("{\"eye\": [0, 0]}").is_err());
+        assert!(serde_json::from_str::<CameraState>("not json").is_err());
+    }
+}
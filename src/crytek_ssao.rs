@@ -1,34 +1,199 @@
-use glam::{vec3, Vec3};
+use std::{ops::RangeInclusive, path::Path};
+
+use glam::vec3;
 use half::f16;
 use rand::prelude::*;
-use wgpu::{SamplerBindingType, ShaderStages, TextureFormat, TextureSampleType, TextureUsages};
+use serde::{Deserialize, Serialize};
+use wgpu::{
+    CommandEncoder, SamplerBindingType, ShaderStages, TextureFormat, TextureSampleType,
+    TextureUsages, TextureView,
+};
 
 use crate::{
+    ao_technique::AoTechnique,
     resource_manager::{
-        BindGroupDesc, BindGroupLayoutDesc, Handle, ResourceManager, SamplerDesc, ShaderDesc,
-        ShaderModuleDesc, ShaderPipelineDesc, TextureDesc,
+        size_changed, BindGroupDesc, BindGroupLayoutDesc, BlendComponent, BlendFactor,
+        BlendOperation, BlendState, BufferDesc, BufferUsages, CompareFunction, ComputeShaderDesc,
+        Handle, ResourceError, ResourceManager, SamplerDesc, ShaderDesc, ShaderModuleDesc,
+        ShaderPipelineDesc, StorageTextureAccess, TextureDesc, TextureViewDimension, DEPTH_FORMAT,
+        SCENE_COLOR_FORMAT,
     },
-    scene::SceneUniformData,
+    scene::{bytemuck_impl, Scene},
 };
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CrytekSSAOUniformData {
+    pub normal_bias: f32,
+    pub flip_normals: u32,
+    pub noise_scale: [f32; 2],
+    pub radius: f32,
+    pub range_check: f32,
+    pub sample_count: u32,
+    pub rotation_noise: u32,
+}
+bytemuck_impl!(CrytekSSAOUniformData);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SSAOCompositeUniformData {
+    pub mode: u32,
+    pub power: f32,
+    pub intensity: f32,
+    pub multi_bounce: u32,
+}
+bytemuck_impl!(SSAOCompositeUniformData);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SSAODownsampleUniformData {
+    pub divisor: u32,
+    pub _padding: [u32; 3],
+}
+bytemuck_impl!(SSAODownsampleUniformData);
+
+/// Which AO buffer ends up on screen
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AOOutput {
+    Blurred = 0,
+    Raw = 1,
+    /// Raw on the left half, blurred on the right
+    Split = 2,
+}
+
+/// Where the per pixel kernel rotation comes from
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RotationNoise {
+    /// Tiled 4x4 texture of random rotations, which the 4x4 blur averages out exactly
+    #[default]
+    Texture = 0,
+    /// Computed per pixel without a texture fetch, doesn't repeat every 4 pixels
+    InterleavedGradient = 1,
+}
+
+/// The tunable parameters, saved to and loaded from JSON files.
+#[derive(Serialize, Deserialize)]
+pub struct CrytekSSAOPreset {
+    pub radius: f32,
+    pub range_check: f32,
+    pub normal_bias: f32,
+    pub flip_normals: bool,
+    pub output: AOOutput,
+    // Missing from presets saved before these existed
+    #[serde(default = "default_strength")]
+    pub power: f32,
+    #[serde(default = "default_strength")]
+    pub intensity: f32,
+    #[serde(default = "default_sample_count")]
+    pub sample_count: u32,
+    #[serde(default)]
+    pub multi_bounce: bool,
+    #[serde(default)]
+    pub rotation_noise: RotationNoise,
+}
+
+fn default_strength() -> f32 {
+    1.0
+}
+
+fn default_sample_count() -> u32 {
+    DEFAULT_SAMPLE_COUNT
+}
+
 pub struct CrytekSSAO {
     samples_texture: Handle,
-    depth_buffer_sampler: Handle,
+    noise_texture: Handle,
+    uniform_buffer: Handle,
     ssao_bind_group: Handle,
     ssao_shader: Handle,
+    facing_shader: Handle,
+    compute_bind_group: Handle,
+    scaled_compute_bind_group: Handle,
+    compute_shader: Handle,
+
+    // At the scaled resolution, as is everything prefixed scaled
+    raw_ao: Handle,
+    // Full resolution, blurred straight from raw_ao or upsampled from scaled_blurred_ao
+    blurred_ao: Handle,
+    scaled_ssao_bind_group: Handle,
+    scaled_depth: Handle,
+    scaled_blurred_ao: Handle,
+    downsample_uniform_buffer: Handle,
+    downsample_bind_group: Handle,
+    downsample_shader: Handle,
+    upsample_bind_group: Handle,
+    upsample_shader: Handle,
+    blur_bind_group: Handle,
+    blur_shader: Handle,
+    composite_uniform_buffer: Handle,
+    composite_bind_group: Handle,
+    composite_shader: Handle,
+    apply_shader: Handle,
+
+    /// How far kernel samples are pulled toward the surface normal, 0 leaves them untouched
+    pub normal_bias: f32,
+    /// Negates the normals reconstructed from depth
+    pub flip_normals: bool,
+    /// Shows which way the reconstructed normals face instead of AO
+    show_facing: bool,
+    pub output: AOOutput,
+    pub rotation_noise: RotationNoise,
+    /// View-space sample radius
+    pub radius: f32,
+    /// Depth difference past which occluders start fading out, stops far geometry haloing
+    /// near silhouettes
+    pub range_check: f32,
+    /// Composited AO is `pow(ao, power) * intensity`, clamped so it never brightens
+    pub power: f32,
+    pub intensity: f32,
+    /// Brightens AO on light albedos to approximate interreflections, from GTAO
+    pub multi_bounce: bool,
+    /// AO is rendered at 1 / divisor of the full resolution along each axis
+    resolution_divisor: u32,
+    /// Kernel size, one texel of samples_texture per sample
+    sample_count: u32,
+    /// Writes raw AO from a compute shader instead of a fullscreen draw, timed under the same
+    /// profiler scope so the two can be compared
+    pub compute: bool,
 }
 
-const NUM_SAMPLES: usize = 16;
+const DEFAULT_SAMPLE_COUNT: u32 = 16;
+const SAMPLE_COUNT_RANGE: RangeInclusive<u32> = 4..=64;
+/// Width and height of the tiled rotation texture, in pixels
+pub const NOISE_SIZE: u32 = 4;
+const NOISE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const AO_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+/// R8Unorm can't be a storage texture, raw AO is written by the compute path too
+const RAW_AO_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+/// Matches @workgroup_size in crytek_ssao.wgsl
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Rounds up, so the scaled texture always covers the full one.
+pub fn scaled_dimensions(dimensions: (u32, u32), divisor: u32) -> (u32, u32) {
+    (
+        dimensions.0.div_ceil(divisor),
+        dimensions.1.div_ceil(divisor),
+    )
+}
 
 impl CrytekSSAO {
-    pub fn new(rm: &mut ResourceManager, depth_buffer: Handle) -> Self {
+    /// One texel per sample: xyz is a point in the unit hemisphere around +Z (rotated onto the
+    /// surface normal in the shader), w is unused. Samples are scaled so more of them land close
+    /// to the origin.
+    fn generate_samples(count: u32) -> Vec<f16> {
         let mut rng = rand::thread_rng();
-        // generate samples
         let mut data: Vec<f16> = vec![];
 
-        for i in 0..NUM_SAMPLES {
-            let mut sample = vec3(rng.gen(), rng.gen(), rng.gen());
-            sample = sample.normalize();
+        for i in 0..count {
+            let mut sample = vec3(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.0..1.0),
+            );
+            sample = sample.normalize_or_zero() * rng.gen::<f32>();
+
+            let t = i as f32 / count as f32;
+            sample *= 0.1 + (1.0 - 0.1) * t * t;
 
             data.push(f16::from_f32(sample.x));
             data.push(f16::from_f32(sample.y));
@@ -36,33 +201,119 @@ impl CrytekSSAO {
             data.push(f16::from_f32(1.0));
         }
 
+        data
+    }
+
+    /// Random rotations around the surface normal, in the tangent plane (z = 0)
+    fn generate_noise() -> Vec<f16> {
+        let mut rng = rand::thread_rng();
+        let mut noise: Vec<f16> = vec![];
+
+        for _ in 0..NOISE_SIZE * NOISE_SIZE {
+            noise.push(f16::from_f32(rng.gen_range(-1.0..1.0)));
+            noise.push(f16::from_f32(rng.gen_range(-1.0..1.0)));
+            noise.push(f16::from_f32(0.0));
+            noise.push(f16::from_f32(0.0));
+        }
+
+        noise
+    }
+
+    /// `albedo` is only read by the multi-bounce approximation in the composite.
+    pub fn new(
+        rm: &mut ResourceManager,
+        depth_buffer: Handle,
+        albedo: Handle,
+    ) -> Result<Self, ResourceError> {
+        let data = CrytekSSAO::generate_samples(DEFAULT_SAMPLE_COUNT);
         let samples_texture = rm.create_texture(&TextureDesc {
             label: Some("Samples texture"),
-            dimensions: (16, 1),
+            dimensions: (DEFAULT_SAMPLE_COUNT, 1),
             mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
             format: wgpu::TextureFormat::Rgba16Float,
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
             initial_data: Some(bytemuck::cast_slice(data.as_slice())),
         });
 
+        let noise = CrytekSSAO::generate_noise();
+        let noise_texture = rm.create_texture(&TextureDesc {
+            label: Some("Noise texture"),
+            dimensions: (NOISE_SIZE, NOISE_SIZE),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: NOISE_FORMAT,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            initial_data: Some(bytemuck::cast_slice(noise.as_slice())),
+        });
+
         let depth_buffer_sampler = rm.create_sampler(SamplerDesc {
             label: Some("Depth buffer sampler"),
             address_mode: wgpu::AddressMode::ClampToEdge,
-            mag_min_filter: wgpu::FilterMode::Linear,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
             mipmaps: None,
             compare: None,
+            anisotropy: 1,
         });
 
-        let ssao_bind_group = rm.create_bind_group(&BindGroupDesc {
-            label: None,
-            visibility: ShaderStages::FRAGMENT,
-            layout: CrytekSSAO::bind_group_layout(),
-            buffers: &[],
-            textures: &[samples_texture, depth_buffer_sampler],
-            samplers: &[depth_buffer_sampler],
+        let noise_sampler = rm.create_sampler(SamplerDesc {
+            label: Some("Noise sampler"),
+            address_mode: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            mipmaps: None,
+            compare: None,
+            anisotropy: 1,
+        });
+
+        // Written every pass
+        let uniform_buffer = rm.create_buffer(&BufferDesc {
+            label: Some("SSAO uniform buffer"),
+            byte_size: std::mem::size_of::<CrytekSSAOUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
+        });
+
+        let full_size = (
+            rm.surface_configuration.width,
+            rm.surface_configuration.height,
+        );
+        // Point sampled from depth_buffer when rendering below full resolution
+        let scaled_depth = rm.create_texture(&TextureDesc {
+            label: Some("Scaled SSAO depth"),
+            dimensions: full_size,
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
         });
 
-        let ssao_shader = rm.create_shader(ShaderDesc {
+        let mut create_ssao_bind_group = |depth| {
+            rm.create_bind_group(&BindGroupDesc {
+                label: None,
+                visibility: ShaderStages::FRAGMENT,
+                layout: CrytekSSAO::bind_group_layout(),
+                buffers: &[uniform_buffer],
+                textures: &[samples_texture, noise_texture, depth],
+                samplers: &[depth_buffer_sampler, noise_sampler],
+                storage_buffers: &[],
+                storage_textures: &[],
+            })
+        };
+        let ssao_bind_group = create_ssao_bind_group(depth_buffer)?;
+        let scaled_ssao_bind_group = create_ssao_bind_group(scaled_depth)?;
+
+        let ssao_shader_desc = ShaderDesc {
             label: Some(String::from("SSAO shader")),
             vs: ShaderModuleDesc {
                 path: String::from("src/shaders/crytek_ssao.wgsl"),
@@ -73,40 +324,1134 @@ impl CrytekSSAO {
                 entry_func: String::from("fs_main"),
             }),
             bind_group_layouts: vec![
-                BindGroupLayoutDesc {
-                    label: None,
-                    visibility: ShaderStages::VERTEX_FRAGMENT,
-                    buffers: vec![std::mem::size_of::<SceneUniformData>()],
-                    textures: vec![],
-                    samplers: vec![],
-                },
+                Scene::scene_bind_group_layout(),
                 CrytekSSAO::bind_group_layout(),
             ],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![RAW_AO_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        };
+        let ssao_shader = rm.create_shader(ssao_shader_desc.clone())?;
+
+        // Draws straight to the screen, skipping the blur and composite
+        let facing_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("SSAO normal facing shader")),
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/crytek_ssao.wgsl"),
+                entry_func: String::from("fs_facing"),
+            }),
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![SCENE_COLOR_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+            ..ssao_shader_desc
+        })?;
+
+        let ao_texture_desc = TextureDesc {
+            label: Some("Raw AO"),
+            dimensions: full_size,
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: AO_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        };
+        let raw_ao = rm.create_texture(&TextureDesc {
+            format: RAW_AO_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::STORAGE_BINDING,
+            ..ao_texture_desc
+        });
+        let blurred_ao = rm.create_texture(&TextureDesc {
+            label: Some("Blurred AO"),
+            ..ao_texture_desc
+        });
+        let scaled_blurred_ao = rm.create_texture(&TextureDesc {
+            label: Some("Scaled blurred AO"),
+            ..ao_texture_desc
+        });
+
+        let downsample_uniform_buffer = rm.create_buffer(&BufferDesc {
+            label: Some("SSAO downsample uniform buffer"),
+            byte_size: std::mem::size_of::<SSAODownsampleUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
+        });
+
+        let downsample_bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: CrytekSSAO::downsample_bind_group_layout(),
+            buffers: &[downsample_uniform_buffer],
+            textures: &[depth_buffer],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        let downsample_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("SSAO depth downsample shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_downsample_depth.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_downsample_depth.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![CrytekSSAO::downsample_bind_group_layout()],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                // Always passes, so every pixel's depth is written
+                depth_test: Some(CompareFunction::Always),
+                targets: vec![],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let upsample_bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: CrytekSSAO::upsample_bind_group_layout(),
+            buffers: &[],
+            textures: &[scaled_blurred_ao, scaled_depth, depth_buffer],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        let upsample_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("SSAO upsample shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_upsample.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_upsample.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![
+                Scene::scene_bind_group_layout(),
+                CrytekSSAO::upsample_bind_group_layout(),
+            ],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![AO_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let mut create_compute_bind_group = |depth| {
+            rm.create_bind_group(&BindGroupDesc {
+                label: None,
+                visibility: ShaderStages::COMPUTE,
+                layout: CrytekSSAO::compute_bind_group_layout(),
+                buffers: &[uniform_buffer],
+                textures: &[samples_texture, noise_texture, depth],
+                samplers: &[depth_buffer_sampler, noise_sampler],
+                storage_buffers: &[],
+                storage_textures: &[raw_ao],
+            })
+        };
+        let compute_bind_group = create_compute_bind_group(depth_buffer)?;
+        let scaled_compute_bind_group = create_compute_bind_group(scaled_depth)?;
+
+        let compute_shader = rm.create_compute_shader(ComputeShaderDesc {
+            label: Some(String::from("SSAO compute shader")),
+            module: ShaderModuleDesc {
+                path: String::from("src/shaders/crytek_ssao.wgsl"),
+                entry_func: String::from("cs_main"),
+            },
+            bind_group_layouts: vec![
+                Scene::scene_bind_group_layout(),
+                CrytekSSAO::compute_bind_group_layout(),
+            ],
+        })?;
+
+        let blur_bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: CrytekSSAO::blur_bind_group_layout(),
+            buffers: &[],
+            textures: &[raw_ao],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        let blur_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("SSAO blur shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_blur.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_blur.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![CrytekSSAO::blur_bind_group_layout()],
+            push_constant_ranges: vec![],
             pipeline_state: ShaderPipelineDesc {
                 depth_test: None,
-                targets: vec![TextureFormat::Bgra8UnormSrgb],
+                targets: vec![AO_FORMAT],
                 vertex_buffer_bindings: vec![],
+                ..Default::default()
             },
+        })?;
+
+        let composite_uniform_buffer = rm.create_buffer(&BufferDesc {
+            label: Some("SSAO composite uniform buffer"),
+            byte_size: std::mem::size_of::<SSAOCompositeUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
         });
 
-        Self {
+        let composite_bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: CrytekSSAO::composite_bind_group_layout(),
+            buffers: &[composite_uniform_buffer],
+            textures: &[raw_ao, blurred_ao, albedo],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        let composite_shader_desc = ShaderDesc {
+            label: Some(String::from("SSAO composite shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_composite.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_composite.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![CrytekSSAO::composite_bind_group_layout()],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![SCENE_COLOR_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        };
+        let composite_shader = rm.create_shader(composite_shader_desc.clone())?;
+
+        // Multiplies the AO into what's already in the target
+        let multiply = BlendComponent {
+            src_factor: BlendFactor::Dst,
+            dst_factor: BlendFactor::Zero,
+            operation: BlendOperation::Add,
+        };
+        let apply_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("SSAO apply shader")),
+            pipeline_state: ShaderPipelineDesc {
+                blends: vec![Some(BlendState {
+                    color: multiply,
+                    alpha: BlendComponent::OVER,
+                })],
+                ..composite_shader_desc.pipeline_state
+            },
+            ..composite_shader_desc
+        })?;
+
+        Ok(Self {
             samples_texture,
-            depth_buffer_sampler,
+            noise_texture,
+            uniform_buffer,
             ssao_bind_group,
             ssao_shader,
+            facing_shader,
+            compute_bind_group,
+            scaled_compute_bind_group,
+            compute_shader,
+            raw_ao,
+            blurred_ao,
+            scaled_ssao_bind_group,
+            scaled_depth,
+            scaled_blurred_ao,
+            downsample_uniform_buffer,
+            downsample_bind_group,
+            downsample_shader,
+            upsample_bind_group,
+            upsample_shader,
+            blur_bind_group,
+            blur_shader,
+            composite_uniform_buffer,
+            composite_bind_group,
+            composite_shader,
+            apply_shader,
+            normal_bias: 0.0,
+            flip_normals: false,
+            show_facing: false,
+            output: AOOutput::Blurred,
+            rotation_noise: RotationNoise::Texture,
+            radius: 0.5,
+            range_check: 0.5,
+            power: 1.0,
+            intensity: 1.0,
+            multi_bounce: false,
+            resolution_divisor: 1,
+            sample_count: DEFAULT_SAMPLE_COUNT,
+            compute: false,
+        })
+    }
+
+    /// Reallocates the samples texture at the new width and fills it with a new kernel, unless the
+    /// count is unchanged.
+    fn set_sample_count(&mut self, rm: &mut ResourceManager, count: u32) {
+        let count = count.clamp(*SAMPLE_COUNT_RANGE.start(), *SAMPLE_COUNT_RANGE.end());
+        if !size_changed(rm.get_texture(self.samples_texture).size(), (count, 1)) {
+            return;
         }
+
+        self.sample_count = count;
+        rm.resize_texture(self.samples_texture, (self.sample_count, 1));
+        rm.update_texture(
+            self.samples_texture,
+            bytemuck::cast_slice(CrytekSSAO::generate_samples(self.sample_count).as_slice()),
+        );
     }
 
-    pub fn bind_group_layout() -> BindGroupLayoutDesc {
+    fn save_preset(&self, path: &Path) {
+        let preset = CrytekSSAOPreset {
+            radius: self.radius,
+            range_check: self.range_check,
+            normal_bias: self.normal_bias,
+            flip_normals: self.flip_normals,
+            output: self.output,
+            power: self.power,
+            intensity: self.intensity,
+            sample_count: self.sample_count,
+            multi_bounce: self.multi_bounce,
+            rotation_noise: self.rotation_noise,
+        };
+        let result = serde_json::to_string_pretty(&preset)
+            .map_err(|err| err.to_string())
+            .and_then(|json| std::fs::write(path, json).map_err(|err| err.to_string()));
+
+        if let Err(err) = result {
+            println!("Couldn't save preset to {}: {}", path.display(), err);
+        }
+    }
+
+    fn load_preset(&mut self, rm: &mut ResourceManager, path: &Path) {
+        let result = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| {
+                serde_json::from_str::<CrytekSSAOPreset>(&json).map_err(|err| err.to_string())
+            });
+
+        match result {
+            Ok(preset) => {
+                self.radius = preset.radius;
+                self.range_check = preset.range_check;
+                self.normal_bias = preset.normal_bias;
+                self.flip_normals = preset.flip_normals;
+                self.output = preset.output;
+                self.power = preset.power;
+                self.intensity = preset.intensity;
+                self.multi_bounce = preset.multi_bounce;
+                self.rotation_noise = preset.rotation_noise;
+                self.set_sample_count(rm, preset.sample_count);
+            }
+            Err(err) => println!("Couldn't load preset from {}: {}", path.display(), err),
+        }
+    }
+
+    fn update_uniforms(&self, rm: &ResourceManager) {
+        let size = rm.get_texture(self.raw_ao).size();
+        rm.update_buffer(
+            self.uniform_buffer,
+            bytemuck::cast_slice(&[CrytekSSAOUniformData {
+                normal_bias: self.normal_bias,
+                flip_normals: self.flip_normals as u32,
+                // Tiles the noise texture across the screen, follows resizes
+                noise_scale: [
+                    size.width as f32 / NOISE_SIZE as f32,
+                    size.height as f32 / NOISE_SIZE as f32,
+                ],
+                radius: self.radius,
+                range_check: self.range_check,
+                sample_count: self.sample_count,
+                rotation_noise: self.rotation_noise as u32,
+            }]),
+        );
+    }
+
+    fn fullscreen_pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        view: &TextureView,
+        shader: Handle,
+        bind_group: Handle,
+    ) {
+        let mut ssao_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Crytek SSAO"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        ssao_pass.set_pipeline(rm.get_shader(shader).pipeline());
+        ssao_pass.set_bind_group(0, rm.get_bind_group(scene.scene_uniform_bind_group), &[]);
+        ssao_pass.set_bind_group(1, rm.get_bind_group(bind_group), &[]);
+        ssao_pass.draw(0..6, 0..1);
+    }
+
+    /// Like `fullscreen_pass` with the SSAO shader, but dispatched over raw_ao as a compute shader.
+    fn compute_pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        bind_group: Handle,
+    ) {
+        let size = rm.get_texture(self.raw_ao).size();
+        let mut ssao_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Crytek SSAO (compute)"),
+        });
+
+        ssao_pass.set_pipeline(rm.get_compute_shader(self.compute_shader).pipeline());
+        ssao_pass.set_bind_group(0, rm.get_bind_group(scene.scene_uniform_bind_group), &[]);
+        ssao_pass.set_bind_group(1, rm.get_bind_group(bind_group), &[]);
+        ssao_pass.dispatch_workgroups(
+            size.width.div_ceil(WORKGROUP_SIZE),
+            size.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+    }
+
+    /// Renders raw AO with either the fragment or the compute shader.
+    fn ssao_pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        scaled: bool,
+    ) {
+        if self.compute {
+            let bind_group = if scaled {
+                self.scaled_compute_bind_group
+            } else {
+                self.compute_bind_group
+            };
+            self.compute_pass(rm, encoder, scene, bind_group);
+        } else {
+            let bind_group = if scaled {
+                self.scaled_ssao_bind_group
+            } else {
+                self.ssao_bind_group
+            };
+            self.fullscreen_pass(
+                rm,
+                encoder,
+                scene,
+                rm.get_texture(self.raw_ao).view(),
+                self.ssao_shader,
+                bind_group,
+            );
+        }
+    }
+
+    fn blur_pass(&self, rm: &ResourceManager, encoder: &mut CommandEncoder, target: Handle) {
+        let mut blur_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSAO blur"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: rm.get_texture(target).view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        blur_pass.set_pipeline(rm.get_shader(self.blur_shader).pipeline());
+        blur_pass.set_bind_group(0, rm.get_bind_group(self.blur_bind_group), &[]);
+        blur_pass.draw(0..6, 0..1);
+    }
+
+    fn downsample_depth(&self, rm: &ResourceManager, encoder: &mut CommandEncoder) {
+        rm.update_buffer(
+            self.downsample_uniform_buffer,
+            bytemuck::cast_slice(&[SSAODownsampleUniformData {
+                divisor: self.resolution_divisor,
+                _padding: [0; 3],
+            }]),
+        );
+
+        let mut downsample_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSAO depth downsample"),
+            color_attachments: &[],
+            depth_stencil_attachment: rm
+                .get_texture(self.scaled_depth)
+                .depth_stencil_attachment_with(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+        });
+
+        downsample_pass.set_pipeline(rm.get_shader(self.downsample_shader).pipeline());
+        downsample_pass.set_bind_group(0, rm.get_bind_group(self.downsample_bind_group), &[]);
+        downsample_pass.draw(0..6, 0..1);
+    }
+
+    fn upsample(&self, rm: &ResourceManager, encoder: &mut CommandEncoder, scene: &Scene) {
+        let mut upsample_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSAO upsample"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: rm.get_texture(self.blurred_ao).view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        upsample_pass.set_pipeline(rm.get_shader(self.upsample_shader).pipeline());
+        upsample_pass.set_bind_group(0, rm.get_bind_group(scene.scene_uniform_bind_group), &[]);
+        upsample_pass.set_bind_group(1, rm.get_bind_group(self.upsample_bind_group), &[]);
+        upsample_pass.draw(0..6, 0..1);
+    }
+
+    fn downsample_bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![std::mem::size_of::<SSAODownsampleUniformData>()],
+            textures: vec![(TextureSampleType::Depth, TextureViewDimension::D2)],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    fn upsample_bind_group_layout() -> BindGroupLayoutDesc {
         BindGroupLayoutDesc {
             label: None,
             visibility: ShaderStages::FRAGMENT,
             buffers: vec![],
             textures: vec![
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (TextureSampleType::Depth, TextureViewDimension::D2),
+                (TextureSampleType::Depth, TextureViewDimension::D2),
+            ],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    pub fn blur_bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![],
+            textures: vec![(
                 TextureSampleType::Float { filterable: true },
-                TextureSampleType::Depth,
+                TextureViewDimension::D2,
+            )],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    pub fn composite_bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![std::mem::size_of::<SSAOCompositeUniformData>()],
+            textures: vec![
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
             ],
-            samplers: vec![SamplerBindingType::Filtering],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
         }
     }
+
+    /// `bind_group_layout` plus raw AO as a storage texture, for cs_main.
+    fn compute_bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            visibility: ShaderStages::COMPUTE,
+            storage_textures: vec![(RAW_AO_FORMAT, StorageTextureAccess::WriteOnly)],
+            ..CrytekSSAO::bind_group_layout()
+        }
+    }
+
+    pub fn bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![std::mem::size_of::<CrytekSSAOUniformData>()],
+            textures: vec![
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (TextureSampleType::Depth, TextureViewDimension::D2),
+            ],
+            samplers: vec![SamplerBindingType::Filtering, SamplerBindingType::Filtering],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+}
+
+impl AoTechnique for CrytekSSAO {
+    fn name(&self) -> &'static str {
+        "Crytek SSAO"
+    }
+
+    fn ui(&mut self, rm: &mut ResourceManager, ui: &mut egui::Ui) {
+        let mut resolution_divisor = self.resolution_divisor;
+        ui.horizontal(|ui| {
+            ui.label("Resolution");
+            ui.selectable_value(&mut resolution_divisor, 1, "1.0");
+            ui.selectable_value(&mut resolution_divisor, 2, "0.5");
+            ui.selectable_value(&mut resolution_divisor, 4, "0.25");
+        });
+        if resolution_divisor != self.resolution_divisor {
+            self.resolution_divisor = resolution_divisor;
+            let size = rm.get_texture(self.blurred_ao).size();
+            self.resize(rm, (size.width, size.height));
+        }
+
+        ui.add(
+            egui::Slider::new(&mut self.radius, 0.05..=2.0)
+                .text("Radius")
+                .show_value(true),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.range_check, 0.01..=2.0)
+                .text("Range check")
+                .show_value(true),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.normal_bias, 0.0..=1.0)
+                .text("Normal bias")
+                .show_value(true),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.power, 0.1..=4.0)
+                .text("Power")
+                .show_value(true),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.intensity, 0.0..=2.0)
+                .text("Intensity")
+                .show_value(true),
+        );
+        let mut sample_count = self.sample_count;
+        ui.add(egui::Slider::new(&mut sample_count, SAMPLE_COUNT_RANGE).text("Samples"));
+        self.set_sample_count(rm, sample_count);
+        ui.checkbox(&mut self.multi_bounce, "Multi-bounce");
+        ui.checkbox(&mut self.flip_normals, "Flip reconstructed normals");
+        ui.checkbox(&mut self.show_facing, "Show normal facing");
+        ui.checkbox(&mut self.compute, "Compute shader");
+
+        ui.horizontal(|ui| {
+            ui.label("Output");
+            ui.selectable_value(&mut self.output, AOOutput::Blurred, "Blurred");
+            ui.selectable_value(&mut self.output, AOOutput::Raw, "Raw");
+            ui.selectable_value(&mut self.output, AOOutput::Split, "Raw | blurred");
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Rotation");
+            ui.selectable_value(
+                &mut self.rotation_noise,
+                RotationNoise::Texture,
+                "4x4 texture",
+            );
+            ui.selectable_value(
+                &mut self.rotation_noise,
+                RotationNoise::InterleavedGradient,
+                "Interleaved gradient",
+            );
+        });
+
+        if ui.button("Regenerate samples").clicked() {
+            rm.update_texture(
+                self.samples_texture,
+                bytemuck::cast_slice(CrytekSSAO::generate_samples(self.sample_count).as_slice()),
+            );
+            rm.update_texture(
+                self.noise_texture,
+                bytemuck::cast_slice(CrytekSSAO::generate_noise().as_slice()),
+            );
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Save preset…").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("JSON", &["json"])
+                    .save_file()
+                {
+                    self.save_preset(&path);
+                }
+            }
+            if ui.button("Load preset…").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("JSON", &["json"])
+                    .pick_file()
+                {
+                    self.load_preset(rm, &path);
+                }
+            }
+        });
+    }
+
+    fn resize(&self, rm: &mut ResourceManager, dimensions: (u32, u32)) {
+        let scaled = scaled_dimensions(dimensions, self.resolution_divisor);
+        rm.resize_texture(self.raw_ao, scaled);
+        rm.resize_texture(self.scaled_depth, scaled);
+        rm.resize_texture(self.scaled_blurred_ao, scaled);
+        rm.resize_texture(self.blurred_ao, dimensions);
+    }
+
+    fn ao_texture(&self) -> Handle {
+        self.blurred_ao
+    }
+
+    fn debug_targets(&self) -> Vec<(&'static str, Handle)> {
+        // Raw AO is at the scaled resolution, so it only fills part of the view when scaled
+        vec![("Raw AO", self.raw_ao), ("Blurred AO", self.blurred_ao)]
+    }
+
+    fn occlusion(&self, rm: &ResourceManager, encoder: &mut CommandEncoder, scene: &Scene) {
+        self.update_uniforms(rm);
+        if self.resolution_divisor == 1 {
+            self.ssao_pass(rm, encoder, scene, false);
+            self.blur_pass(rm, encoder, self.blurred_ao);
+            return;
+        }
+
+        self.downsample_depth(rm, encoder);
+        self.ssao_pass(rm, encoder, scene, true);
+        self.blur_pass(rm, encoder, self.scaled_blurred_ao);
+        self.upsample(rm, encoder, scene);
+    }
+
+    fn composite(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        multiply: bool,
+    ) {
+        rm.update_buffer(
+            self.composite_uniform_buffer,
+            bytemuck::cast_slice(&[SSAOCompositeUniformData {
+                mode: self.output as u32,
+                power: self.power,
+                intensity: self.intensity,
+                multi_bounce: self.multi_bounce as u32,
+            }]),
+        );
+
+        {
+            let mut composite_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("SSAO composite"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: if multiply {
+                            wgpu::LoadOp::Load
+                        } else {
+                            wgpu::LoadOp::Clear(wgpu::Color::WHITE)
+                        },
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            let shader = if multiply {
+                self.apply_shader
+            } else {
+                self.composite_shader
+            };
+            composite_pass.set_pipeline(rm.get_shader(shader).pipeline());
+            composite_pass.set_bind_group(0, rm.get_bind_group(self.composite_bind_group), &[]);
+            composite_pass.draw(0..6, 0..1);
+        }
+    }
+
+    fn shows_debug_output(&self) -> bool {
+        self.show_facing
+    }
+
+    fn debug_pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        view: &TextureView,
+    ) {
+        self.update_uniforms(rm);
+        self.fullscreen_pass(
+            rm,
+            encoder,
+            scene,
+            view,
+            self.facing_shader,
+            self.ssao_bind_group,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec3, Vec4};
+
+    use super::*;
+
+    /// Runs `multi_bounce` from ssao_composite.wgsl on each `(ao, albedo)`.
+    fn multi_bounce(rm: &mut ResourceManager, inputs: &[(f32, Vec3)]) -> Vec<Vec3> {
+        let inputs: Vec<Vec4> = inputs
+            .iter()
+            .map(|&(ao, albedo)| albedo.extend(ao))
+            .collect();
+        crate::shader_harness::eval(
+            rm,
+            "src/shaders/ssao_composite.wgsl",
+            "vec4<f32>(multi_bounce(input.w, input.xyz), 0.0)",
+            &inputs,
+        )
+        .iter()
+        .map(|output| output.truncate())
+        .collect()
+    }
+
+    /// Runs `adjust` from ssao_composite.wgsl on each `(ao, power, intensity)`.
+    fn adjust(rm: &mut ResourceManager, inputs: &[(f32, f32, f32)]) -> Vec<f32> {
+        let inputs: Vec<Vec4> = inputs
+            .iter()
+            .map(|&(ao, power, intensity)| Vec4::new(ao, power, intensity, 0.0))
+            .collect();
+        crate::shader_harness::eval(
+            rm,
+            "src/shaders/ssao_composite.wgsl",
+            "vec4<f32>(adjust(input.x, input.y, input.z))",
+            &inputs,
+        )
+        .iter()
+        .map(|output| output.x)
+        .collect()
+    }
+
+    #[test]
+    fn unit_power_and_intensity_keep_the_ao() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let aos: Vec<f32> = (0..=100).map(|i| i as f32 / 100.0).collect();
+        let inputs: Vec<_> = aos.iter().map(|&ao| (ao, 1.0, 1.0)).collect();
+
+        for (adjusted, ao) in adjust(&mut rm, &inputs).into_iter().zip(aos) {
+            assert!((adjusted - ao).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn intensity_never_brightens_past_unoccluded() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let adjusted = adjust(&mut rm, &[(0.9, 1.0, 2.0), (0.5, 2.0, 1.0)]);
+
+        assert_eq!(adjusted[0], 1.0);
+        assert!(adjusted[1] < 0.5);
+    }
+
+    /// Runs `interleaved_gradient_noise` from crytek_ssao.wgsl on each pixel.
+    fn interleaved_gradient_noise(rm: &mut ResourceManager, pixels: &[(f32, f32)]) -> Vec<f32> {
+        let inputs: Vec<Vec4> = pixels
+            .iter()
+            .map(|&(x, y)| Vec4::new(x, y, 0.0, 0.0))
+            .collect();
+        crate::shader_harness::eval(
+            rm,
+            "src/shaders/crytek_ssao.wgsl",
+            "vec4<f32>(interleaved_gradient_noise(input.xy))",
+            &inputs,
+        )
+        .iter()
+        .map(|output| output.x)
+        .collect()
+    }
+
+    #[test]
+    fn interleaved_gradient_noise_matches_the_formula() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        // fract(52.9829189 * fract(0.06711056 * x + 0.00583715 * y)), worked in double precision
+        let expected = [
+            (0.0, 0.0, 0.0),
+            (1.0, 0.0, 0.555_713_4),
+            (0.0, 1.0, 0.309_269_2),
+            (3.0, 7.0, 0.832_024_8),
+        ];
+        let pixels: Vec<_> = expected.iter().map(|&(x, y, _)| (x, y)).collect();
+
+        for (noise, (_, _, expected)) in interleaved_gradient_noise(&mut rm, &pixels)
+            .into_iter()
+            .zip(expected)
+        {
+            assert!((noise - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn interleaved_gradient_noise_is_in_unit_range() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let pixels: Vec<_> = (0..64)
+            .flat_map(|y| (0..64).map(move |x| (x as f32, y as f32)))
+            .collect();
+
+        for noise in interleaved_gradient_noise(&mut rm, &pixels) {
+            assert!((0.0..1.0).contains(&noise));
+        }
+    }
+
+    #[test]
+    fn multi_bounce_matches_the_published_fit() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        // ((ao * a + b) * ao + c) * ao with Jimenez et al.'s coefficients, worked by hand
+        let bounced = multi_bounce(&mut rm, &[(0.5, Vec3::new(0.0, 0.5, 0.8))]);
+        assert!(
+            (bounced[0] - Vec3::new(0.5, 0.680_962_5, 0.811_125))
+                .abs()
+                .max_element()
+                < 1e-5
+        );
+    }
+
+    #[test]
+    fn multi_bounce_keeps_the_end_points() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let albedos = [0.0, 0.25, 0.5, 1.0].map(Vec3::splat);
+        let occluded: Vec<_> = albedos.iter().map(|&albedo| (0.0, albedo)).collect();
+        let unoccluded: Vec<_> = albedos.iter().map(|&albedo| (1.0, albedo)).collect();
+
+        for bounced in multi_bounce(&mut rm, &occluded) {
+            assert_eq!(bounced, Vec3::ZERO);
+        }
+        for bounced in multi_bounce(&mut rm, &unoccluded) {
+            // The fit's coefficients sum to 1 within rounding
+            assert!((bounced - Vec3::ONE).abs().max_element() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn multi_bounce_brightens_with_albedo() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let aos = [0.1, 0.3, 0.5, 0.7, 0.9];
+        let dark: Vec<_> = aos.iter().map(|&ao| (ao, Vec3::splat(0.2))).collect();
+        let light: Vec<_> = aos.iter().map(|&ao| (ao, Vec3::splat(0.9))).collect();
+
+        let dark = multi_bounce(&mut rm, &dark);
+        let light = multi_bounce(&mut rm, &light);
+        for ((ao, dark), light) in aos.into_iter().zip(dark).zip(light) {
+            assert!(dark.x >= ao);
+            assert!(light.x > dark.x);
+        }
+    }
+
+    #[test]
+    fn samples_stay_in_the_upper_unit_hemisphere() {
+        for sample in CrytekSSAO::generate_samples(SAMPLE_COUNT_RANGE.end() * 4).chunks_exact(4) {
+            let sample = Vec3::new(sample[0].to_f32(), sample[1].to_f32(), sample[2].to_f32());
+            assert!(sample.z >= 0.0);
+            // Rounding to f16 can push the length a little past 1
+            assert!(sample.length() <= 1.001);
+        }
+    }
+
+    #[test]
+    fn noise_covers_a_4x4_rgba16_float_texture() {
+        let noise = CrytekSSAO::generate_noise();
+
+        assert_eq!(NOISE_SIZE, 4);
+        assert_eq!(NOISE_FORMAT, TextureFormat::Rgba16Float);
+        assert_eq!(
+            std::mem::size_of_val(noise.as_slice()),
+            (NOISE_FORMAT.block_size(None).unwrap() * NOISE_SIZE * NOISE_SIZE) as usize
+        );
+        for rotation in noise.chunks_exact(4) {
+            assert_eq!(rotation[2].to_f32(), 0.0);
+        }
+    }
+
+    #[test]
+    fn scaled_dimensions_round_up() {
+        assert_eq!(scaled_dimensions((1600, 900), 1), (1600, 900));
+        assert_eq!(scaled_dimensions((1600, 900), 2), (800, 450));
+        assert_eq!(scaled_dimensions((1599, 901), 2), (800, 451));
+        assert_eq!(scaled_dimensions((1599, 901), 4), (400, 226));
+        assert_eq!(scaled_dimensions((1, 1), 4), (1, 1));
+    }
+
+    #[test]
+    fn uniforms_match_the_wgsl_struct() {
+        // SSAOUniforms in crytek_ssao.wgsl, where the vec2 noise_scale starts 8 byte aligned
+        assert_eq!(std::mem::size_of::<CrytekSSAOUniformData>(), 32);
+        let uniforms = CrytekSSAOUniformData {
+            normal_bias: 0.0,
+            flip_normals: 0,
+            noise_scale: [0.25, 0.5],
+            radius: 0.5,
+            range_check: 2.0,
+            sample_count: 16,
+            rotation_noise: 1,
+        };
+        let bytes = bytemuck::bytes_of(&uniforms);
+        assert_eq!(bytes[8..16], *bytemuck::bytes_of(&[0.25f32, 0.5]));
+        assert_eq!(bytes[16..20], 0.5f32.to_ne_bytes());
+        assert_eq!(bytes[20..24], 2.0f32.to_ne_bytes());
+        assert_eq!(bytes[24..28], 16u32.to_ne_bytes());
+    }
+    /// Builds the technique against blank scene-sized inputs, returns it with its albedo input.
+    fn crytek_ssao(rm: &mut ResourceManager) -> (CrytekSSAO, Handle) {
+        let dimensions = (
+            rm.surface_configuration.width,
+            rm.surface_configuration.height,
+        );
+        let mut buffer = |format| {
+            rm.create_texture(&TextureDesc {
+                dimensions,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                ..Default::default()
+            })
+        };
+        let depth_buffer = buffer(DEPTH_FORMAT);
+        let albedo = buffer(TextureFormat::Rgba8UnormSrgb);
+        (CrytekSSAO::new(rm, depth_buffer, albedo).unwrap(), albedo)
+    }
+
+    #[test]
+    fn radius_slider_clamps_into_the_uniforms() {
+        let Some(mut rm) = ResourceManager::headless_loading_depth() else {
+            return;
+        };
+        let (mut ssao, _) = crytek_ssao(&mut rm);
+        ssao.radius = 5.0;
+
+        let ctx = egui::Context::default();
+        let mut press = |key| {
+            let input = egui::RawInput {
+                events: vec![egui::Event::Key {
+                    key,
+                    pressed: true,
+                    repeat: false,
+                    modifiers: egui::Modifiers::NONE,
+                }],
+                ..Default::default()
+            };
+            let _ = ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| ssao.ui(&mut rm, ui));
+            });
+        };
+        // Tabs past the three resolution buttons to the radius slider, then nudges it
+        for _ in 0..4 {
+            press(egui::Key::Tab);
+        }
+        press(egui::Key::ArrowLeft);
+
+        assert_eq!(ssao.radius, 2.0);
+        ssao.update_uniforms(&rm);
+        let uniforms: CrytekSSAOUniformData =
+            bytemuck::pod_read_unaligned(&rm.read_buffer(ssao.uniform_buffer));
+        assert_eq!(uniforms.radius, 2.0);
+    }
+
+    #[test]
+    fn sample_count_sets_the_samples_texture_width() {
+        let Some(mut rm) = ResourceManager::headless_loading_depth() else {
+            return;
+        };
+        let (mut ssao, _) = crytek_ssao(&mut rm);
+
+        ssao.set_sample_count(&mut rm, 32);
+        assert_eq!(rm.get_texture(ssao.samples_texture).size().width, 32);
+        ssao.set_sample_count(&mut rm, 1000);
+        assert_eq!(ssao.sample_count, *SAMPLE_COUNT_RANGE.end());
+        assert_eq!(
+            rm.get_texture(ssao.samples_texture).size().width,
+            *SAMPLE_COUNT_RANGE.end()
+        );
+    }
+
+    #[test]
+    fn composite_reads_the_ao_and_the_albedo() {
+        let Some(mut rm) = ResourceManager::headless_loading_depth() else {
+            return;
+        };
+        let (ssao, albedo) = crytek_ssao(&mut rm);
+
+        // Raw and blurred AO, so the output mode can pick between them without rebinding
+        let textures = rm.bind_group_textures(ssao.composite_bind_group);
+        assert!(textures == [ssao.raw_ao, ssao.blurred_ao, albedo]);
+    }
 }
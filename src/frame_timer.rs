@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+
+const HISTORY_LENGTH: usize = 240;
+
+/// CPU frame times over the last few seconds.
+pub struct FrameTimer {
+    // Seconds, oldest first
+    frame_times: VecDeque<f32>,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    pub fn record(&mut self, dt: f32) {
+        if self.frame_times.len() == HISTORY_LENGTH {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt);
+    }
+
+    pub fn average(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    /// Average of the slowest 1% of frames, at least one frame.
+    pub fn one_percent_low(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+
+        let mut sorted = self.frame_times.iter().copied().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| b.total_cmp(a));
+        let count = (sorted.len() / 100).max(1);
+        sorted[..count].iter().sum::<f32>() / count as f32
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Frame time")
+            .default_open(true)
+            .show(ui, |ui| {
+                let average = self.average();
+                let low = self.one_percent_low();
+                ui.label(format!(
+                    "{:.2} ms ({:.0} fps), 1% low {:.2} ms ({:.0} fps)",
+                    average * 1000.0,
+                    1.0 / average.max(f32::EPSILON),
+                    low * 1000.0,
+                    1.0 / low.max(f32::EPSILON),
+                ));
+
+                let points = self
+                    .frame_times
+                    .iter()
+                    .enumerate()
+                    .map(|(i, dt)| [i as f64, *dt as f64 * 1000.0])
+                    .collect::<egui::plot::PlotPoints>();
+                egui::plot::Plot::new("Frame times")
+                    .height(80.0)
+                    .include_x(HISTORY_LENGTH as f64)
+                    .include_y(0.0)
+                    .allow_drag(false)
+                    .allow_zoom(false)
+                    .allow_scroll(false)
+                    .show_x(false)
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(egui::plot::Line::new(points).name("ms"))
+                    });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_timer_reports_zero() {
+        let timer = FrameTimer::new();
+        assert_eq!(timer.average(), 0.0);
+        assert_eq!(timer.one_percent_low(), 0.0);
+    }
+
+    #[test]
+    fn averages_known_frame_times() {
+        let mut timer = FrameTimer::new();
+        for dt in [0.010, 0.020, 0.030, 0.040] {
+            timer.record(dt);
+        }
+        assert!((timer.average() - 0.025).abs() < 1e-6);
+        // Fewer than 100 frames, so the 1% low is the single slowest one
+        assert_eq!(timer.one_percent_low(), 0.040);
+    }
+
+    #[test]
+    fn only_the_last_frames_are_kept() {
+        let mut timer = FrameTimer::new();
+        for _ in 0..HISTORY_LENGTH {
+            timer.record(1.0);
+        }
+        for _ in 0..HISTORY_LENGTH {
+            timer.record(0.010);
+        }
+        assert_eq!(timer.frame_times.len(), HISTORY_LENGTH);
+        assert!((timer.average() - 0.010).abs() < 1e-6);
+    }
+
+    #[test]
+    fn one_percent_low_averages_the_slowest_frames() {
+        let mut timer = FrameTimer::new();
+        for i in 0..200 {
+            timer.record(if i % 100 == 0 { 0.050 } else { 0.010 });
+        }
+        // 2 of the 200 frames
+        assert!((timer.one_percent_low() - 0.050).abs() < 1e-6);
+
+        timer.record(0.100);
+        // Still 2 frames, the slowest two of 201
+        assert!((timer.one_percent_low() - 0.075).abs() < 1e-6);
+    }
+}
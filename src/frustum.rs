@@ -0,0 +1,129 @@
+use glam::{Mat4, Vec3, Vec4};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(
+            Aabb {
+                min: Vec3::splat(f32::MAX),
+                max: Vec3::splat(f32::MIN),
+            },
+            |aabb, point| Aabb {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            },
+        )
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Radius of the sphere through the corners, centered on the box.
+    pub fn bounding_radius(&self) -> f32 {
+        (self.max - self.min).length() * 0.5
+    }
+
+    /// The box around all eight transformed corners, so it can be looser than the geometry's.
+    pub fn transformed(&self, transform: Mat4) -> Self {
+        let corners = (0..8).map(|i| {
+            transform.transform_point3(Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            ))
+        });
+        Aabb::from_points(corners)
+    }
+}
+
+/// Planes facing into the frustum as (normal, distance), unnormalized.
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Gribb-Hartmann extraction, for clip space depth in 0..1.
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let x = view_projection.row(0);
+        let y = view_projection.row(1);
+        let z = view_projection.row(2);
+        let w = view_projection.row(3);
+
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z],
+        }
+    }
+
+    /// False only when the box is entirely behind one of the planes. Boxes near a frustum
+    /// corner can pass without being visible.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal
+            let corner = Vec3::select(plane.truncate().cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera_frustum() -> Frustum {
+        // Looking down +Z from the origin
+        let projection = Mat4::perspective_lh(90f32.to_radians(), 1.0, 1.0, 10.0);
+        let view = Mat4::look_to_lh(Vec3::ZERO, Vec3::Z, Vec3::Y);
+        Frustum::from_view_projection(projection * view)
+    }
+
+    #[test]
+    fn planes_match_the_projection() {
+        let frustum = camera_frustum();
+        let normalized = frustum
+            .planes
+            .map(|plane| plane / plane.truncate().length());
+
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        let expected = [
+            Vec4::new(diagonal, 0.0, diagonal, 0.0),
+            Vec4::new(-diagonal, 0.0, diagonal, 0.0),
+            Vec4::new(0.0, diagonal, diagonal, 0.0),
+            Vec4::new(0.0, -diagonal, diagonal, 0.0),
+            Vec4::new(0.0, 0.0, 1.0, -1.0),
+            Vec4::new(0.0, 0.0, -1.0, 10.0),
+        ];
+        for (plane, expected) in normalized.iter().zip(expected) {
+            assert!(plane.abs_diff_eq(expected, 1e-5), "{plane} != {expected}");
+        }
+    }
+
+    #[test]
+    fn culls_boxes_outside_the_frustum() {
+        let frustum = camera_frustum();
+        let unit_box_at = |center: Vec3| Aabb {
+            min: center - 0.5,
+            max: center + 0.5,
+        };
+
+        assert!(frustum.intersects(&unit_box_at(Vec3::new(0.0, 0.0, 5.0))));
+        // Straddling the near plane
+        assert!(frustum.intersects(&unit_box_at(Vec3::new(0.0, 0.0, 1.0))));
+
+        assert!(!frustum.intersects(&unit_box_at(Vec3::new(0.0, 0.0, -5.0))));
+        assert!(!frustum.intersects(&unit_box_at(Vec3::new(0.0, 0.0, 20.0))));
+        assert!(!frustum.intersects(&unit_box_at(Vec3::new(10.0, 0.0, 5.0))));
+    }
+}
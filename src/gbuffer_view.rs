@@ -0,0 +1,230 @@
+use wgpu::{CommandEncoder, SamplerBindingType, ShaderStages, TextureSampleType, TextureView};
+
+use crate::{
+    resource_manager::{
+        BindGroupDesc, BindGroupLayoutDesc, Handle, ResourceError, ResourceManager, SamplerDesc,
+        ShaderDesc, ShaderModuleDesc, ShaderPipelineDesc, TextureDesc, TextureFormat,
+        TextureUsages, TextureViewDimension, SCENE_COLOR_FORMAT,
+    },
+    scene::Scene,
+};
+
+/// Tile labels, row by row, matching the layout in gbuffer_view.wgsl
+const TILE_LABELS: [&str; 4] = ["Color", "View-space normals", "Linear depth", "AO"];
+
+/// Tiles the geometry pass outputs and the active technique's AO into a 2x2 grid.
+pub struct GBufferView {
+    // The scene color target is being rendered to, so it's copied here first
+    color_copy: Handle,
+    normal_buffer: Handle,
+    depth_buffer: Handle,
+    sampler: Handle,
+    // White, shown while no technique is selected
+    no_ao: Handle,
+    shader: Handle,
+    // By AO texture, each technique's is bound in a bind group of its own
+    bind_groups: Vec<(Handle, Handle)>,
+    ao_texture: Handle,
+}
+
+impl GBufferView {
+    pub fn bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![],
+            textures: vec![
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (TextureSampleType::Depth, TextureViewDimension::D2),
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+            ],
+            samplers: vec![SamplerBindingType::Filtering],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    pub fn new(
+        rm: &mut ResourceManager,
+        normal_buffer: Handle,
+        depth_buffer: Handle,
+    ) -> Result<Self, ResourceError> {
+        let color_copy = rm.create_texture(&TextureDesc {
+            label: Some("G-buffer view color copy"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: SCENE_COLOR_FORMAT,
+            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        });
+
+        let sampler = rm.create_sampler(SamplerDesc {
+            label: Some("G-buffer view sampler"),
+            address_mode: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            mipmaps: None,
+            compare: None,
+            anisotropy: 1,
+        });
+
+        let no_ao = rm.create_texture(&TextureDesc {
+            label: Some("G-buffer view no AO"),
+            dimensions: (1, 1),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            initial_data: Some(&[255]),
+        });
+
+        let shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("G-buffer view shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/gbuffer_view.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/gbuffer_view.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![
+                Scene::scene_bind_group_layout(),
+                GBufferView::bind_group_layout(),
+            ],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![SCENE_COLOR_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let mut gbuffer_view = Self {
+            color_copy,
+            normal_buffer,
+            depth_buffer,
+            sampler,
+            no_ao,
+            shader,
+            bind_groups: vec![],
+            ao_texture: no_ao,
+        };
+        gbuffer_view.set_ao_texture(rm, None)?;
+
+        Ok(gbuffer_view)
+    }
+
+    /// Shows `ao_texture` in the AO tile, white for None.
+    pub fn set_ao_texture(
+        &mut self,
+        rm: &mut ResourceManager,
+        ao_texture: Option<Handle>,
+    ) -> Result<(), ResourceError> {
+        let ao_texture = ao_texture.unwrap_or(self.no_ao);
+        if !self.bind_groups.iter().any(|(t, _)| *t == ao_texture) {
+            let bind_group = rm.create_bind_group(&BindGroupDesc {
+                label: None,
+                visibility: ShaderStages::FRAGMENT,
+                layout: GBufferView::bind_group_layout(),
+                buffers: &[],
+                textures: &[
+                    self.color_copy,
+                    self.normal_buffer,
+                    self.depth_buffer,
+                    ao_texture,
+                ],
+                samplers: &[self.sampler],
+                storage_buffers: &[],
+                storage_textures: &[],
+            })?;
+            self.bind_groups.push((ao_texture, bind_group));
+        }
+
+        self.ao_texture = ao_texture;
+        Ok(())
+    }
+
+    pub fn resize(&self, rm: &mut ResourceManager, dimensions: (u32, u32)) {
+        rm.resize_texture(self.color_copy, dimensions);
+    }
+
+    /// `color` is the texture behind `view`, it must allow `COPY_SRC`.
+    pub fn pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        color: Handle,
+        view: &TextureView,
+    ) {
+        let color = rm.get_texture(color);
+        encoder.copy_texture_to_texture(
+            color.as_image_copy(),
+            rm.get_texture(self.color_copy).as_image_copy(),
+            color.size(),
+        );
+
+        {
+            let mut grid_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("G-buffer view"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            grid_pass.set_pipeline(rm.get_shader(self.shader).pipeline());
+            grid_pass.set_bind_group(0, rm.get_bind_group(scene.scene_uniform_bind_group), &[]);
+            let (_, bind_group) = self
+                .bind_groups
+                .iter()
+                .find(|(t, _)| *t == self.ao_texture)
+                .unwrap();
+            grid_pass.set_bind_group(1, rm.get_bind_group(*bind_group), &[]);
+            grid_pass.draw(0..6, 0..1);
+        }
+    }
+
+    /// Draws the tile labels over the grid, `viewport` is where the scene is shown.
+    pub fn labels(&self, painter: &egui::Painter, viewport: egui::Rect) {
+        let tile_size = viewport.size() / 2.0;
+
+        for (i, label) in TILE_LABELS.iter().enumerate() {
+            let tile_min = viewport.min + egui::vec2((i % 2) as f32, (i / 2) as f32) * tile_size;
+            painter.text(
+                tile_min + egui::vec2(8.0, 8.0),
+                egui::Align2::LEFT_TOP,
+                label,
+                egui::FontId::proportional(16.0),
+                egui::Color32::WHITE,
+            );
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use crate::resource_manager::ResourceManager;
+
+const MAX_SCOPES: u32 = 16;
+// Frames that can be in flight before one is read back. Frames resolved while every buffer is
+// still waiting on the GPU aren't timed.
+const READBACK_FRAMES: u64 = 3;
+
+struct Readback {
+    buffer: wgpu::Buffer,
+    /// Labels of the timestamps in `buffer`, empty while it's free to copy into
+    scopes: Vec<String>,
+    frame: u64,
+    mapped: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+struct Queries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readbacks: Vec<Readback>,
+}
+
+/// Brackets passes with timestamp queries and reads the results back once the GPU is done with
+/// them, usually a frame or two later. Does nothing when the device doesn't support
+/// `TIMESTAMP_QUERY`.
+pub struct GpuProfiler {
+    queries: Option<Queries>,
+    scopes: Vec<String>,
+    frame: u64,
+    timings: Vec<(String, f32)>,
+    timings_frame: u64,
+}
+
+impl GpuProfiler {
+    pub fn new(rm: &ResourceManager) -> Self {
+        let queries = if rm
+            .device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+        {
+            let byte_size = (MAX_SCOPES * 2) as u64 * std::mem::size_of::<u64>() as u64;
+
+            Some(Queries {
+                query_set: rm.device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: Some("GPU profiler queries"),
+                    ty: wgpu::QueryType::Timestamp,
+                    count: MAX_SCOPES * 2,
+                }),
+                resolve_buffer: rm.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("GPU profiler resolve buffer"),
+                    size: byte_size,
+                    usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                readbacks: (0..READBACK_FRAMES)
+                    .map(|_| Readback {
+                        buffer: rm.device.create_buffer(&wgpu::BufferDescriptor {
+                            label: Some("GPU profiler readback buffer"),
+                            size: byte_size,
+                            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        }),
+                        scopes: vec![],
+                        frame: 0,
+                        mapped: None,
+                    })
+                    .collect(),
+            })
+        } else {
+            None
+        };
+
+        Self {
+            queries,
+            scopes: vec![],
+            frame: 0,
+            timings: vec![],
+            timings_frame: 0,
+        }
+    }
+
+    pub fn begin_scope(&mut self, encoder: &mut wgpu::CommandEncoder, label: &str) {
+        if let Some(queries) = &self.queries {
+            if self.scopes.len() as u32 >= MAX_SCOPES {
+                return;
+            }
+
+            encoder.write_timestamp(&queries.query_set, self.scopes.len() as u32 * 2);
+            self.scopes.push(String::from(label));
+        }
+    }
+
+    pub fn end_scope(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(queries) = &self.queries {
+            if self.scopes.is_empty() {
+                return;
+            }
+
+            encoder.write_timestamp(&queries.query_set, self.scopes.len() as u32 * 2 - 1);
+        }
+    }
+
+    fn readback_slot(&self) -> usize {
+        (self.frame % READBACK_FRAMES) as usize
+    }
+
+    /// Call once per frame after the last scope, before the encoder is finished.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(queries) = &self.queries {
+            let readback = &queries.readbacks[self.readback_slot()];
+            if self.scopes.is_empty() || !readback.scopes.is_empty() {
+                return;
+            }
+
+            let query_count = self.scopes.len() as u32 * 2;
+            encoder.resolve_query_set(
+                &queries.query_set,
+                0..query_count,
+                &queries.resolve_buffer,
+                0,
+            );
+            encoder.copy_buffer_to_buffer(
+                &queries.resolve_buffer,
+                0,
+                &readback.buffer,
+                0,
+                query_count as u64 * std::mem::size_of::<u64>() as u64,
+            );
+        }
+    }
+
+    /// Call once per frame after submission. Starts reading back this frame's timestamps and
+    /// shows the latest frame the GPU has finished, without waiting on it.
+    pub fn read_timings(&mut self, rm: &ResourceManager) {
+        let slot = self.readback_slot();
+        if let Some(queries) = &mut self.queries {
+            let readback = &mut queries.readbacks[slot];
+            // Matches the check in resolve, nothing was copied when the buffer was still in use
+            if !self.scopes.is_empty() && readback.scopes.is_empty() {
+                let byte_size = self.scopes.len() as u64 * 2 * std::mem::size_of::<u64>() as u64;
+                let (sender, receiver) = std::sync::mpsc::channel();
+                readback
+                    .buffer
+                    .slice(0..byte_size)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        _ = sender.send(result);
+                    });
+                readback.scopes = std::mem::take(&mut self.scopes);
+                readback.frame = self.frame;
+                readback.mapped = Some(receiver);
+            }
+
+            // Runs the callbacks of finished mappings without blocking
+            rm.device.poll(wgpu::Maintain::Poll);
+
+            let period = rm.queue.get_timestamp_period();
+            for readback in &mut queries.readbacks {
+                let result = match &readback.mapped {
+                    Some(receiver) => receiver.try_recv(),
+                    None => continue,
+                };
+                match result {
+                    Err(TryRecvError::Empty) => continue,
+                    Ok(Ok(())) => {
+                        if readback.frame >= self.timings_frame {
+                            let byte_size = readback.scopes.len() as u64
+                                * 2
+                                * std::mem::size_of::<u64>() as u64;
+                            let timestamps: Vec<u64> = bytemuck::cast_slice(
+                                &readback.buffer.slice(0..byte_size).get_mapped_range(),
+                            )
+                            .to_vec();
+
+                            self.timings = readback
+                                .scopes
+                                .iter()
+                                .zip(timestamps.chunks_exact(2))
+                                .map(|(label, pair)| {
+                                    let ticks = pair[1].wrapping_sub(pair[0]);
+                                    (label.clone(), ticks as f32 * period / 1_000_000.0)
+                                })
+                                .collect();
+                            self.timings_frame = readback.frame;
+                        }
+                        readback.buffer.unmap();
+                    }
+                    // Failed mappings leave the buffer unmapped
+                    Ok(Err(_)) | Err(TryRecvError::Disconnected) => {}
+                }
+                readback.scopes.clear();
+                readback.mapped = None;
+            }
+        }
+
+        self.scopes.clear();
+        self.frame += 1;
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        if self.queries.is_none() {
+            return;
+        }
+
+        egui::CollapsingHeader::new("GPU timings").show(ui, |ui| {
+            for (label, ms) in &self.timings {
+                ui.label(format!("{}: {:.2} ms", label, ms));
+            }
+        });
+    }
+}
@@ -0,0 +1,89 @@
+use glam::{vec3, Vec3};
+
+use crate::scene::SceneUniformData;
+
+/// A sun-like light shining from the same direction everywhere, plus a constant ambient term.
+pub struct DirectionalLight {
+    // Degrees around the y axis, 0 is +x
+    azimuth: f32,
+    // Degrees above the horizon
+    elevation: f32,
+    // Linear RGB
+    color: [f32; 3],
+    intensity: f32,
+    ambient: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            azimuth: 45.0,
+            elevation: 45.0,
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            ambient: 0.2,
+        }
+    }
+}
+
+impl DirectionalLight {
+    /// World space, pointing from surfaces towards the light.
+    pub fn direction(&self) -> Vec3 {
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+        vec3(
+            azimuth.cos() * elevation.cos(),
+            elevation.sin(),
+            azimuth.sin() * elevation.cos(),
+        )
+    }
+
+    /// Fills in the light fields of uniforms built by the camera.
+    pub fn apply(&self, uniforms: &mut SceneUniformData) {
+        uniforms.light_direction = self.direction();
+        uniforms.light_intensity = self.intensity;
+        uniforms.light_color = Vec3::from_array(self.color);
+        uniforms.ambient_intensity = self.ambient;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Light").show(ui, |ui| {
+            ui.add(egui::Slider::new(&mut self.azimuth, -180.0..=180.0).text("Azimuth"));
+            ui.add(egui::Slider::new(&mut self.elevation, -90.0..=90.0).text("Elevation"));
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgb(&mut self.color);
+                ui.label("Color");
+            });
+            ui.add(egui::Slider::new(&mut self.intensity, 0.0..=4.0).text("Intensity"));
+            ui.add(egui::Slider::new(&mut self.ambient, 0.0..=1.0).text("Ambient"));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_uniforms_match_the_wgsl_struct() {
+        // SceneUniforms in common.wgsl, where each vec3 starts 16 byte aligned
+        assert_eq!(std::mem::size_of::<SceneUniformData>(), 320);
+        assert_eq!(std::mem::align_of::<SceneUniformData>(), 16);
+
+        let light = DirectionalLight {
+            azimuth: 90.0,
+            elevation: 0.0,
+            color: [1.0, 0.5, 0.25],
+            intensity: 2.0,
+            ambient: 0.125,
+        };
+        let mut uniforms = SceneUniformData::default();
+        light.apply(&mut uniforms);
+        let bytes = bytemuck::bytes_of(&uniforms);
+
+        let direction: [f32; 3] = bytemuck::pod_read_unaligned(&bytes[288..300]);
+        assert!((Vec3::from(direction) - Vec3::Z).abs().max_element() < 1e-6);
+        assert_eq!(bytes[300..304], 2.0f32.to_ne_bytes());
+        assert_eq!(bytes[304..316], *bytemuck::bytes_of(&[1.0f32, 0.5, 0.25]));
+        assert_eq!(bytes[316..320], 0.125f32.to_ne_bytes());
+    }
+}
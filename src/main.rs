@@ -1,7 +1,13 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
 use egui::{ClippedPrimitive, TexturesDelta};
 use pollster::block_on;
 use renderer::Renderer;
 use resource_manager::ResourceManager;
+use settings::{Settings, SETTINGS_PATH};
 use winit::{
     dpi::PhysicalSize,
     event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
@@ -9,16 +15,39 @@ use winit::{
     window::WindowBuilder,
 };
 
-pub const WIDTH: u32 = 1600;
-pub const HEIGHT: u32 = 900;
-pub const BACKEND: wgpu::Backends = wgpu::Backends::DX12;
+/// Only read when neither the command line nor the settings file picks a backend.
+pub const BACKEND_ENV_VAR: &str = "SSAO_BACKEND";
 
+mod ao_technique;
 mod camera;
 mod crytek_ssao;
+mod frame_timer;
+mod frustum;
+mod gbuffer_view;
+mod gpu_profiler;
+mod light;
+mod overdraw_view;
+mod reconstructed_normals_view;
+mod reference_ao;
 mod renderer;
 mod resource_manager;
 mod scene;
+mod settings;
+#[cfg(test)]
+mod shader_harness;
+mod shader_preprocessor;
+mod shader_watcher;
+mod temporal_accumulator;
 mod texture_debug_view;
+mod tone_mapping;
+mod visibility_bitmask_ao;
+
+// Enabled when the adapter has them. The GPU profiler hides its timings when unsupported, shaders
+// can only declare push constant ranges with PUSH_CONSTANTS and the wireframe toggle checks
+// supports_wireframe.
+const OPTIONAL_FEATURES: wgpu::Features = wgpu::Features::TIMESTAMP_QUERY
+    .union(wgpu::Features::PUSH_CONSTANTS)
+    .union(wgpu::Features::POLYGON_MODE_LINE);
 
 pub struct EguiRenderData {
     clipped_primitives: Vec<ClippedPrimitive>,
@@ -50,25 +79,63 @@ impl Into<egui_wgpu::renderer::ScreenDescriptor> for ScreenDescriptor {
     }
 }
 
+/// Maps a backend name (dx12, vulkan, metal, gl, primary) to wgpu backends, defaulting to primary.
+pub fn parse_backend(name: &str) -> wgpu::Backends {
+    match name.trim().to_lowercase().as_str() {
+        "dx12" => wgpu::Backends::DX12,
+        "vulkan" => wgpu::Backends::VULKAN,
+        "metal" => wgpu::Backends::METAL,
+        "gl" => wgpu::Backends::GL,
+        _ => wgpu::Backends::PRIMARY,
+    }
+}
+
+/// Settings from the command line or file win over the environment variable, so that an explicit
+/// `--backend` is never ignored.
+pub fn resolve_backend(setting: Option<&str>, env: Option<&str>) -> wgpu::Backends {
+    parse_backend(setting.or(env).unwrap_or("primary"))
+}
+
+/// Prefers an sRGB format so egui's output is encoded by the hardware, otherwise the surface's
+/// preferred format, which comes first.
+pub fn surface_format(formats: &[wgpu::TextureFormat]) -> wgpu::TextureFormat {
+    formats
+        .iter()
+        .copied()
+        .find(|f| f.is_srgb())
+        .unwrap_or(formats[0])
+}
+
 fn main() {
     env_logger::init();
+    let settings = match Settings::load(SETTINGS_PATH).apply_args(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
     let event_loop = event_loop::EventLoop::new();
     let window = WindowBuilder::new()
-        .with_inner_size(PhysicalSize::new(WIDTH, HEIGHT))
+        .with_inner_size(PhysicalSize::new(settings.width, settings.height))
         .with_title("SSAO techniques")
         .build(&event_loop)
         .unwrap();
 
     let mut egui_state = egui_winit::State::new(&event_loop);
     let egui_context = egui::Context::default();
-    let egui_screen_descriptor = egui_wgpu::renderer::ScreenDescriptor {
-        size_in_pixels: [WIDTH, HEIGHT],
+    let mut egui_screen_descriptor = egui_wgpu::renderer::ScreenDescriptor {
+        size_in_pixels: [settings.width, settings.height],
         pixels_per_point: window.scale_factor() as f32,
     };
 
+    let backend = resolve_backend(
+        settings.backend.as_deref(),
+        std::env::var(BACKEND_ENV_VAR).ok().as_deref(),
+    );
     let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-        backends: BACKEND,
+        backends: backend,
         dx12_shader_compiler: Default::default(),
     });
 
@@ -81,36 +148,73 @@ fn main() {
     }))
     .unwrap();
 
+    let features = adapter.features() & OPTIONAL_FEATURES;
+    let limits = wgpu::Limits {
+        max_push_constant_size: adapter.limits().max_push_constant_size.min(128),
+        ..wgpu::Limits::default()
+    };
+
     let (device, queue) = block_on(adapter.request_device(
         &wgpu::DeviceDescriptor {
             label: Some("Device"),
-            features: wgpu::Features::empty(),
-            limits: wgpu::Limits::default(),
+            features,
+            limits,
         },
         None,
     ))
     .unwrap();
 
     let surface_caps = surface.get_capabilities(&adapter);
-    let surface_format = surface_caps
-        .formats
-        .iter()
-        .copied()
-        .find(|f| f.is_srgb())
-        .unwrap_or(surface_caps.formats[0]);
+    let surface_format = surface_format(&surface_caps.formats);
     let config = wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST,
         format: surface_format,
-        width: WIDTH,
-        height: HEIGHT,
-        present_mode: surface_caps.present_modes[0],
+        width: settings.width,
+        height: settings.height,
+        // Fifo is always supported
+        present_mode: if settings.vsync {
+            wgpu::PresentMode::Fifo
+        } else {
+            surface_caps
+                .present_modes
+                .iter()
+                .copied()
+                .find(|mode| {
+                    matches!(
+                        mode,
+                        wgpu::PresentMode::Mailbox | wgpu::PresentMode::Immediate
+                    )
+                })
+                .unwrap_or(wgpu::PresentMode::Fifo)
+        },
         alpha_mode: surface_caps.alpha_modes[0],
         view_formats: vec![],
     };
     surface.configure(&device, &config);
 
-    let resource_manager = ResourceManager::new(device, queue, surface, config);
-    let mut renderer = Renderer::new(resource_manager);
+    let mut resource_manager = ResourceManager::new(
+        device,
+        queue,
+        Some(surface),
+        config,
+        surface_caps.present_modes,
+    );
+    resource_manager.watch_shaders();
+    let mut renderer = match Renderer::new(resource_manager) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            println!("Failed to create renderer: {}", err);
+            std::process::exit(1);
+        }
+    };
+    match settings.scene.as_deref().map(Path::new) {
+        Some(scene) if scene.exists() => renderer.load_scene(scene),
+        Some(scene) => println!("Scene {} from settings doesn't exist", scene.display()),
+        None => {}
+    }
+    renderer.select_ao_technique(settings.ao_technique);
+
+    let mut last_frame = Instant::now();
 
     event_loop.run(move |event, _, control_flow| match event {
         winit::event::Event::WindowEvent { window_id, event } if window_id == window.id() => {
@@ -127,6 +231,37 @@ fn main() {
                         },
                     ..
                 } => *control_flow = ControlFlow::ExitWithCode(0),
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F12),
+                            ..
+                        },
+                    ..
+                } => {
+                    let seconds = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |time| time.as_secs());
+                    let path = PathBuf::from(format!("screenshot-{}.png", seconds));
+                    match renderer.capture_screenshot(&path) {
+                        Ok(()) => println!("Saved {}", path.display()),
+                        Err(err) => println!("Couldn't save {}: {}", path.display(), err),
+                    }
+                }
+                WindowEvent::Resized(new_size) => {
+                    renderer.resize(new_size);
+                    egui_screen_descriptor.size_in_pixels = [new_size.width, new_size.height];
+                }
+                WindowEvent::ScaleFactorChanged {
+                    scale_factor,
+                    new_inner_size,
+                } => {
+                    renderer.resize(*new_inner_size);
+                    egui_screen_descriptor.size_in_pixels =
+                        [new_inner_size.width, new_inner_size.height];
+                    egui_screen_descriptor.pixels_per_point = scale_factor as f32;
+                }
                 _ => {}
             }
         }
@@ -145,11 +280,63 @@ fn main() {
                 screen_descriptor: ScreenDescriptor::from(&egui_screen_descriptor),
             };
 
-            renderer.update(egui_render_data);
+            let now = Instant::now();
+            let dt = (now - last_frame).as_secs_f32();
+            last_frame = now;
+
+            renderer.update(egui_render_data, dt);
         }
         winit::event::Event::MainEventsCleared => {
+            if let Some(interval) = renderer.frame_interval() {
+                let elapsed = last_frame.elapsed();
+                if elapsed < interval {
+                    std::thread::sleep(interval - elapsed);
+                }
+            }
             window.request_redraw();
         }
         _ => {}
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_backend() {
+        assert_eq!(parse_backend("dx12"), wgpu::Backends::DX12);
+        assert_eq!(parse_backend("vulkan"), wgpu::Backends::VULKAN);
+        assert_eq!(parse_backend("metal"), wgpu::Backends::METAL);
+        assert_eq!(parse_backend("gl"), wgpu::Backends::GL);
+        assert_eq!(parse_backend("primary"), wgpu::Backends::PRIMARY);
+        assert_eq!(parse_backend(" Vulkan "), wgpu::Backends::VULKAN);
+    }
+
+    #[test]
+    fn unknown_backends_fall_back_to_primary() {
+        assert_eq!(parse_backend(""), wgpu::Backends::PRIMARY);
+        assert_eq!(parse_backend("webgpu"), wgpu::Backends::PRIMARY);
+    }
+
+    #[test]
+    fn settings_backend_wins_over_the_environment() {
+        assert_eq!(
+            resolve_backend(Some("vulkan"), Some("gl")),
+            wgpu::Backends::VULKAN
+        );
+        assert_eq!(resolve_backend(None, Some("gl")), wgpu::Backends::GL);
+        assert_eq!(resolve_backend(None, None), wgpu::Backends::PRIMARY);
+    }
+
+    #[test]
+    fn surface_format_prefers_srgb() {
+        use wgpu::TextureFormat::*;
+
+        assert_eq!(
+            surface_format(&[Bgra8Unorm, Rgba8UnormSrgb, Bgra8UnormSrgb]),
+            Rgba8UnormSrgb
+        );
+        assert_eq!(surface_format(&[Rgba16Float, Bgra8Unorm]), Rgba16Float);
+    }
+}
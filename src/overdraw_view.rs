@@ -0,0 +1,161 @@
+use wgpu::{CommandEncoder, ShaderStages, TextureFormat, TextureSampleType, TextureView};
+
+use crate::{
+    resource_manager::{
+        BindGroupDesc, BindGroupLayoutDesc, Handle, ResourceError, ResourceManager, ShaderDesc,
+        ShaderModuleDesc, ShaderPipelineDesc, TextureDesc, TextureUsages, TextureViewDimension,
+        ADDITIVE_BLEND, SCENE_COLOR_FORMAT,
+    },
+    scene::{Mesh, Scene, VertexAttributes},
+};
+
+pub struct OverdrawView {
+    accumulation_texture: Handle,
+    accumulate_shader: Handle,
+    heatmap_shader: Handle,
+    heatmap_bind_group: Handle,
+}
+
+impl OverdrawView {
+    pub fn bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![],
+            textures: vec![(
+                TextureSampleType::Float { filterable: true },
+                TextureViewDimension::D2,
+            )],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    pub fn new(rm: &mut ResourceManager) -> Result<Self, ResourceError> {
+        let accumulation_texture = rm.create_texture(&TextureDesc {
+            label: Some("Overdraw accumulation"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: TextureFormat::Rgba16Float,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        });
+
+        // No depth test, every rasterized fragment is counted
+        let accumulate_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Overdraw accumulate shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/overdraw.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/overdraw.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![Scene::scene_bind_group_layout(), Mesh::bind_group_layout()],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![TextureFormat::Rgba16Float],
+                blends: vec![Some(ADDITIVE_BLEND)],
+                vertex_buffer_bindings: vec![VertexAttributes::vertex_buffer_layout()],
+                ..Default::default()
+            },
+        })?;
+
+        let heatmap_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Overdraw heatmap shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/overdraw_heatmap.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/overdraw_heatmap.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![OverdrawView::bind_group_layout()],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![SCENE_COLOR_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let heatmap_bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: OverdrawView::bind_group_layout(),
+            buffers: &[],
+            textures: &[accumulation_texture],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        Ok(Self {
+            accumulation_texture,
+            accumulate_shader,
+            heatmap_shader,
+            heatmap_bind_group,
+        })
+    }
+
+    pub fn resize(&self, rm: &mut ResourceManager, dimensions: (u32, u32)) {
+        rm.resize_texture(self.accumulation_texture, dimensions);
+    }
+
+    pub fn pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        view: &TextureView,
+    ) {
+        {
+            let mut accumulate_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overdraw accumulate"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: rm.get_texture(self.accumulation_texture).view(),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            accumulate_pass.set_pipeline(rm.get_shader(self.accumulate_shader).pipeline());
+            scene.draw(rm, &mut accumulate_pass);
+        }
+
+        {
+            let mut heatmap_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overdraw heatmap"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            heatmap_pass.set_pipeline(rm.get_shader(self.heatmap_shader).pipeline());
+            heatmap_pass.set_bind_group(0, rm.get_bind_group(self.heatmap_bind_group), &[]);
+            heatmap_pass.draw(0..6, 0..1);
+        }
+    }
+}
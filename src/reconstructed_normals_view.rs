@@ -0,0 +1,126 @@
+use wgpu::{CommandEncoder, ShaderStages, TextureSampleType, TextureView, TextureViewDimension};
+
+use crate::{
+    resource_manager::{
+        BindGroupDesc, BindGroupLayoutDesc, Handle, ResourceError, ResourceManager, ShaderDesc,
+        ShaderModuleDesc, ShaderPipelineDesc, SCENE_COLOR_FORMAT,
+    },
+    scene::Scene,
+};
+
+/// View-space normals reconstructed from depth alone, the way the Crytek SSAO pass does, to
+/// compare against the normal buffer.
+pub struct ReconstructedNormalsView {
+    shader: Handle,
+    bind_group: Handle,
+}
+
+impl ReconstructedNormalsView {
+    pub fn bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![],
+            textures: vec![(TextureSampleType::Depth, TextureViewDimension::D2)],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    pub fn new(rm: &mut ResourceManager, depth_buffer: Handle) -> Result<Self, ResourceError> {
+        let shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Reconstructed normals shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/reconstructed_normals.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/reconstructed_normals.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![
+                Scene::scene_bind_group_layout(),
+                ReconstructedNormalsView::bind_group_layout(),
+            ],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![SCENE_COLOR_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: Some("Reconstructed normals bind group"),
+            visibility: ShaderStages::FRAGMENT,
+            layout: ReconstructedNormalsView::bind_group_layout(),
+            buffers: &[],
+            textures: &[depth_buffer],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        Ok(Self { shader, bind_group })
+    }
+
+    pub fn pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        view: &TextureView,
+    ) {
+        let mut normals_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Reconstructed normals view"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        normals_pass.set_pipeline(rm.get_shader(self.shader).pipeline());
+        normals_pass.set_bind_group(0, rm.get_bind_group(scene.scene_uniform_bind_group), &[]);
+        normals_pass.set_bind_group(1, rm.get_bind_group(self.bind_group), &[]);
+        normals_pass.draw(0..6, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, Vec3};
+
+    use crate::camera::{view_position_from_depth, Camera};
+
+    #[test]
+    fn flat_plane_facing_the_camera_reconstructs_to_minus_z() {
+        for reverse_z in [false, true] {
+            let mut camera = Camera::default();
+            camera.reverse_z = reverse_z;
+            let uniforms = camera.build_uniforms();
+            let clip = uniforms.perspective * glam::vec4(0.0, 0.0, 5.0, 1.0);
+            let depth = clip.z / clip.w;
+
+            // One pixel apart on a 1600x900 target, as dpdx and dpdy see neighbouring fragments
+            let texel = vec2(1.0 / 1600.0, 1.0 / 900.0);
+            let uv = vec2(0.3, 0.6);
+            let position = |uv| view_position_from_depth(uv, depth, uniforms.inverse_perspective);
+            let origin = position(uv);
+            let dpdx = position(uv + vec2(texel.x, 0.0)) - origin;
+            let dpdy = position(uv + vec2(0.0, texel.y)) - origin;
+
+            // View space is left-handed, +Z looks into the screen, so a surface facing the camera
+            // has its normal along -Z
+            let normal = dpdx.cross(dpdy).normalize();
+            assert!((normal - Vec3::NEG_Z).length() < 1e-3, "{normal}");
+        }
+    }
+}
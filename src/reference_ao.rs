@@ -0,0 +1,458 @@
+use std::time::Instant;
+
+use glam::Vec3;
+use rand::prelude::*;
+use wgpu::{CommandEncoder, TextureFormat, TextureView};
+
+use crate::{
+    camera::view_position_from_depth,
+    resource_manager::{
+        Handle, IndexFormat, ResourceError, ResourceManager, TextureDesc, TextureUsages,
+        TextureViewDimension,
+    },
+    scene::{Scene, SceneUniformData, VertexAttributes},
+    texture_debug_view::TextureDebugView,
+};
+
+/// Triangles per BVH leaf
+const LEAF_SIZE: usize = 4;
+
+struct BvhNode {
+    min: Vec3,
+    max: Vec3,
+    // Leaves index into the triangles, inner nodes have their children at first and first + 1
+    first: usize,
+    count: usize,
+}
+
+/// Bounding volume hierarchy over world-space triangles, for CPU ray casts.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<[Vec3; 3]>,
+}
+
+impl Bvh {
+    pub fn new(mut triangles: Vec<[Vec3; 3]>) -> Self {
+        let mut bvh = Self {
+            nodes: vec![],
+            triangles: vec![],
+        };
+
+        if !triangles.is_empty() {
+            let count = triangles.len();
+            bvh.nodes.push(BvhNode {
+                min: Vec3::ZERO,
+                max: Vec3::ZERO,
+                first: 0,
+                count,
+            });
+            bvh.build(0, &mut triangles);
+        }
+
+        bvh.triangles = triangles;
+        bvh
+    }
+
+    fn build(&mut self, node: usize, triangles: &mut [[Vec3; 3]]) {
+        let first = self.nodes[node].first;
+        let count = self.nodes[node].count;
+        let range = &mut triangles[first..first + count];
+
+        let (min, max) = range.iter().flatten().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), vertex| (min.min(*vertex), max.max(*vertex)),
+        );
+        self.nodes[node].min = min;
+        self.nodes[node].max = max;
+
+        if count <= LEAF_SIZE {
+            return;
+        }
+
+        // Median split along the longest axis of the bounds
+        let extent = max - min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        range.sort_unstable_by(|a, b| {
+            let a = a[0][axis] + a[1][axis] + a[2][axis];
+            let b = b[0][axis] + b[1][axis] + b[2][axis];
+            a.total_cmp(&b)
+        });
+
+        let left = self.nodes.len();
+        let half = count / 2;
+        self.nodes.push(BvhNode {
+            min,
+            max,
+            first,
+            count: half,
+        });
+        self.nodes.push(BvhNode {
+            min,
+            max,
+            first: first + half,
+            count: count - half,
+        });
+        self.nodes[node].first = left;
+        self.nodes[node].count = 0;
+
+        self.build(left, triangles);
+        self.build(left + 1, triangles);
+    }
+
+    /// Distance to the closest triangle hit within `max_distance`, and that triangle's index.
+    pub fn cast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<(f32, usize)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inverse_direction = direction.recip();
+        let mut closest: Option<(f32, usize)> = None;
+        let mut stack = vec![0];
+
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let limit = closest.map_or(max_distance, |(distance, _)| distance);
+            if !ray_hits_box(origin, inverse_direction, node.min, node.max, limit) {
+                continue;
+            }
+
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+                continue;
+            }
+
+            for i in node.first..node.first + node.count {
+                if let Some(distance) = ray_hits_triangle(origin, direction, &self.triangles[i]) {
+                    if distance < closest.map_or(max_distance, |(distance, _)| distance) {
+                        closest = Some((distance, i));
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+
+    pub fn triangle(&self, index: usize) -> &[Vec3; 3] {
+        &self.triangles[index]
+    }
+}
+
+/// Slab test, true if the ray enters the box before `max_distance`.
+fn ray_hits_box(
+    origin: Vec3,
+    inverse_direction: Vec3,
+    min: Vec3,
+    max: Vec3,
+    max_distance: f32,
+) -> bool {
+    let t0 = (min - origin) * inverse_direction;
+    let t1 = (max - origin) * inverse_direction;
+    let near = t0.min(t1).max_element().max(0.0);
+    let far = t0.max(t1).min_element().min(max_distance);
+    near <= far
+}
+
+/// Möller-Trumbore, two-sided.
+fn ray_hits_triangle(origin: Vec3, direction: Vec3, triangle: &[Vec3; 3]) -> Option<f32> {
+    let edge1 = triangle[1] - triangle[0];
+    let edge2 = triangle[2] - triangle[0];
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+
+    let inverse_determinant = 1.0 / determinant;
+    let s = origin - triangle[0];
+    let u = s.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(q) * inverse_determinant;
+    (distance > 0.0).then_some(distance)
+}
+
+/// Ground truth AO, ray cast against the scene's triangles on the CPU. Slow, so it's only
+/// computed on request, for the camera at that moment.
+pub struct ReferenceAO {
+    texture: Handle,
+    view: TextureDebugView,
+
+    /// Hemisphere rays per pixel
+    pub samples: u32,
+    /// Occluders further than this don't count, matches the SSAO radius
+    pub radius: f32,
+}
+
+impl ReferenceAO {
+    pub fn new(rm: &mut ResourceManager) -> Result<Self, ResourceError> {
+        // Rgba rather than a single channel so the debug view shows it as gray
+        let texture = rm.create_texture(&TextureDesc {
+            label: Some("Reference AO"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        });
+        let view = TextureDebugView::new(rm, texture)?;
+
+        Ok(Self {
+            texture,
+            view,
+            samples: 64,
+            radius: 0.5,
+        })
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::Slider::new(&mut self.samples, 1..=512)
+                .logarithmic(true)
+                .text("Samples"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.radius, 0.05..=2.0)
+                .text("Radius")
+                .show_value(true),
+        );
+    }
+
+    /// Contents are discarded, `compute` has to be run again.
+    pub fn resize(&self, rm: &mut ResourceManager, dimensions: (u32, u32)) {
+        rm.resize_texture(self.texture, dimensions);
+    }
+
+    /// Reads the scene's geometry back from the GPU and ray casts AO for every pixel.
+    pub fn compute(&self, rm: &ResourceManager, scene: &Scene, camera: &SceneUniformData) {
+        let start = Instant::now();
+        let bvh = Bvh::new(ReferenceAO::read_triangles(rm, scene));
+
+        let size = rm.get_texture(self.texture).size();
+        let (width, height) = (size.width as usize, size.height as usize);
+        let mut pixels = vec![0u8; width * height * 4];
+
+        let threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+        let rows_per_thread = height.div_ceil(threads).max(1);
+        std::thread::scope(|scope| {
+            for (chunk, rows) in pixels.chunks_mut(rows_per_thread * width * 4).enumerate() {
+                let bvh = &bvh;
+                scope.spawn(move || {
+                    let mut rng = rand::thread_rng();
+                    for (i, pixel) in rows.chunks_exact_mut(4).enumerate() {
+                        let x = i % width;
+                        let y = chunk * rows_per_thread + i / width;
+                        let ao = self.trace_pixel(bvh, camera, x, y, width, height, &mut rng);
+                        let value = (ao * 255.0).round() as u8;
+                        pixel.copy_from_slice(&[value, value, value, 255]);
+                    }
+                });
+            }
+        });
+
+        rm.update_texture(self.texture, &pixels);
+        println!(
+            "Reference AO: {} triangles, {}x{}, {} samples in {:.2}s",
+            bvh.triangles.len(),
+            width,
+            height,
+            self.samples,
+            start.elapsed().as_secs_f32()
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn trace_pixel(
+        &self,
+        bvh: &Bvh,
+        camera: &SceneUniformData,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        rng: &mut ThreadRng,
+    ) -> f32 {
+        let uv = glam::vec2(
+            (x as f32 + 0.5) / width as f32,
+            (y as f32 + 0.5) / height as f32,
+        );
+        // Any depth inside the frustum is on the ray, whichever way depth is mapped
+        let target = camera
+            .inverse_view
+            .transform_point3(view_position_from_depth(
+                uv,
+                0.5,
+                camera.inverse_perspective,
+            ));
+        let origin = camera.camera_position;
+        let direction = (target - origin).normalize();
+
+        // Background is unoccluded, same as the SSAO clear color
+        let Some((distance, triangle)) = bvh.cast(origin, direction, f32::MAX) else {
+            return 1.0;
+        };
+
+        let triangle = bvh.triangle(triangle);
+        let mut normal = (triangle[1] - triangle[0])
+            .cross(triangle[2] - triangle[0])
+            .normalize();
+        if normal.dot(direction) > 0.0 {
+            normal = -normal;
+        }
+        // Nudged off the surface so the rays don't hit the triangle they start on
+        let origin = origin + direction * distance + normal * 1e-4;
+
+        let tangent = normal.any_orthonormal_vector();
+        let bitangent = normal.cross(tangent);
+
+        let mut occluded = 0;
+        for _ in 0..self.samples {
+            // Cosine weighted, so the visible fraction is the AO directly
+            let r = rng.gen_range(0.0f32..1.0).sqrt();
+            let phi = rng.gen_range(0.0..std::f32::consts::TAU);
+            let z = (1.0 - r * r).sqrt();
+            let sample = tangent * r * phi.cos() + bitangent * r * phi.sin() + normal * z;
+
+            if bvh.cast(origin, sample, self.radius).is_some() {
+                occluded += 1;
+            }
+        }
+
+        1.0 - occluded as f32 / self.samples as f32
+    }
+
+    fn read_triangles(rm: &ResourceManager, scene: &Scene) -> Vec<[Vec3; 3]> {
+        let mut triangles = vec![];
+
+        for mesh in &scene.meshes {
+            let model = mesh.uniforms.model;
+
+            let positions = rm
+                .read_buffer(mesh.vertex_buffer)
+                .chunks_exact(std::mem::size_of::<VertexAttributes>())
+                .map(|vertex| {
+                    let position =
+                        bytemuck::pod_read_unaligned::<VertexAttributes>(vertex).position;
+                    model.transform_point3(Vec3::from_array(position))
+                })
+                .collect::<Vec<_>>();
+
+            let index_data = rm.read_buffer(mesh.index_buffer);
+            let indices: Vec<usize> = match mesh.index_format {
+                IndexFormat::Uint16 => index_data
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+                    .collect(),
+                IndexFormat::Uint32 => index_data
+                    .chunks_exact(4)
+                    .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+                    .collect(),
+            };
+
+            // The u16 path pads to an even count, so only take the drawn indices
+            for triangle in indices[..mesh.index_count as usize].chunks_exact(3) {
+                triangles.push([
+                    positions[triangle[0]],
+                    positions[triangle[1]],
+                    positions[triangle[2]],
+                ]);
+            }
+        }
+
+        triangles
+    }
+
+    pub fn pass(&self, rm: &ResourceManager, encoder: &mut CommandEncoder, view: &TextureView) {
+        self.view.pass(rm, encoder, view);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The 12 triangles of the axis-aligned box from -1 to 1.
+    fn unit_box() -> Vec<[Vec3; 3]> {
+        let corner = |i: usize| {
+            Vec3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            )
+        };
+        let faces = [
+            [0, 2, 6, 4],
+            [1, 5, 7, 3],
+            [0, 4, 5, 1],
+            [2, 3, 7, 6],
+            [0, 1, 3, 2],
+            [4, 6, 7, 5],
+        ];
+        faces
+            .iter()
+            .flat_map(|[a, b, c, d]| {
+                [
+                    [corner(*a), corner(*b), corner(*c)],
+                    [corner(*a), corner(*c), corner(*d)],
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cast_from_inside_hits_the_walls() {
+        let bvh = Bvh::new(unit_box());
+
+        let (distance, _) = bvh.cast(Vec3::ZERO, Vec3::X, 10.0).unwrap();
+        assert!((distance - 1.0).abs() < 1e-5);
+
+        let direction = Vec3::new(1.0, 0.5, 0.0).normalize();
+        let (distance, triangle) = bvh.cast(Vec3::ZERO, direction, 10.0).unwrap();
+        assert!((distance - 1.25f32.sqrt()).abs() < 1e-5);
+        assert!(bvh.triangle(triangle).iter().all(|vertex| vertex.x == 1.0));
+
+        let (distance, _) = bvh.cast(Vec3::new(0.0, 0.0, 0.5), -Vec3::Z, 10.0).unwrap();
+        assert!((distance - 1.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cast_stops_at_max_distance() {
+        let bvh = Bvh::new(unit_box());
+        assert!(bvh.cast(Vec3::ZERO, Vec3::Y, 0.5).is_none());
+    }
+
+    #[test]
+    fn cast_away_from_outside_misses() {
+        let bvh = Bvh::new(unit_box());
+        assert!(bvh.cast(Vec3::new(3.0, 0.0, 0.0), Vec3::X, 10.0).is_none());
+
+        let (distance, _) = bvh.cast(Vec3::new(3.0, 0.0, 0.0), -Vec3::X, 10.0).unwrap();
+        assert!((distance - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn empty_bvh_never_hits() {
+        assert!(Bvh::new(vec![]).cast(Vec3::ZERO, Vec3::X, 10.0).is_none());
+    }
+}
@@ -1,23 +1,163 @@
-use wgpu::{vertex_attr_array, ShaderStages, VertexAttribute};
-use winit::event::WindowEvent;
+use std::{path::Path, time::Duration};
+
+use glam::Mat4;
+use serde::{Deserialize, Serialize};
+use wgpu::{ShaderStages, TextureSampleType, VertexAttribute};
+use winit::{
+    dpi::PhysicalSize,
+    event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
+};
 
 use crate::{
-    camera::{Camera, CameraController, FlyCamera},
+    ao_technique::AoTechnique,
+    camera::{Camera, CameraController, CameraState, FlyCamera},
     crytek_ssao::CrytekSSAO,
+    frame_timer::FrameTimer,
+    gbuffer_view::GBufferView,
+    gpu_profiler::GpuProfiler,
+    light::DirectionalLight,
+    overdraw_view::OverdrawView,
+    reconstructed_normals_view::ReconstructedNormalsView,
+    reference_ao::ReferenceAO,
     resource_manager::{
-        BindGroupLayoutDesc, CompareFunction, Handle, ResourceManager, ShaderDesc,
-        ShaderModuleDesc, ShaderPipelineDesc, TextureDesc, TextureFormat, TextureUsages,
-        VertexBufferLayout, DEPTH_FORMAT,
+        far_plane_depth, BindGroupDesc, BindGroupLayoutDesc, BlendComponent, BlendFactor,
+        BlendOperation, BlendState, CompareFunction, Face, Handle, PolygonMode, ResourceError,
+        ResourceManager, ShaderDesc, ShaderModuleDesc, ShaderPipelineDesc, TextureDesc,
+        TextureFormat, TextureUsages, TextureViewDimension, DEPTH_FORMAT, SCENE_COLOR_FORMAT,
     },
-    scene::{Mesh, Scene, SceneUniformData, VertexAttributes},
+    scene::{Mesh, Scene, VertexAttributes},
+    temporal_accumulator::TemporalAccumulator,
     texture_debug_view::TextureDebugView,
+    tone_mapping::ToneMapping,
+    visibility_bitmask_ao::VisibilityBitmaskAO,
     EguiRenderData,
 };
 
+const SCENE_EXTENSIONS: [&str; 3] = ["gltf", "glb", "obj"];
+
 #[derive(PartialEq, Eq)]
 enum DebugView {
     None,
-    DepthBuffer,
+    /// One of `Renderer::debug_targets`
+    Texture(Handle),
+    Overdraw,
+    GBuffer,
+    /// Normals reconstructed from depth, to compare against the normal buffer
+    ReconstructedNormals,
+    /// The last computed ray cast AO
+    ReferenceAO,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AoTechniqueKind {
+    None,
+    Crytek,
+    VisibilityBitmask,
+}
+
+impl AoTechniqueKind {
+    pub const ALL: [AoTechniqueKind; 3] = [
+        AoTechniqueKind::None,
+        AoTechniqueKind::Crytek,
+        AoTechniqueKind::VisibilityBitmask,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            AoTechniqueKind::None => "None",
+            AoTechniqueKind::Crytek => "Crytek SSAO",
+            AoTechniqueKind::VisibilityBitmask => "Visibility bitmask AO",
+        }
+    }
+
+    /// The name used in the settings file and on the command line.
+    pub fn id(self) -> &'static str {
+        match self {
+            AoTechniqueKind::None => "none",
+            AoTechniqueKind::Crytek => "crytek",
+            AoTechniqueKind::VisibilityBitmask => "visibility_bitmask",
+        }
+    }
+
+    /// The technique after this one, wrapping back to the first.
+    fn next(self) -> Self {
+        let index = AoTechniqueKind::ALL
+            .iter()
+            .position(|t| *t == self)
+            .unwrap();
+        AoTechniqueKind::ALL[(index + 1) % AoTechniqueKind::ALL.len()]
+    }
+}
+
+// How long the technique name stays on screen after cycling, in seconds
+const TECHNIQUE_TOAST_DURATION: f32 = 1.5;
+
+/// How the selected technique's AO is combined with the scene color
+#[derive(PartialEq, Eq)]
+enum AoDisplay {
+    Composite,
+    ColorOnly,
+    AoOnly,
+}
+
+/// What a frame draws for the selected technique
+#[derive(Debug, PartialEq, Eq)]
+enum AoPass {
+    /// The technique's own debug output, in place of the scene
+    Debug,
+    /// The AO multiplied into the ambient light
+    Multiply,
+    /// The AO in place of the scene
+    Replace,
+}
+
+impl AoDisplay {
+    /// None when the AO isn't drawn at all.
+    fn pass(&self, shows_debug_output: bool) -> Option<AoPass> {
+        match self {
+            AoDisplay::Composite => Some(AoPass::Multiply),
+            AoDisplay::ColorOnly => None,
+            AoDisplay::AoOnly if shows_debug_output => Some(AoPass::Debug),
+            AoDisplay::AoOnly => Some(AoPass::Replace),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum GeometryOutput {
+    Forward,
+    AoOnly,
+    Gray,
+}
+
+/// What `Renderer::ui` draws besides the scene
+#[derive(Debug, PartialEq, Eq)]
+enum UiLayout {
+    /// Hidden with F1, nothing but the scene
+    SceneOnly,
+    /// Controls in a side panel next to the scene
+    SidePanel,
+    /// Controls in a window over the scene
+    Window,
+}
+
+impl UiLayout {
+    fn new(ui_hidden: bool, embedded_viewport: bool) -> Self {
+        match (ui_hidden, embedded_viewport) {
+            (true, _) => UiLayout::SceneOnly,
+            (false, true) => UiLayout::SidePanel,
+            (false, false) => UiLayout::Window,
+        }
+    }
+}
+
+/// The geometry pass pipelines for one depth convention and sample count
+#[derive(Clone, Copy)]
+struct GeometryShaders {
+    forward: Handle,
+    ao_only: Handle,
+    gray: Handle,
 }
 
 pub struct Renderer {
@@ -25,24 +165,131 @@ pub struct Renderer {
     egui: egui_wgpu::Renderer,
     scene: Scene,
     debug_view: DebugView,
+    // Linear depth range shown from black to white when a depth texture is being viewed
+    depth_debug_range: [f32; 2],
+    geometry_output: GeometryOutput,
 
     camera: Camera,
     camera_controller: Box<dyn CameraController>,
+    light: DirectionalLight,
+
+    // The scene is rendered here, sized to the viewport rather than the window
+    scene_color: Handle,
+    // scene_color after tone mapping, what egui shows and screenshots save
+    display_color: Handle,
+    tone_mapping: ToneMapping,
+    scene_texture_id: egui::TextureId,
+    embedded_viewport: bool,
+    // Toggled with F1, shows only the scene for screenshots
+    ui_hidden: bool,
+    // Frames per second, None renders as fast as presentation allows
+    frame_rate_cap: Option<u32>,
 
     depth_buffer: Handle,
-    depth_buffer_debug: TextureDebugView,
-    shader: Handle,
+    normal_buffer: Handle,
+    // Ambient lighting, which the AO technique darkens before it's added to scene_color
+    ambient_buffer: Handle,
+    ambient_bind_group: Handle,
+    ambient_shader: Handle,
+    albedo_buffer: Handle,
+    // Geometry pass targets after the surface, in the same order as the pipeline targets
+    gbuffer: Vec<Handle>,
+    // Created the first time each target is shown
+    texture_debug_views: Vec<(Handle, TextureDebugView)>,
+    overdraw_view: OverdrawView,
+    gbuffer_view: GBufferView,
+    reconstructed_normals_view: ReconstructedNormalsView,
+    reference_ao: ReferenceAO,
+    // Indexed by [msaa][Camera::reverse_z]
+    geometry_shaders: [[GeometryShaders; 2]; 2],
+    // Same as geometry_shaders but drawing lines, None when unsupported
+    wireframe_shaders: Option<[[GeometryShaders; 2]; 2]>,
+    wireframe: bool,
+    // Redraws single sampled depth after an MSAA main pass, or before the main pass with the
+    // depth prepass. Indexed by `Camera::reverse_z`.
+    depth_only_shader: [Handle; 2],
+    // Tests for equal depth, so either depth convention works
+    prepass_geometry_shaders: GeometryShaders,
+    // Only shades the closest surface, not used with MSAA
+    depth_prepass: bool,
+    // Linear RGB
+    clear_color: [f32; 3],
+    // Otherwise scene_color keeps whatever an earlier pass wrote
+    clear_scene_color: bool,
+
+    msaa: bool,
+    msaa_sample_count: u32,
+    // Multisampled copies of scene_color and the gbuffer, resolved into them
+    msaa_targets: Vec<Handle>,
+    // Multisampled depth can't be read by the AO passes, so depth_buffer is drawn separately
+    msaa_depth_buffer: Handle,
+
+    ao_technique: AoTechniqueKind,
+    // Seconds left showing the technique name after cycling with Tab
+    technique_toast: f32,
+    // Held keys repeat their pressed events, so Tab only cycles on the first one
+    tab_held: bool,
+    ao_display: AoDisplay,
+    // Unlit materials are left alone by AO unless this is set, for debugging
+    occlude_unlit: bool,
+    // Created the first time the technique is selected
+    crytek_ssao: Option<CrytekSSAO>,
+    visibility_bitmask_ao: Option<VisibilityBitmaskAO>,
+    // One per technique, created along with it
+    temporal_accumulators: Vec<(AoTechniqueKind, TemporalAccumulator)>,
+    // The camera last frame was rendered with, for reprojection
+    previous_view_projection: Mat4,
+
+    frustum_culling: bool,
+    // Meshes skipped by culling last frame
+    culled_count: usize,
 
-    crytek_ssao: CrytekSSAO,
+    gpu_profiler: GpuProfiler,
+    frame_timer: FrameTimer,
 }
 
 impl Renderer {
-    pub fn new(mut rm: ResourceManager) -> Self {
-        let scene = Scene::new(&mut rm);
+    pub fn new(mut rm: ResourceManager) -> Result<Self, ResourceError> {
+        let scene = Scene::new(&mut rm)?;
 
         let camera = Camera::default();
         let fly_camera = Box::new(FlyCamera::new());
 
+        let scene_color = rm.create_texture(&TextureDesc {
+            label: Some("Scene color"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: SCENE_COLOR_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC,
+            initial_data: None,
+        });
+
+        let display_color = rm.create_texture(&TextureDesc {
+            label: Some("Display color"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            // Tone mapped into the surface's format, so egui samples it like its own textures
+            format: rm.surface_configuration.format,
+            usage: TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC,
+            initial_data: None,
+        });
+
         let depth_buffer = rm.create_texture(&TextureDesc {
             label: Some("Depth buffer"),
             dimensions: (
@@ -50,12 +297,118 @@ impl Renderer {
                 rm.surface_configuration.height,
             ),
             mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
             format: DEPTH_FORMAT,
             usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
             initial_data: None,
         });
 
-        let shader = rm.create_shader(ShaderDesc {
+        // View-space normals in xyz, w is 1 where geometry was drawn
+        let normal_buffer = rm.create_texture(&TextureDesc {
+            label: Some("Normal buffer"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: TextureFormat::Rgba16Float,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        });
+
+        let ambient_buffer = rm.create_texture(&TextureDesc {
+            label: Some("Ambient buffer"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            // Same as scene_color, so AO techniques can composite into it
+            format: SCENE_COLOR_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        });
+
+        let albedo_buffer = rm.create_texture(&TextureDesc {
+            label: Some("Albedo buffer"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        });
+
+        let gbuffer = vec![normal_buffer, ambient_buffer, albedo_buffer];
+        let targets: Vec<TextureFormat> = std::iter::once(SCENE_COLOR_FORMAT)
+            .chain(
+                gbuffer
+                    .iter()
+                    .map(|target| rm.get_texture(*target).format()),
+            )
+            .collect();
+
+        // 4x is enough to clean up edges, and is supported nearly everywhere
+        let msaa_sample_count = rm
+            .supported_sample_counts(
+                &targets
+                    .iter()
+                    .copied()
+                    .chain(std::iter::once(DEPTH_FORMAT))
+                    .collect::<Vec<_>>(),
+            )
+            .into_iter()
+            .filter(|count| *count <= 4)
+            .max()
+            .unwrap_or(1);
+        let msaa_targets = std::iter::once(scene_color)
+            .chain(gbuffer.iter().copied())
+            .map(|target| {
+                let texture = rm.get_texture(target);
+                let size = texture.size();
+                let format = texture.format();
+                rm.create_texture(&TextureDesc {
+                    label: Some("MSAA target"),
+                    dimensions: (size.width, size.height),
+                    mipmaps: None,
+                    sample_count: msaa_sample_count,
+                    layers: 1,
+                    view_dimension: TextureViewDimension::D2,
+                    format,
+                    usage: TextureUsages::RENDER_ATTACHMENT,
+                    initial_data: None,
+                })
+            })
+            .collect();
+        let msaa_depth_buffer = rm.create_texture(&TextureDesc {
+            label: Some("MSAA depth buffer"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: msaa_sample_count,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            initial_data: None,
+        });
+
+        let shader_desc = ShaderDesc {
             label: None,
             vs: ShaderModuleDesc {
                 path: String::from("src/shaders/debug_draw.wgsl"),
@@ -66,90 +419,822 @@ impl Renderer {
                 entry_func: String::from("fs_main"),
             }),
             bind_group_layouts: vec![
-                BindGroupLayoutDesc {
-                    label: None,
-                    visibility: ShaderStages::VERTEX_FRAGMENT,
-                    buffers: vec![std::mem::size_of::<SceneUniformData>()],
-                    textures: vec![],
-                    samplers: vec![],
-                },
+                Scene::scene_bind_group_layout(),
                 Mesh::bind_group_layout(),
+                Mesh::material_bind_group_layout(),
             ],
+            push_constant_ranges: vec![],
             pipeline_state: ShaderPipelineDesc {
-                depth_test: Some(CompareFunction::Less),
-                targets: vec![TextureFormat::Bgra8UnormSrgb],
-                vertex_buffer_bindings: vec![VertexBufferLayout {
-                    array_stride: std::mem::size_of::<VertexAttributes>() as u64,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: Vec::from(vertex_attr_array![0 => Float32x3, 1=>Float32x3]),
-                }],
+                // Replaced per depth convention below
+                depth_test: None,
+                targets,
+                // Both loaders flip z and the camera is left-handed, so glTF's CCW winding holds
+                cull_mode: Some(Face::Back),
+                vertex_buffer_bindings: vec![VertexAttributes::vertex_buffer_layout()],
+                ..Default::default()
             },
-        });
+        };
+        let supports_wireframe = rm.supports_wireframe();
+        let mut create_geometry_shaders =
+            |depth_compare, sample_count, polygon_mode| -> Result<_, ResourceError> {
+                let mut shader_desc = shader_desc.clone();
+                shader_desc.pipeline_state.depth_test = Some(depth_compare);
+                shader_desc.pipeline_state.sample_count = sample_count;
+                // Softens alpha masked edges, see alpha_coverage in debug_draw.wgsl
+                shader_desc.pipeline_state.alpha_to_coverage = sample_count > 1;
+                shader_desc.pipeline_state.polygon_mode = polygon_mode;
+
+                let shader = rm.create_shader(shader_desc.clone())?;
+
+                // Same geometry pass, but with pure white albedo so the image is lighting x AO only
+                let ao_only_shader = rm.create_shader(ShaderDesc {
+                    label: Some(String::from("AO only shader")),
+                    ps: Some(ShaderModuleDesc {
+                        path: String::from("src/shaders/debug_draw.wgsl"),
+                        entry_func: String::from("fs_ao_only"),
+                    }),
+                    ..shader_desc.clone()
+                })?;
+
+                // Neutral gray instead of the material colors
+                let gray_shader = rm.create_shader(ShaderDesc {
+                    label: Some(String::from("Gray shader")),
+                    ps: Some(ShaderModuleDesc {
+                        path: String::from("src/shaders/debug_draw.wgsl"),
+                        entry_func: String::from("fs_gray"),
+                    }),
+                    ..shader_desc
+                })?;
+
+                Ok(GeometryShaders {
+                    forward: shader,
+                    ao_only: ao_only_shader,
+                    gray: gray_shader,
+                })
+            };
+        // Equal doesn't depend on the Z convention
+        let prepass_geometry_shaders =
+            create_geometry_shaders(geometry_depth_compare(true, false), 1, PolygonMode::Fill)?;
+        let mut create_geometry_shader_set = |polygon_mode| -> Result<_, ResourceError> {
+            Ok([
+                [
+                    create_geometry_shaders(geometry_depth_compare(false, false), 1, polygon_mode)?,
+                    create_geometry_shaders(geometry_depth_compare(false, true), 1, polygon_mode)?,
+                ],
+                [
+                    create_geometry_shaders(
+                        geometry_depth_compare(false, false),
+                        msaa_sample_count,
+                        polygon_mode,
+                    )?,
+                    create_geometry_shaders(
+                        geometry_depth_compare(false, true),
+                        msaa_sample_count,
+                        polygon_mode,
+                    )?,
+                ],
+            ])
+        };
+        let geometry_shaders = create_geometry_shader_set(PolygonMode::Fill)?;
+        let wireframe_shaders = if supports_wireframe {
+            Some(create_geometry_shader_set(PolygonMode::Line)?)
+        } else {
+            None
+        };
+
+        let mut create_depth_only_shader = |depth_compare| {
+            rm.create_shader(ShaderDesc {
+                label: Some(String::from("Depth only shader")),
+                // Only discards alpha masked fragments, so they don't write depth
+                ps: Some(ShaderModuleDesc {
+                    path: String::from("src/shaders/debug_draw.wgsl"),
+                    entry_func: String::from("fs_alpha_test"),
+                }),
+                pipeline_state: ShaderPipelineDesc {
+                    depth_test: Some(depth_compare),
+                    targets: vec![],
+                    ..shader_desc.pipeline_state.clone()
+                },
+                ..shader_desc.clone()
+            })
+        };
+        let depth_only_shader = [
+            create_depth_only_shader(CompareFunction::Less)?,
+            create_depth_only_shader(CompareFunction::Greater)?,
+        ];
+
+        let ambient_bind_group_layout = BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![],
+            textures: vec![(
+                TextureSampleType::Float { filterable: true },
+                TextureViewDimension::D2,
+            )],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        };
+        let ambient_bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: Some("Ambient bind group"),
+            visibility: ShaderStages::FRAGMENT,
+            layout: ambient_bind_group_layout.clone(),
+            buffers: &[],
+            textures: &[ambient_buffer],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+        let ambient_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Add ambient shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/add_ambient.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/add_ambient.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![ambient_bind_group_layout],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![SCENE_COLOR_FORMAT],
+                blends: vec![Some(BlendState {
+                    color: BlendComponent {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                    alpha: BlendComponent::REPLACE,
+                })],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
 
-        let depth_buffer_debug = TextureDebugView::new(&mut rm, depth_buffer);
+        let overdraw_view = OverdrawView::new(&mut rm)?;
+        let gbuffer_view = GBufferView::new(&mut rm, normal_buffer, depth_buffer)?;
+        let reconstructed_normals_view = ReconstructedNormalsView::new(&mut rm, depth_buffer)?;
+        let reference_ao = ReferenceAO::new(&mut rm)?;
+        let tone_mapping = ToneMapping::new(&mut rm, scene_color)?;
 
-        let egui = egui_wgpu::renderer::Renderer::new(
+        let mut egui = egui_wgpu::renderer::Renderer::new(
             &rm.device,
             rm.surface_configuration.format,
             None,
             1,
         );
+        let scene_texture_id = egui.register_native_texture(
+            &rm.device,
+            rm.get_texture(display_color).view(),
+            wgpu::FilterMode::Linear,
+        );
 
-        let crytek_ssao = CrytekSSAO::new(&mut rm, depth_buffer);
+        let gpu_profiler = GpuProfiler::new(&rm);
 
-        Self {
+        Ok(Self {
             scene,
             rm,
+            scene_color,
+            display_color,
+            tone_mapping,
+            scene_texture_id,
+            embedded_viewport: false,
+            ui_hidden: false,
+            frame_rate_cap: None,
             depth_buffer,
-            depth_buffer_debug,
+            normal_buffer,
+            ambient_buffer,
+            ambient_bind_group,
+            ambient_shader,
+            albedo_buffer,
+            gbuffer,
+            texture_debug_views: vec![],
+            overdraw_view,
+            gbuffer_view,
+            reconstructed_normals_view,
+            reference_ao,
             debug_view: DebugView::None,
-            shader,
+            depth_debug_range: [0.0, 1.0],
+            geometry_output: GeometryOutput::Forward,
+            geometry_shaders,
+            depth_only_shader,
+            wireframe_shaders,
+            wireframe: false,
+            prepass_geometry_shaders,
+            depth_prepass: false,
+            clear_color: [0.0, 0.0, 1.0],
+            clear_scene_color: true,
+            msaa: false,
+            msaa_sample_count,
+            msaa_targets,
+            msaa_depth_buffer,
             egui,
             camera,
             camera_controller: fly_camera,
-            crytek_ssao,
-        }
+            light: DirectionalLight::default(),
+            ao_technique: AoTechniqueKind::None,
+            technique_toast: 0.0,
+            tab_held: false,
+            ao_display: AoDisplay::Composite,
+            occlude_unlit: false,
+            crytek_ssao: None,
+            visibility_bitmask_ao: None,
+            temporal_accumulators: vec![],
+            previous_view_projection: Mat4::IDENTITY,
+            frustum_culling: true,
+            culled_count: 0,
+            gpu_profiler,
+            frame_timer: FrameTimer::new(),
+        })
     }
 
     pub fn ui(&mut self, ctx: &egui::Context) {
-        egui::Window::new("Renderer").show(ctx, |ui| {
-            egui::CollapsingHeader::new("Resources").show(ui, |ui| {
-                self.rm.egui(ui);
+        match UiLayout::new(self.ui_hidden, self.embedded_viewport) {
+            UiLayout::SceneOnly => {
+                let painter = ctx.layer_painter(egui::LayerId::background());
+                self.viewport(ctx, &painter, ctx.screen_rect());
+                return;
+            }
+            UiLayout::SidePanel => {
+                egui::SidePanel::left("Renderer").show(ctx, |ui| {
+                    egui::ScrollArea::vertical().show(ui, |ui| self.controls(ui));
+                });
+                egui::CentralPanel::default()
+                    .frame(egui::Frame::none())
+                    .show(ctx, |ui| {
+                        let rect = ui.available_rect_before_wrap();
+                        self.viewport(ctx, &ui.painter_at(rect), rect);
+                    });
+            }
+            UiLayout::Window => {
+                let painter = ctx.layer_painter(egui::LayerId::background());
+                self.viewport(ctx, &painter, ctx.screen_rect());
+                egui::Window::new("Renderer").show(ctx, |ui| self.controls(ui));
+            }
+        }
+
+        if self.technique_toast > 0.0 {
+            egui::Area::new("Technique toast")
+                .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 16.0))
+                .interactable(false)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.heading(self.ao_technique.name());
+                    });
+                });
+        }
+    }
+
+    /// Shows the scene in `rect`, resizing the offscreen targets to match it.
+    fn viewport(&mut self, ctx: &egui::Context, painter: &egui::Painter, rect: egui::Rect) {
+        let pixels_per_point = ctx.pixels_per_point();
+        self.resize_viewport((
+            (rect.width() * pixels_per_point) as u32,
+            (rect.height() * pixels_per_point) as u32,
+        ));
+
+        painter.image(
+            self.scene_texture_id,
+            rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            egui::Color32::WHITE,
+        );
+
+        if self.debug_view == DebugView::GBuffer && !self.ui_hidden {
+            self.gbuffer_view.labels(painter, rect);
+        }
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.embedded_viewport, "Embedded viewport");
+
+        let mut limit_frame_rate = self.frame_rate_cap.is_some();
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut limit_frame_rate, "Frame rate cap");
+            let mut cap = self.frame_rate_cap.unwrap_or(60);
+            ui.add_enabled(
+                limit_frame_rate,
+                egui::DragValue::new(&mut cap)
+                    .clamp_range(1..=480)
+                    .suffix(" fps"),
+            );
+            self.frame_rate_cap = limit_frame_rate.then_some(cap);
+        });
+
+        let mut present_mode = self.rm.surface_configuration.present_mode;
+        egui::ComboBox::from_label("Present mode")
+            .selected_text(format!("{:?}", present_mode))
+            .show_ui(ui, |ui| {
+                for mode in self.rm.present_modes() {
+                    ui.selectable_value(&mut present_mode, *mode, format!("{:?}", mode));
+                }
             });
+        if present_mode != self.rm.surface_configuration.present_mode {
+            self.rm.set_present_mode(present_mode);
+        }
 
-            egui::CollapsingHeader::new("Loader").show(ui, |ui| {
-                if ui.button("Load glTF").clicked() {
+        egui::CollapsingHeader::new("Resources").show(ui, |ui| {
+            self.rm.egui(ui);
+        });
+
+        egui::CollapsingHeader::new("Loader").show(ui, |ui| {
+            if ui.button("Load scene").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("Scene", &SCENE_EXTENSIONS)
+                    .add_filter("glTF", &["gltf", "glb"])
+                    .add_filter("OBJ", &["obj"])
+                    .pick_file()
+                {
+                    self.load_scene(&path);
+                }
+            }
+            if ui.button("Frame scene").clicked() {
+                if let Some(bounds) = self.scene.bounds {
+                    self.camera.frame(bounds.center(), bounds.bounding_radius());
+                }
+            }
+            if ui.button("Append glTF").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("glTF", &["gltf", "glb"])
+                    .pick_file()
+                {
+                    if let Err(err) = self.scene.append_gltf(&mut self.rm, &path, Mat4::IDENTITY) {
+                        println!("Couldn't append {}: {}", path.display(), err);
+                    }
+                }
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Save camera…").clicked() {
                     if let Some(path) = rfd::FileDialog::new()
-                        .add_filter("glTF", &["gltf", "glb"])
+                        .add_filter("JSON", &["json"])
+                        .save_file()
+                    {
+                        self.save_camera(&path);
+                    }
+                }
+                if ui.button("Load camera…").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("JSON", &["json"])
                         .pick_file()
                     {
-                        self.scene =
-                            Scene::load_gltf(&mut self.rm, &String::from(path.to_str().unwrap()));
+                        self.load_camera(&path);
                     }
                 }
             });
+        });
+
+        self.frame_timer.ui(ui);
+        self.gpu_profiler.ui(ui);
 
-            self.camera_controller.ui(&mut self.camera, ui);
+        self.camera_controller.ui(&mut self.camera, ui);
+        self.light.ui(ui);
+        self.tone_mapping.ui(ui);
 
-            egui::CollapsingHeader::new("Debug views").show(ui, |ui| {
-                ui.selectable_value(&mut self.debug_view, DebugView::None, "None");
-                ui.selectable_value(&mut self.debug_view, DebugView::DepthBuffer, "Depth buffer");
+        egui::CollapsingHeader::new("Geometry output").show(ui, |ui| {
+            ui.selectable_value(
+                &mut self.geometry_output,
+                GeometryOutput::Forward,
+                "Forward",
+            );
+            ui.selectable_value(
+                &mut self.geometry_output,
+                GeometryOutput::AoOnly,
+                "AO only (white albedo)",
+            );
+            ui.selectable_value(
+                &mut self.geometry_output,
+                GeometryOutput::Gray,
+                "Uniform gray",
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.clear_scene_color, "Clear color");
+            ui.add_enabled_ui(self.clear_scene_color, |ui| {
+                ui.color_edit_button_rgb(&mut self.clear_color);
             });
         });
+        ui.checkbox(&mut self.camera.reverse_z, "Reversed Z");
+        ui.add_enabled(
+            self.wireframe_shaders.is_some(),
+            egui::Checkbox::new(&mut self.wireframe, "Wireframe"),
+        );
+        ui.add_enabled(
+            !self.msaa && !self.wireframe,
+            egui::Checkbox::new(&mut self.depth_prepass, "Depth prepass"),
+        );
+        ui.add_enabled(
+            self.msaa_sample_count > 1,
+            egui::Checkbox::new(
+                &mut self.msaa,
+                format!("MSAA ({}x)", self.msaa_sample_count),
+            ),
+        );
+        ui.checkbox(&mut self.frustum_culling, "Frustum culling");
+        ui.label(format!(
+            "{} of {} meshes culled",
+            self.culled_count,
+            self.scene.meshes.len()
+        ));
+        ui.label(format!(
+            "{} bind group layouts shared",
+            self.rm.bind_group_layout_cache_hits()
+        ));
+
+        let debug_targets = self.debug_targets();
+        egui::CollapsingHeader::new("Debug views").show(ui, |ui| {
+            ui.selectable_value(&mut self.debug_view, DebugView::None, "None");
+            for (name, texture) in debug_targets {
+                ui.selectable_value(&mut self.debug_view, DebugView::Texture(texture), name);
+            }
+            ui.selectable_value(&mut self.debug_view, DebugView::Overdraw, "Overdraw");
+            ui.selectable_value(&mut self.debug_view, DebugView::GBuffer, "G-buffer (G)");
+            ui.selectable_value(
+                &mut self.debug_view,
+                DebugView::ReconstructedNormals,
+                "Reconstructed normals",
+            );
+            ui.selectable_value(&mut self.debug_view, DebugView::ReferenceAO, "Reference AO");
+        });
+        if let DebugView::Texture(texture) = self.debug_view {
+            self.create_texture_debug_view(texture);
+            if self.rm.get_texture(texture).depth {
+                let [min, max] = &mut self.depth_debug_range;
+                ui.add(egui::Slider::new(min, 0.0..=1.0).text("Depth range min"));
+                ui.add(egui::Slider::new(max, 0.0..=1.0).text("Depth range max"));
+                *max = max.max(*min);
+            }
+        }
+
+        egui::CollapsingHeader::new("Reference AO").show(ui, |ui| {
+            self.reference_ao.ui(ui);
+            if ui.button("Compute (slow)").clicked() {
+                self.reference_ao
+                    .compute(&self.rm, &self.scene, &self.camera.build_uniforms());
+                self.debug_view = DebugView::ReferenceAO;
+            }
+        });
+
+        let mut technique = self.ao_technique;
+        egui::ComboBox::from_label("AO technique (Tab)")
+            .selected_text(technique.name())
+            .show_ui(ui, |ui| {
+                for option in AoTechniqueKind::ALL {
+                    ui.selectable_value(&mut technique, option, option.name());
+                }
+            });
+        if technique != self.ao_technique {
+            self.select_ao_technique(technique);
+        }
+
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.ao_display, AoDisplay::Composite, "Composite");
+            ui.selectable_value(&mut self.ao_display, AoDisplay::ColorOnly, "Color only");
+            ui.selectable_value(&mut self.ao_display, AoDisplay::AoOnly, "AO only");
+        });
+        ui.checkbox(&mut self.occlude_unlit, "Apply AO to unlit materials");
+
+        let technique: Option<&mut dyn AoTechnique> = match self.ao_technique {
+            AoTechniqueKind::None => None,
+            AoTechniqueKind::Crytek => self.crytek_ssao.as_mut().map(|t| t as _),
+            AoTechniqueKind::VisibilityBitmask => {
+                self.visibility_bitmask_ao.as_mut().map(|t| t as _)
+            }
+        };
+        if let Some(technique) = technique {
+            egui::CollapsingHeader::new(technique.name()).show(ui, |ui| {
+                technique.ui(&mut self.rm, ui);
+            });
+        }
+
+        if let Some((_, temporal_accumulator)) = self
+            .temporal_accumulators
+            .iter_mut()
+            .find(|(technique, _)| *technique == self.ao_technique)
+        {
+            egui::CollapsingHeader::new("Temporal accumulation").show(ui, |ui| {
+                temporal_accumulator.ui(ui);
+            });
+        }
+    }
+
+    /// Textures that can be shown with a `TextureDebugView`, including the active technique's.
+    fn debug_targets(&self) -> Vec<(&'static str, Handle)> {
+        let mut targets = vec![
+            ("Depth buffer", self.depth_buffer),
+            ("Normals", self.normal_buffer),
+            ("Albedo", self.albedo_buffer),
+        ];
+        let technique: Option<&dyn AoTechnique> = match self.ao_technique {
+            AoTechniqueKind::None => None,
+            AoTechniqueKind::Crytek => self.crytek_ssao.as_ref().map(|t| t as _),
+            AoTechniqueKind::VisibilityBitmask => {
+                self.visibility_bitmask_ao.as_ref().map(|t| t as _)
+            }
+        };
+        if let Some(technique) = technique {
+            targets.extend(technique.debug_targets());
+        }
+        targets
+    }
+
+    fn create_texture_debug_view(&mut self, texture: Handle) {
+        if self.texture_debug_views.iter().any(|(t, _)| *t == texture) {
+            return;
+        }
+
+        match TextureDebugView::new(&mut self.rm, texture) {
+            Ok(view) => self.texture_debug_views.push((texture, view)),
+            Err(err) => {
+                println!("Couldn't create texture debug view: {}", err);
+                self.debug_view = DebugView::None;
+            }
+        }
+    }
+
+    /// Sizes a newly created technique to the viewport and gives it a temporal accumulator.
+    fn prepare_technique(&mut self, kind: AoTechniqueKind, technique: &dyn AoTechnique) {
+        // Created at the surface size, which the viewport might not match
+        let size = self.rm.get_texture(self.scene_color).size();
+        technique.resize(&mut self.rm, (size.width, size.height));
+
+        match TemporalAccumulator::new(&mut self.rm, technique.ao_texture(), self.depth_buffer) {
+            Ok(temporal_accumulator) => self
+                .temporal_accumulators
+                .push((kind, temporal_accumulator)),
+            Err(err) => println!("Couldn't create temporal accumulation: {}", err),
+        }
+    }
+
+    /// Switches techniques, creating the technique's resources if it hasn't been used yet.
+    pub fn select_ao_technique(&mut self, technique: AoTechniqueKind) {
+        if technique == AoTechniqueKind::Crytek && self.crytek_ssao.is_none() {
+            match CrytekSSAO::new(&mut self.rm, self.depth_buffer, self.albedo_buffer) {
+                Ok(crytek_ssao) => {
+                    self.prepare_technique(technique, &crytek_ssao);
+                    self.crytek_ssao = Some(crytek_ssao);
+                }
+                Err(err) => {
+                    println!("Couldn't create Crytek SSAO: {}", err);
+                    return;
+                }
+            }
+        }
+        if technique == AoTechniqueKind::VisibilityBitmask && self.visibility_bitmask_ao.is_none() {
+            match VisibilityBitmaskAO::new(
+                &mut self.rm,
+                self.depth_buffer,
+                self.normal_buffer,
+                self.albedo_buffer,
+            ) {
+                Ok(visibility_bitmask_ao) => {
+                    self.prepare_technique(technique, &visibility_bitmask_ao);
+                    self.visibility_bitmask_ao = Some(visibility_bitmask_ao);
+                }
+                Err(err) => {
+                    println!("Couldn't create visibility bitmask AO: {}", err);
+                    return;
+                }
+            }
+        }
+
+        self.ao_technique = technique;
+
+        let technique: Option<&dyn AoTechnique> = match self.ao_technique {
+            AoTechniqueKind::None => None,
+            AoTechniqueKind::Crytek => self.crytek_ssao.as_ref().map(|t| t as _),
+            AoTechniqueKind::VisibilityBitmask => {
+                self.visibility_bitmask_ao.as_ref().map(|t| t as _)
+            }
+        };
+        let ao_texture = technique.map(|technique| technique.ao_texture());
+        if let Err(err) = self.gbuffer_view.set_ao_texture(&mut self.rm, ao_texture) {
+            println!("Couldn't show AO in the G-buffer view: {}", err);
+        }
+
+        // The previous technique's targets aren't rendered anymore
+        if let DebugView::Texture(texture) = self.debug_view {
+            if !self.debug_targets().iter().any(|(_, t)| *t == texture) {
+                self.debug_view = DebugView::None;
+            }
+        }
+    }
+
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        // Minimized windows report a zero size, which the surface can't be configured with
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+
+        self.rm.surface_configuration.width = new_size.width;
+        self.rm.surface_configuration.height = new_size.height;
+        self.rm.configure_surface();
+    }
+
+    /// Resizes everything the scene is rendered to, a no-op if the size hasn't changed.
+    fn resize_viewport(&mut self, dimensions: (u32, u32)) {
+        let size = self.rm.get_texture(self.scene_color).size();
+        if dimensions.0 == 0 || dimensions.1 == 0 || (size.width, size.height) == dimensions {
+            return;
+        }
+
+        self.rm.resize_texture(self.scene_color, dimensions);
+        self.rm.resize_texture(self.display_color, dimensions);
+        self.egui.update_egui_texture_from_wgpu_texture(
+            &self.rm.device,
+            self.rm.get_texture(self.display_color).view(),
+            wgpu::FilterMode::Linear,
+            self.scene_texture_id,
+        );
+
+        self.rm.resize_texture(self.depth_buffer, dimensions);
+        for target in &self.gbuffer {
+            self.rm.resize_texture(*target, dimensions);
+        }
+        for target in &self.msaa_targets {
+            self.rm.resize_texture(*target, dimensions);
+        }
+        self.rm.resize_texture(self.msaa_depth_buffer, dimensions);
+        self.overdraw_view.resize(&mut self.rm, dimensions);
+        self.gbuffer_view.resize(&mut self.rm, dimensions);
+        self.reference_ao.resize(&mut self.rm, dimensions);
+        if let Some(crytek_ssao) = &self.crytek_ssao {
+            crytek_ssao.resize(&mut self.rm, dimensions);
+        }
+        if let Some(visibility_bitmask_ao) = &self.visibility_bitmask_ao {
+            visibility_bitmask_ao.resize(&mut self.rm, dimensions);
+        }
+        for (_, temporal_accumulator) in &mut self.temporal_accumulators {
+            temporal_accumulator.resize(&mut self.rm, dimensions);
+        }
+        self.camera
+            .set_aspect_ratio(dimensions.0 as f32 / dimensions.1 as f32);
+    }
+
+    /// Minimum time between frames, if the frame rate is capped.
+    pub fn frame_interval(&self) -> Option<Duration> {
+        self.frame_rate_cap
+            .map(|cap| Duration::from_secs_f32(1.0 / cap as f32))
+    }
+
+    /// Saves the tone mapped scene, without the UI, as a PNG.
+    pub fn capture_screenshot(&mut self, path: &Path) -> image::ImageResult<()> {
+        let texture = self.rm.get_texture(self.display_color);
+        let size = texture.size();
+        let format = texture.format();
+        let swap_red_blue = match format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            _ => {
+                return Err(image::ImageError::Parameter(
+                    image::error::ParameterError::from_kind(
+                        image::error::ParameterErrorKind::Generic(format!(
+                            "can't convert {:?} to RGBA8",
+                            format
+                        )),
+                    ),
+                ))
+            }
+        };
+
+        let mut pixels = self.rm.read_texture(self.display_color);
+        if swap_red_blue {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        image::save_buffer(
+            path,
+            &pixels,
+            size.width,
+            size.height,
+            image::ColorType::Rgba8,
+        )
+    }
+
+    /// Keeps the current scene if the file can't be loaded.
+    pub fn load_scene(&mut self, path: &Path) {
+        match Scene::load(&mut self.rm, path) {
+            Ok(scene) => self.scene = scene,
+            Err(err) => println!("Couldn't load scene from {}: {}", path.display(), err),
+        }
+    }
+
+    pub fn save_camera(&self, path: &Path) {
+        let state = self.camera.state(self.camera_controller.as_ref());
+        let result = serde_json::to_string_pretty(&state)
+            .map_err(|err| err.to_string())
+            .and_then(|json| std::fs::write(path, json).map_err(|err| err.to_string()));
+
+        if let Err(err) = result {
+            println!("Couldn't save camera to {}: {}", path.display(), err);
+        }
+    }
+
+    /// Keeps the current camera if the file can't be read.
+    pub fn load_camera(&mut self, path: &Path) {
+        let result = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| {
+                serde_json::from_str::<CameraState>(&json).map_err(|err| err.to_string())
+            });
+
+        match result {
+            Ok(state) => self.camera.restore(self.camera_controller.as_mut(), &state),
+            Err(err) => println!("Couldn't load camera from {}: {}", path.display(), err),
+        }
     }
 
     pub fn input(&mut self, event: &WindowEvent) {
+        if let WindowEvent::DroppedFile(path) = event {
+            let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+            if SCENE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+                self.load_scene(path);
+            } else {
+                println!("Can't load {}, not a scene file", path.display());
+            }
+        }
+
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::G),
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.debug_view = if self.debug_view == DebugView::GBuffer {
+                DebugView::None
+            } else {
+                DebugView::GBuffer
+            };
+        }
+
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F1),
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.ui_hidden = !self.ui_hidden;
+        }
+
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state,
+                    virtual_keycode: Some(VirtualKeyCode::Tab),
+                    ..
+                },
+            ..
+        } = event
+        {
+            let pressed = *state == ElementState::Pressed;
+            if pressed && !self.tab_held {
+                self.select_ao_technique(self.ao_technique.next());
+                self.technique_toast = TECHNIQUE_TOAST_DURATION;
+            }
+            self.tab_held = pressed;
+        }
+
         self.camera_controller.input(event);
     }
 
-    pub fn update(&mut self, egui_render_data: EguiRenderData) {
-        self.camera_controller.update(&mut self.camera);
-        self.rm.update_buffer(
-            self.scene.scene_uniform_buffer,
-            bytemuck::cast_slice(&[self.camera.build_uniforms()]),
-        );
+    /// `dt` is the time since the previous frame, in seconds.
+    pub fn update(&mut self, egui_render_data: EguiRenderData, dt: f32) {
+        self.frame_timer.record(dt);
+        self.technique_toast = (self.technique_toast - dt).max(0.0);
+        self.rm.poll_reloads();
+
+        self.camera_controller.update(&mut self.camera, dt);
+        let mut scene_uniforms = self.camera.build_uniforms();
+        self.light.apply(&mut scene_uniforms);
+        scene_uniforms.occlude_unlit = self.occlude_unlit as u32;
+
+        if self.frustum_culling {
+            self.culled_count = self.scene.cull(scene_uniforms.view_projection());
+        } else {
+            self.scene.show_all();
+            self.culled_count = 0;
+        }
 
-        let output = self.rm.surface.get_current_texture().unwrap();
+        let output = self
+            .rm
+            .surface
+            .as_ref()
+            .expect("Presenting needs a surface")
+            .get_current_texture()
+            .unwrap();
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -157,53 +1242,268 @@ impl Renderer {
             .rm
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.rm.stage_buffer_update(
+            &mut encoder,
+            self.scene.scene_uniform_buffer,
+            bytemuck::cast_slice(&[scene_uniforms]),
+        );
+
+        let scene_view = self.rm.get_texture(self.scene_color).view();
+
+        // Lines wouldn't pass the equal depth test against filled triangles
+        let depth_prepass = self.depth_prepass && !self.msaa && !self.wireframe;
+        if depth_prepass {
+            self.gpu_profiler.begin_scope(&mut encoder, "Depth prepass");
+            self.depth_pass(&mut encoder);
+            self.gpu_profiler.end_scope(&mut encoder);
+        }
 
+        self.gpu_profiler.begin_scope(&mut encoder, "Main pass");
         {
+            let color_attachments: Vec<Option<wgpu::RenderPassColorAttachment>> =
+                std::iter::once(self.scene_color)
+                    .chain(self.gbuffer.iter().copied())
+                    .enumerate()
+                    .map(|(i, target)| {
+                        let (view, resolve_target) = if self.msaa {
+                            (
+                                self.rm.get_texture(self.msaa_targets[i]).view(),
+                                Some(self.rm.get_texture(target).view()),
+                            )
+                        } else {
+                            (self.rm.get_texture(target).view(), None)
+                        };
+
+                        Some(wgpu::RenderPassColorAttachment {
+                            view,
+                            resolve_target,
+                            ops: wgpu::Operations {
+                                load: if i == 0 {
+                                    scene_color_load_op(
+                                        self.clear_scene_color.then_some(self.clear_color),
+                                    )
+                                } else {
+                                    wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+                                },
+                                // Only the resolved result is read afterwards
+                                store: !self.msaa,
+                            },
+                        })
+                    })
+                    .collect();
+
+            let depth_buffer = if self.msaa {
+                self.msaa_depth_buffer
+            } else {
+                self.depth_buffer
+            };
+            let depth_stencil_attachment = self
+                .rm
+                .get_texture(depth_buffer)
+                .depth_stencil_attachment_with(wgpu::Operations {
+                    load: geometry_depth_load_op(depth_prepass, self.camera.reverse_z),
+                    store: true,
+                });
             let mut draw_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLUE),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: self
-                    .rm
-                    .get_texture(self.depth_buffer)
-                    .depth_stencil_attachment(),
+                color_attachments: &color_attachments,
+                depth_stencil_attachment,
             });
 
-            draw_pass.set_pipeline(self.rm.get_shader(self.shader).pipeline());
-            draw_pass.set_bind_group(
-                0,
-                self.rm.get_bind_group(self.scene.scene_uniform_bind_group),
-                &[],
-            );
+            let shaders = if depth_prepass {
+                self.prepass_geometry_shaders
+            } else {
+                let shader_set = match (self.wireframe, &self.wireframe_shaders) {
+                    (true, Some(wireframe_shaders)) => wireframe_shaders,
+                    _ => &self.geometry_shaders,
+                };
+                shader_set[self.msaa as usize][self.camera.reverse_z as usize]
+            };
+            let shader = match self.geometry_output {
+                GeometryOutput::Forward => shaders.forward,
+                GeometryOutput::AoOnly => shaders.ao_only,
+                GeometryOutput::Gray => shaders.gray,
+            };
+            draw_pass.set_pipeline(self.rm.get_shader(shader).pipeline());
+            self.scene.draw(&self.rm, &mut draw_pass);
+        }
 
-            for mesh in &self.scene.meshes {
-                draw_pass.set_bind_group(1, self.rm.get_bind_group(mesh.bind_group), &[]);
-                draw_pass.set_vertex_buffer(0, self.rm.get_buffer(mesh.vertex_buffer).slice());
-                draw_pass.set_index_buffer(
-                    self.rm.get_buffer(mesh.index_buffer).slice(),
-                    wgpu::IndexFormat::Uint32,
-                );
-                draw_pass.draw_indexed(0..mesh.index_count, 0, 0..1);
+        if self.msaa {
+            self.depth_pass(&mut encoder);
+        }
+        self.gpu_profiler.end_scope(&mut encoder);
+
+        let technique: Option<&dyn AoTechnique> = match self.ao_technique {
+            AoTechniqueKind::None => None,
+            AoTechniqueKind::Crytek => self.crytek_ssao.as_ref().map(|t| t as _),
+            AoTechniqueKind::VisibilityBitmask => {
+                self.visibility_bitmask_ao.as_ref().map(|t| t as _)
             }
+        };
+        let mut add_ambient = true;
+        // AO alone isn't lighting, so it's shown without tone mapping like the debug views
+        let mut raw_output = self.debug_view != DebugView::None;
+        if let Some(technique) = technique {
+            self.gpu_profiler
+                .begin_scope(&mut encoder, technique.name());
+            match self.ao_display.pass(technique.shows_debug_output()) {
+                None => {}
+                Some(AoPass::Debug) => {
+                    technique.debug_pass(&self.rm, &mut encoder, &self.scene, scene_view);
+                    raw_output = true;
+                }
+                Some(pass) => {
+                    technique.occlusion(&self.rm, &mut encoder, &self.scene);
+                    if let Some((_, temporal_accumulator)) = self
+                        .temporal_accumulators
+                        .iter_mut()
+                        .find(|(kind, accumulator)| {
+                            *kind == self.ao_technique && accumulator.enabled
+                        })
+                    {
+                        temporal_accumulator.pass(
+                            &self.rm,
+                            &mut encoder,
+                            &self.scene,
+                            self.previous_view_projection,
+                        );
+                    }
+                    if pass == AoPass::Multiply {
+                        // Only the ambient term is occluded, direct light is left alone
+                        let ambient_view = self.rm.get_texture(self.ambient_buffer).view();
+                        technique.composite(&self.rm, &mut encoder, ambient_view, true);
+                    } else {
+                        technique.composite(&self.rm, &mut encoder, scene_view, false);
+                        add_ambient = false;
+                        raw_output = true;
+                    }
+                }
+            }
+            self.gpu_profiler.end_scope(&mut encoder);
+        }
+
+        if add_ambient {
+            self.gpu_profiler.begin_scope(&mut encoder, "Add ambient");
+            self.ambient_pass(&mut encoder);
+            self.gpu_profiler.end_scope(&mut encoder);
         }
 
         {
             match self.debug_view {
                 DebugView::None => {}
-                DebugView::DepthBuffer => {
-                    self.depth_buffer_debug.pass(&self.rm, &mut encoder, &view)
+                DebugView::Texture(texture) => {
+                    if let Some((_, texture_debug_view)) =
+                        self.texture_debug_views.iter().find(|(t, _)| *t == texture)
+                    {
+                        self.gpu_profiler
+                            .begin_scope(&mut encoder, "Texture debug view");
+                        texture_debug_view.update_depth_uniforms(
+                            &self.rm,
+                            scene_uniforms.z_near,
+                            scene_uniforms.z_far,
+                            self.camera.reverse_z,
+                            self.depth_debug_range,
+                        );
+                        texture_debug_view.pass(&self.rm, &mut encoder, scene_view);
+                        self.gpu_profiler.end_scope(&mut encoder);
+                    }
+                }
+                DebugView::Overdraw => {
+                    self.gpu_profiler
+                        .begin_scope(&mut encoder, "Overdraw debug view");
+                    self.overdraw_view
+                        .pass(&self.rm, &mut encoder, &self.scene, scene_view);
+                    self.gpu_profiler.end_scope(&mut encoder);
+                }
+                DebugView::ReferenceAO => {
+                    self.gpu_profiler.begin_scope(&mut encoder, "Reference AO");
+                    self.reference_ao.pass(&self.rm, &mut encoder, scene_view);
+                    self.gpu_profiler.end_scope(&mut encoder);
+                }
+                DebugView::GBuffer => {
+                    self.gpu_profiler.begin_scope(&mut encoder, "G-buffer view");
+                    self.gbuffer_view.pass(
+                        &self.rm,
+                        &mut encoder,
+                        &self.scene,
+                        self.scene_color,
+                        scene_view,
+                    );
+                    self.gpu_profiler.end_scope(&mut encoder);
+                }
+                DebugView::ReconstructedNormals => {
+                    self.gpu_profiler
+                        .begin_scope(&mut encoder, "Reconstructed normals view");
+                    self.reconstructed_normals_view.pass(
+                        &self.rm,
+                        &mut encoder,
+                        &self.scene,
+                        scene_view,
+                    );
+                    self.gpu_profiler.end_scope(&mut encoder);
                 }
             }
         }
+        self.gpu_profiler.begin_scope(&mut encoder, "Tone mapping");
+        self.tone_mapping.pass(
+            &self.rm,
+            &mut encoder,
+            self.rm.get_texture(self.display_color).view(),
+            raw_output,
+        );
+        self.gpu_profiler.end_scope(&mut encoder);
+
+        self.gpu_profiler.begin_scope(&mut encoder, "Egui");
         self.render_egui(&view, &mut encoder, egui_render_data);
+        self.gpu_profiler.end_scope(&mut encoder);
+
+        self.gpu_profiler.resolve(&mut encoder);
+        self.rm.finish_staging();
         self.rm.queue.submit(std::iter::once(encoder.finish()));
+        self.rm.recall_staging();
+        self.gpu_profiler.read_timings(&self.rm);
         output.present();
+
+        self.previous_view_projection = scene_uniforms.view_projection();
+    }
+
+    /// Adds ambient_buffer onto scene_color.
+    fn ambient_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut ambient_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Add ambient"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.rm.get_texture(self.scene_color).view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        ambient_pass.set_pipeline(self.rm.get_shader(self.ambient_shader).pipeline());
+        ambient_pass.set_bind_group(0, self.rm.get_bind_group(self.ambient_bind_group), &[]);
+        ambient_pass.draw(0..6, 0..1);
+    }
+
+    /// Draws the scene's depth alone into depth_buffer.
+    fn depth_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut depth_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth"),
+            color_attachments: &[],
+            depth_stencil_attachment: self
+                .rm
+                .get_texture(self.depth_buffer)
+                .depth_stencil_attachment(self.camera.reverse_z),
+        });
+
+        depth_pass.set_pipeline(
+            self.rm
+                .get_shader(self.depth_only_shader[self.camera.reverse_z as usize])
+                .pipeline(),
+        );
+        self.scene.draw(&self.rm, &mut depth_pass);
     }
 
     fn render_egui(
@@ -232,7 +1532,7 @@ impl Renderer {
                     view: &view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                         store: true,
                     },
                 })],
@@ -251,3 +1551,155 @@ impl Renderer {
         }
     }
 }
+
+/// Depth test of the geometry pass. After the depth prepass only fragments at the depth it wrote,
+/// the visible ones, are shaded.
+fn geometry_depth_compare(depth_prepass: bool, reverse_z: bool) -> CompareFunction {
+    match (depth_prepass, reverse_z) {
+        (true, _) => CompareFunction::Equal,
+        (false, false) => CompareFunction::Less,
+        (false, true) => CompareFunction::Greater,
+    }
+}
+
+/// Keeps the depth the prepass wrote, otherwise starts from the far plane.
+fn geometry_depth_load_op(depth_prepass: bool, reverse_z: bool) -> wgpu::LoadOp<f32> {
+    if depth_prepass {
+        wgpu::LoadOp::Load
+    } else {
+        wgpu::LoadOp::Clear(far_plane_depth(reverse_z))
+    }
+}
+
+/// Clears to `clear_color` in linear RGB, or loads when there's none.
+fn scene_color_load_op(clear_color: Option<[f32; 3]>) -> wgpu::LoadOp<wgpu::Color> {
+    if let Some(clear_color) = clear_color {
+        let [r, g, b] = clear_color.map(f64::from);
+        wgpu::LoadOp::Clear(wgpu::Color { r, g, b, a: 1.0 })
+    } else {
+        wgpu::LoadOp::Load
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn technique_cycle_wraps_to_the_first() {
+        let last = AoTechniqueKind::ALL[AoTechniqueKind::ALL.len() - 1];
+        assert!(last.next() == AoTechniqueKind::ALL[0]);
+    }
+
+    #[test]
+    fn technique_cycle_visits_every_technique_once() {
+        let mut technique = AoTechniqueKind::ALL[0];
+        for expected in AoTechniqueKind::ALL.iter().skip(1) {
+            technique = technique.next();
+            assert!(technique == *expected);
+        }
+    }
+
+    #[test]
+    fn clear_color_reaches_the_load_op() {
+        assert_eq!(
+            scene_color_load_op(Some([0.5, 0.25, 1.0])),
+            wgpu::LoadOp::Clear(wgpu::Color {
+                r: 0.5,
+                g: 0.25,
+                b: 1.0,
+                a: 1.0
+            })
+        );
+        assert_eq!(scene_color_load_op(None), wgpu::LoadOp::Load);
+    }
+
+    #[test]
+    fn depth_prepass_switches_the_geometry_pass_to_equal() {
+        for reverse_z in [false, true] {
+            assert_eq!(
+                geometry_depth_compare(true, reverse_z),
+                CompareFunction::Equal
+            );
+        }
+        assert_eq!(geometry_depth_compare(false, false), CompareFunction::Less);
+        assert_eq!(
+            geometry_depth_compare(false, true),
+            CompareFunction::Greater
+        );
+    }
+
+    #[test]
+    fn depth_prepass_is_loaded_by_the_geometry_pass() {
+        for reverse_z in [false, true] {
+            assert_eq!(geometry_depth_load_op(true, reverse_z), wgpu::LoadOp::Load);
+        }
+        assert_eq!(
+            geometry_depth_load_op(false, false),
+            wgpu::LoadOp::Clear(1.0)
+        );
+        assert_eq!(
+            geometry_depth_load_op(false, true),
+            wgpu::LoadOp::Clear(0.0)
+        );
+    }
+
+    #[test]
+    fn hidden_ui_only_draws_the_scene() {
+        for embedded_viewport in [false, true] {
+            assert_eq!(UiLayout::new(true, embedded_viewport), UiLayout::SceneOnly);
+        }
+        assert_eq!(UiLayout::new(false, true), UiLayout::SidePanel);
+        assert_eq!(UiLayout::new(false, false), UiLayout::Window);
+    }
+
+    #[test]
+    fn ao_display_picks_the_pass() {
+        for shows_debug_output in [false, true] {
+            assert_eq!(
+                AoDisplay::Composite.pass(shows_debug_output),
+                Some(AoPass::Multiply)
+            );
+            assert_eq!(AoDisplay::ColorOnly.pass(shows_debug_output), None);
+        }
+        // Techniques with their own debug output show it in place of the plain AO
+        assert_eq!(AoDisplay::AoOnly.pass(false), Some(AoPass::Replace));
+        assert_eq!(AoDisplay::AoOnly.pass(true), Some(AoPass::Debug));
+    }
+    #[test]
+    fn selecting_a_technique_creates_it_once() {
+        let Some(rm) = ResourceManager::headless_loading_depth() else {
+            return;
+        };
+        let mut renderer = Renderer::new(rm).unwrap();
+        assert!(renderer.crytek_ssao.is_none());
+        assert!(renderer.visibility_bitmask_ao.is_none());
+
+        renderer.select_ao_technique(AoTechniqueKind::Crytek);
+        assert!(renderer.ao_technique == AoTechniqueKind::Crytek);
+        let ao_texture = renderer.crytek_ssao.as_ref().unwrap().ao_texture();
+        assert!(renderer.visibility_bitmask_ao.is_none());
+
+        renderer.select_ao_technique(AoTechniqueKind::VisibilityBitmask);
+        assert!(renderer.ao_technique == AoTechniqueKind::VisibilityBitmask);
+        assert!(renderer.visibility_bitmask_ao.is_some());
+
+        // Switching back keeps the technique created the first time
+        renderer.select_ao_technique(AoTechniqueKind::Crytek);
+        assert!(renderer.crytek_ssao.as_ref().unwrap().ao_texture() == ao_texture);
+    }
+
+    #[test]
+    fn normal_buffer_is_drawn_to_and_sampled() {
+        let Some(rm) = ResourceManager::headless_loading_depth() else {
+            return;
+        };
+        let renderer = Renderer::new(rm).unwrap();
+
+        let normal_buffer = renderer.rm.get_texture(renderer.normal_buffer);
+        assert_eq!(normal_buffer.format(), TextureFormat::Rgba16Float);
+        assert!(normal_buffer
+            .usage()
+            .contains(TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING));
+    }
+}
@@ -1,11 +1,21 @@
-use std::{borrow::Cow, collections::HashMap, num::NonZeroU64};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    num::NonZeroU64,
+    ops::Range,
+    rc::Rc,
+};
 
 use egui::Color32;
 use pollster::block_on;
+
+use crate::{shader_preprocessor, shader_watcher::ShaderWatcher};
 pub use wgpu::{
-    AddressMode, BufferAddress, BufferSlice, BufferUsages, CompareFunction, FilterMode,
-    SamplerBindingType, ShaderStages, TextureFormat, TextureSampleType, TextureUsages,
-    VertexAttribute, VertexStepMode,
+    AddressMode, BlendComponent, BlendFactor, BlendOperation, BlendState, BufferAddress,
+    BufferSlice, BufferUsages, CompareFunction, Face, FilterMode, FrontFace, IndexFormat,
+    PolygonMode, SamplerBindingType, ShaderStages, StorageTextureAccess, TextureFormat,
+    TextureSampleType, TextureUsages, TextureViewDimension, VertexAttribute, VertexStepMode,
 };
 
 // MARK: Descriptors
@@ -31,8 +41,14 @@ pub struct TextureDesc<'a> {
     pub label: Option<&'a str>,
     pub dimensions: (u32, u32),
     pub mipmaps: Option<u32>,
+    /// Above 1 for multisampled render targets, which can't have mipmaps or initial data
+    pub sample_count: u32,
+    /// 1 for D2 views, 6 for Cube views, any count for D2Array views
+    pub layers: u32,
+    pub view_dimension: TextureViewDimension,
     pub format: TextureFormat,
     pub usage: TextureUsages,
+    /// Layers packed one after another, each covering the whole of mip 0
     pub initial_data: Option<&'a [u8]>,
 }
 
@@ -42,6 +58,9 @@ impl Default for TextureDesc<'_> {
             label: None,
             dimensions: (0, 0),
             mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
             format: TextureFormat::Rgba8UnormSrgb,
             usage: TextureUsages::all(),
             initial_data: None,
@@ -52,9 +71,13 @@ impl Default for TextureDesc<'_> {
 pub struct SamplerDesc<'a> {
     pub label: Option<&'a str>,
     pub address_mode: AddressMode,
-    pub mag_min_filter: FilterMode,
+    pub mag_filter: FilterMode,
+    pub min_filter: FilterMode,
+    pub mipmap_filter: FilterMode,
     pub mipmaps: Option<u32>,
     pub compare: Option<CompareFunction>,
+    /// 1 to 16, only applied when every filter is linear
+    pub anisotropy: u16,
 }
 
 impl Default for SamplerDesc<'_> {
@@ -62,9 +85,47 @@ impl Default for SamplerDesc<'_> {
         SamplerDesc {
             label: None,
             address_mode: AddressMode::Repeat,
-            mag_min_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
             mipmaps: None,
             compare: None,
+            anisotropy: 1,
+        }
+    }
+}
+
+impl<'a> SamplerDesc<'a> {
+    fn descriptor(&self) -> wgpu::SamplerDescriptor<'a> {
+        wgpu::SamplerDescriptor {
+            label: self.label,
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: if let Some(mipmaps) = self.mipmaps {
+                mipmaps as f32
+            } else {
+                0.0
+            },
+            compare: self.compare,
+            anisotropy_clamp: self.anisotropy_clamp(),
+            border_color: None,
+        }
+    }
+
+    fn anisotropy_clamp(&self) -> u16 {
+        // wgpu rejects anisotropy above 1 unless every filter is linear
+        if self.mag_filter == FilterMode::Linear
+            && self.min_filter == FilterMode::Linear
+            && self.mipmap_filter == FilterMode::Linear
+        {
+            self.anisotropy.clamp(1, 16)
+        } else {
+            1
         }
     }
 }
@@ -74,8 +135,15 @@ pub struct BindGroupLayoutDesc {
     pub label: Option<String>,
     pub visibility: ShaderStages,
     pub buffers: Vec<usize>,
-    pub textures: Vec<TextureSampleType>,
+    /// Bound textures must have the same view dimension
+    pub textures: Vec<(TextureSampleType, TextureViewDimension)>,
     pub samplers: Vec<SamplerBindingType>,
+    // Bound after the samplers, read-write
+    pub storage_buffers: Vec<usize>,
+    pub storage_textures: Vec<(TextureFormat, StorageTextureAccess)>,
+    /// Uniform buffers are bound `buffers` bytes at a time, at offsets given when the bind group
+    /// is set. Offsets must be multiples of `min_uniform_buffer_offset_alignment`.
+    pub dynamic_offset: bool,
 }
 
 impl Default for BindGroupLayoutDesc {
@@ -86,6 +154,9 @@ impl Default for BindGroupLayoutDesc {
             buffers: vec![],
             textures: vec![],
             samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
         }
     }
 }
@@ -97,6 +168,8 @@ pub struct BindGroupDesc<'a> {
     pub buffers: &'a [Handle],
     pub textures: &'a [Handle],
     pub samplers: &'a [Handle],
+    pub storage_buffers: &'a [Handle],
+    pub storage_textures: &'a [Handle],
 }
 
 impl Default for BindGroupDesc<'_> {
@@ -109,11 +182,16 @@ impl Default for BindGroupDesc<'_> {
                 buffers: vec![],
                 textures: vec![],
                 samplers: vec![],
+                storage_buffers: vec![],
+                storage_textures: vec![],
+                dynamic_offset: false,
             },
             visibility: ShaderStages::all(),
             buffers: &[],
             textures: &[],
             samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
         }
     }
 }
@@ -131,19 +209,101 @@ pub struct ShaderModuleDesc {
     pub entry_func: String,
 }
 
+pub const ADDITIVE_BLEND: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+    alpha: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+};
+
 #[derive(Clone)]
 pub struct ShaderPipelineDesc {
     pub depth_test: Option<CompareFunction>,
     pub targets: Vec<TextureFormat>,
+    /// Per target, in the same order as `targets`. Targets past the end use `BlendState::REPLACE`,
+    /// `None` disables blending.
+    pub blends: Vec<Option<BlendState>>,
+    pub cull_mode: Option<Face>,
+    pub front_face: FrontFace,
+    /// `Line` needs `Features::POLYGON_MODE_LINE`, check `ResourceManager::supports_wireframe`
+    pub polygon_mode: PolygonMode,
+    /// Must match the sample count of every target and the depth buffer
+    pub sample_count: u32,
+    /// Uses the first target's alpha as coverage, only meaningful when multisampled
+    pub alpha_to_coverage: bool,
     pub vertex_buffer_bindings: Vec<VertexBufferLayout>,
 }
 
+impl Default for ShaderPipelineDesc {
+    fn default() -> Self {
+        ShaderPipelineDesc {
+            depth_test: None,
+            targets: vec![],
+            blends: vec![],
+            cull_mode: None,
+            front_face: FrontFace::Ccw,
+            polygon_mode: PolygonMode::Fill,
+            sample_count: 1,
+            alpha_to_coverage: false,
+            vertex_buffer_bindings: vec![],
+        }
+    }
+}
+
+impl ShaderPipelineDesc {
+    fn color_targets(&self) -> Vec<Option<wgpu::ColorTargetState>> {
+        self.targets
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                Some(wgpu::ColorTargetState {
+                    format: *x,
+                    blend: self
+                        .blends
+                        .get(i)
+                        .copied()
+                        .unwrap_or(Some(BlendState::REPLACE)),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })
+            })
+            .collect()
+    }
+
+    fn primitive_state(&self) -> wgpu::PrimitiveState {
+        wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: self.front_face,
+            cull_mode: self.cull_mode,
+            unclipped_depth: false,
+            polygon_mode: self.polygon_mode,
+            conservative: false,
+        }
+    }
+
+    fn multisample_state(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: self.alpha_to_coverage,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ShaderDesc {
     pub label: Option<String>,
     pub vs: ShaderModuleDesc,
     pub ps: Option<ShaderModuleDesc>,
     pub bind_group_layouts: Vec<BindGroupLayoutDesc>,
+    /// Byte ranges, needs `Features::PUSH_CONSTANTS`
+    pub push_constant_ranges: Vec<(ShaderStages, Range<u32>)>,
     pub pipeline_state: ShaderPipelineDesc,
 }
 
@@ -157,15 +317,31 @@ impl Default for ShaderDesc {
             },
             ps: None,
             bind_group_layouts: vec![],
-            pipeline_state: ShaderPipelineDesc {
-                depth_test: None,
-                targets: vec![],
-                vertex_buffer_bindings: vec![],
-            },
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc::default(),
         }
     }
 }
 
+impl ShaderDesc {
+    fn wgpu_push_constant_ranges(&self) -> Vec<wgpu::PushConstantRange> {
+        self.push_constant_ranges
+            .iter()
+            .map(|(stages, range)| wgpu::PushConstantRange {
+                stages: *stages,
+                range: range.clone(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct ComputeShaderDesc {
+    pub label: Option<String>,
+    pub module: ShaderModuleDesc,
+    pub bind_group_layouts: Vec<BindGroupLayoutDesc>,
+}
+
 // MARK: Resources
 pub struct Buffer {
     internal: wgpu::Buffer,
@@ -179,19 +355,72 @@ impl Buffer {
 
 pub struct Texture {
     pub depth: bool,
+    label: Option<String>,
+    mipmaps: Option<u32>,
+    sample_count: u32,
+    layers: u32,
+    view_dimension: TextureViewDimension,
+    format: TextureFormat,
+    usage: TextureUsages,
     internal: wgpu::Texture,
     view: wgpu::TextureView,
 }
 pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
 
+/// What depth buffers are cleared to, the far plane is 0 with reversed Z.
+pub fn far_plane_depth(reverse_z: bool) -> f32 {
+    if reverse_z {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+/// Whether a texture of `size` has to be reallocated to be `dimensions`, layers are kept as is.
+pub fn size_changed(size: wgpu::Extent3d, dimensions: (u32, u32)) -> bool {
+    (size.width, size.height) != dimensions
+}
+
+/// The scene and every debug view are drawn to an offscreen target of this format, in linear HDR,
+/// so their pipelines don't depend on the surface format, which only egui draws to.
+pub const SCENE_COLOR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
 impl Texture {
-    pub fn depth_stencil_attachment(&self) -> Option<wgpu::RenderPassDepthStencilAttachment> {
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    pub fn size(&self) -> wgpu::Extent3d {
+        self.internal.size()
+    }
+
+    pub fn as_image_copy(&self) -> wgpu::ImageCopyTexture<'_> {
+        self.internal.as_image_copy()
+    }
+
+    /// Clears to the far plane, which is 0 with reversed Z, and stores the result.
+    pub fn depth_stencil_attachment(
+        &self,
+        reverse_z: bool,
+    ) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
+        self.depth_stencil_attachment_with(wgpu::Operations {
+            load: wgpu::LoadOp::Clear(far_plane_depth(reverse_z)),
+            store: true,
+        })
+    }
+
+    /// For passes that load depth written earlier, or don't need to keep it.
+    pub fn depth_stencil_attachment_with(
+        &self,
+        depth_ops: wgpu::Operations<f32>,
+    ) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
         Some(wgpu::RenderPassDepthStencilAttachment {
             view: &self.view,
-            depth_ops: Some(wgpu::Operations {
-                load: wgpu::LoadOp::Clear(1.0),
-                store: true,
-            }),
+            depth_ops: Some(depth_ops),
             stencil_ops: None,
         })
     }
@@ -202,46 +431,151 @@ pub struct Sampler {
 }
 
 pub struct BindGroup {
+    // Kept so the bind group can be rebuilt when one of its textures is recreated
+    label: Option<String>,
+    layout: BindGroupLayoutDesc,
+    buffers: Vec<Handle>,
+    textures: Vec<Handle>,
+    samplers: Vec<Handle>,
+    storage_buffers: Vec<Handle>,
+    storage_textures: Vec<Handle>,
     internal: wgpu::BindGroup,
 }
 
+#[derive(Debug)]
+pub enum ShaderError {
+    Io(std::io::Error),
+    Validation(String),
+    /// The files in the cycle, starting and ending with the same file
+    IncludeCycle(Vec<String>),
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderError::Io(err) => write!(f, "Couldn't read shader: {}", err),
+            ShaderError::Validation(err) => write!(f, "{}", err),
+            ShaderError::IncludeCycle(files) => write!(f, "Include cycle: {}", files.join(" -> ")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ResourceError {
+    Shader(ShaderError),
+    WrongHandleType {
+        expected: HandleType,
+        got: HandleType,
+    },
+    /// The handle doesn't point at an existing resource, e.g. it came from another manager
+    InvalidHandle(HandleType, usize),
+    /// Data can't be uploaded to textures of this format
+    UnsupportedFormat(TextureFormat),
+    /// The view dimension can't be made from this many layers
+    InvalidLayerCount(TextureViewDimension, u32),
+    /// A bound texture doesn't match its entry in the bind group layout
+    IncompatibleTexture {
+        bind_group: Option<String>,
+        binding: usize,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceError::Shader(err) => write!(f, "{}", err),
+            ResourceError::WrongHandleType { expected, got } => {
+                write!(f, "Expected a {:?} handle, got {:?}", expected, got)
+            }
+            ResourceError::InvalidHandle(handle_type, index) => {
+                write!(f, "No {:?} at index {}", handle_type, index)
+            }
+            ResourceError::UnsupportedFormat(format) => {
+                write!(f, "Can't upload texture data in {:?}", format)
+            }
+            ResourceError::InvalidLayerCount(view_dimension, layers) => {
+                write!(
+                    f,
+                    "Can't make a {:?} view of {} layers",
+                    view_dimension, layers
+                )
+            }
+            ResourceError::IncompatibleTexture {
+                bind_group,
+                binding,
+                reason,
+            } => write!(
+                f,
+                "Bind group {:?}, binding {}: {}",
+                bind_group, binding, reason
+            ),
+        }
+    }
+}
+
+impl From<ShaderError> for ResourceError {
+    fn from(err: ShaderError) -> Self {
+        ResourceError::Shader(err)
+    }
+}
+
+/// Reads and preprocesses a WGSL file, also returning the files it includes.
+fn create_module(
+    rm: &ResourceManager,
+    path: &str,
+) -> Result<(wgpu::ShaderModule, Vec<String>), ShaderError> {
+    let preprocessed = shader_preprocessor::preprocess(path)?;
+
+    let module = rm
+        .device
+        .create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(path),
+            source: wgpu::ShaderSource::Wgsl(Cow::from(preprocessed.source.as_str())),
+        });
+
+    Ok((module, preprocessed.includes))
+}
+
 pub struct Shader {
     desc: ShaderDesc,
+    // Files included by either stage
+    includes: Vec<String>,
     internal: wgpu::RenderPipeline,
 }
 
 impl Shader {
-    fn new(rm: &mut ResourceManager, desc: ShaderDesc) -> Self {
-        if desc.ps.is_some() && desc.ps.as_ref().unwrap().path != desc.vs.path {
-            panic!("only supporting ps and vs shaders from same file right now")
-        }
-
-        let source = std::fs::read_to_string(desc.vs.path.clone()).unwrap();
-
-        let shader = rm
-            .device
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some(desc.vs.path.clone().as_str()),
-                source: wgpu::ShaderSource::Wgsl(Cow::from(source.as_str())),
-            });
+    fn new(rm: &mut ResourceManager, desc: ShaderDesc) -> Result<Self, ShaderError> {
+        // Captures WGSL and pipeline validation errors instead of letting wgpu panic
+        rm.device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let shader = create_module(rm, &desc.vs.path);
+        // Stages from the same file share a module
+        let ps_shader = match &desc.ps {
+            Some(ps) if ps.path != desc.vs.path => Some(create_module(rm, &ps.path)),
+            _ => None,
+        };
+        let ((shader, mut includes), ps_shader) = match (shader, ps_shader.transpose()) {
+            (Ok(shader), Ok(ps_shader)) => (shader, ps_shader),
+            (Err(err), _) | (_, Err(err)) => {
+                // Keeps the scope stack balanced for the next shader
+                block_on(rm.device.pop_error_scope());
+                return Err(err);
+            }
+        };
+        let ps_shader = ps_shader.map(|(ps_shader, ps_includes)| {
+            includes.extend(ps_includes);
+            ps_shader
+        });
 
-        let mut bind_group_layouts: Vec<wgpu::BindGroupLayout> = vec![];
+        let mut bind_group_layouts: Vec<Rc<wgpu::BindGroupLayout>> = vec![];
         for entry in &desc.bind_group_layouts {
             bind_group_layouts.push(rm.get_bind_group_layout(entry));
         }
 
-        let targets = desc
-            .pipeline_state
-            .targets
-            .iter()
-            .map(|x| {
-                Some(wgpu::ColorTargetState {
-                    format: *x,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })
-            })
-            .collect::<Vec<Option<wgpu::ColorTargetState>>>();
+        let targets = desc.pipeline_state.color_targets();
+
+        let push_constant_ranges = desc.wgpu_push_constant_ranges();
 
         let mut buffers: Vec<wgpu::VertexBufferLayout> = vec![];
         for buffer in &desc.pipeline_state.vertex_buffer_bindings {
@@ -262,10 +596,10 @@ impl Shader {
                             label: None,
                             bind_group_layouts: bind_group_layouts
                                 .iter()
-                                .map(|x| x)
+                                .map(|x| x.as_ref())
                                 .collect::<Vec<&wgpu::BindGroupLayout>>()
                                 .as_slice(),
-                            push_constant_ranges: &[],
+                            push_constant_ranges: &push_constant_ranges,
                         }),
                 ),
                 vertex: wgpu::VertexState {
@@ -273,15 +607,7 @@ impl Shader {
                     entry_point: desc.vs.entry_func.as_str(),
                     buffers: &buffers,
                 },
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: None,
-                    unclipped_depth: false,
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    conservative: false,
-                },
+                primitive: desc.pipeline_state.primitive_state(),
                 depth_stencil: if let Some(depth_test) = desc.pipeline_state.depth_test {
                     Some(wgpu::DepthStencilState {
                         format: TextureFormat::Depth32Float, // FIXME: move into variable/ texture-impl constant
@@ -293,14 +619,10 @@ impl Shader {
                 } else {
                     None
                 },
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
+                multisample: desc.pipeline_state.multisample_state(),
                 fragment: if desc.ps.is_some() {
                     Some(wgpu::FragmentState {
-                        module: &shader,
+                        module: ps_shader.as_ref().unwrap_or(&shader),
                         entry_point: desc.ps.as_ref().unwrap().entry_func.as_str(),
                         targets: &targets,
                     })
@@ -310,73 +632,215 @@ impl Shader {
                 multiview: None,
             });
 
-        Self {
+        if let Some(err) = block_on(rm.device.pop_error_scope()) {
+            return Err(ShaderError::Validation(err.to_string()));
+        }
+
+        Ok(Self {
             desc,
+            includes,
             internal: pipeline,
-        }
+        })
     }
 
     pub fn pipeline(&self) -> &wgpu::RenderPipeline {
         &self.internal
     }
+
+    /// Every file the pipeline is built from.
+    fn paths(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.desc.vs.path)
+            .chain(self.desc.ps.as_ref().map(|ps| &ps.path))
+            .chain(&self.includes)
+    }
+}
+
+pub struct ComputeShader {
+    desc: ComputeShaderDesc,
+    includes: Vec<String>,
+    internal: wgpu::ComputePipeline,
+}
+
+impl ComputeShader {
+    fn new(rm: &mut ResourceManager, desc: ComputeShaderDesc) -> Result<Self, ShaderError> {
+        rm.device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let (shader, includes) = match create_module(rm, &desc.module.path) {
+            Ok(module) => module,
+            Err(err) => {
+                block_on(rm.device.pop_error_scope());
+                return Err(err);
+            }
+        };
+
+        let mut bind_group_layouts: Vec<Rc<wgpu::BindGroupLayout>> = vec![];
+        for entry in &desc.bind_group_layouts {
+            bind_group_layouts.push(rm.get_bind_group_layout(entry));
+        }
+
+        let pipeline = rm
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: desc.label.as_deref(),
+                layout: Some(
+                    &rm.device
+                        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            label: None,
+                            bind_group_layouts: bind_group_layouts
+                                .iter()
+                                .map(|x| x.as_ref())
+                                .collect::<Vec<&wgpu::BindGroupLayout>>()
+                                .as_slice(),
+                            push_constant_ranges: &[],
+                        }),
+                ),
+                module: &shader,
+                entry_point: desc.module.entry_func.as_str(),
+            });
+
+        if let Some(err) = block_on(rm.device.pop_error_scope()) {
+            return Err(ShaderError::Validation(err.to_string()));
+        }
+
+        Ok(Self {
+            desc,
+            includes,
+            internal: pipeline,
+        })
+    }
+
+    pub fn pipeline(&self) -> &wgpu::ComputePipeline {
+        &self.internal
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.desc.module.path).chain(&self.includes)
+    }
 }
 
+// Scene uniforms and the like are a few hundred bytes, so one chunk covers a frame
+const STAGING_BELT_CHUNK_SIZE: BufferAddress = 1 << 16;
+
 // MARK: Resource manager
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Handle(usize, HandleType);
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum HandleType {
+pub enum HandleType {
     BUFFER,
     TEXTURE,
     SAMPLER,
     BINDGROUP,
     SHADER,
+    COMPUTE,
 }
 
 pub struct ResourceManager {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub surface: wgpu::Surface,
+    /// None for headless devices, which only render offscreen
+    pub surface: Option<wgpu::Surface>,
     pub surface_configuration: wgpu::SurfaceConfiguration,
+    // Supported by the surface, queried once from the adapter
+    present_modes: Vec<wgpu::PresentMode>,
 
     buffers: Vec<Buffer>,
     textures: Vec<Texture>,
     samplers: Vec<Sampler>,
     bind_groups: Vec<BindGroup>,
     shaders: Vec<Shader>,
+    compute_shaders: Vec<ComputeShader>,
+    // Identical layouts share one wgpu object, shaders and bind groups ask for the same few over
+    // and over
+    bind_group_layouts: RefCell<HashMap<BindGroupLayoutDesc, Rc<wgpu::BindGroupLayout>>>,
+    bind_group_layout_cache_hits: Cell<usize>,
+
+    // Uploads per frame data through reused mapped buffers instead of a fresh allocation per
+    // write_buffer call
+    staging_belt: RefCell<wgpu::util::StagingBelt>,
 
     shader_compilation_error: String,
+    // Only set up by watch_shaders, so nothing spawns a watcher thread unless asked to
+    shader_watcher: Option<ShaderWatcher>,
 }
 
 impl ResourceManager {
     pub fn new(
         device: wgpu::Device,
         queue: wgpu::Queue,
-        surface: wgpu::Surface,
+        surface: Option<wgpu::Surface>,
         surface_configuration: wgpu::SurfaceConfiguration,
+        present_modes: Vec<wgpu::PresentMode>,
     ) -> Self {
         Self {
             device,
             queue,
             surface,
             surface_configuration,
+            present_modes,
 
             buffers: vec![],
             textures: vec![],
             samplers: vec![],
             bind_groups: vec![],
             shaders: vec![],
+            compute_shaders: vec![],
+            bind_group_layouts: RefCell::new(HashMap::new()),
+            bind_group_layout_cache_hits: Cell::new(0),
+            staging_belt: RefCell::new(wgpu::util::StagingBelt::new(STAGING_BELT_CHUNK_SIZE)),
 
             shader_compilation_error: String::new(),
+            shader_watcher: None,
+        }
+    }
+
+    /// Sample counts every format in `formats` can be rendered with on this device, 1 is always
+    /// among them.
+    pub fn supported_sample_counts(&self, formats: &[TextureFormat]) -> Vec<u32> {
+        [1, 2, 4, 8, 16]
+            .into_iter()
+            .filter(|count| {
+                formats.iter().all(|format| {
+                    format
+                        .guaranteed_format_features(self.device.features())
+                        .flags
+                        .sample_count_supported(*count)
+                })
+            })
+            .collect()
+    }
+
+    pub fn present_modes(&self) -> &[wgpu::PresentMode] {
+        &self.present_modes
+    }
+
+    /// Reconfigures the surface, falling back to FIFO (which every surface supports) when
+    /// `present_mode` isn't supported.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        self.surface_configuration.present_mode =
+            supported_present_mode(&self.present_modes, present_mode);
+        self.configure_surface();
+    }
+
+    /// Applies `surface_configuration`, after it changed.
+    pub fn configure_surface(&self) {
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_configuration);
         }
     }
 
     pub fn create_buffer(&mut self, desc: &BufferDesc) -> Handle {
+        // Lets read_buffer copy out of any buffer, MAP_READ buffers can only be combined with COPY_DST
+        let usage = if desc.usage.contains(BufferUsages::MAP_READ) {
+            desc.usage
+        } else {
+            desc.usage | BufferUsages::COPY_SRC
+        };
+
         let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
             label: desc.label,
             size: desc.byte_size as u64,
-            usage: desc.usage,
+            usage,
             mapped_at_creation: false,
         });
 
@@ -389,102 +853,176 @@ impl ResourceManager {
         Handle(self.buffers.len() - 1, HandleType::BUFFER)
     }
 
-    pub fn create_texture(&mut self, desc: &TextureDesc) -> Handle {
+    fn build_texture(&self, desc: &TextureDesc) -> Texture {
         let texture = self.device.create_texture(&wgpu::TextureDescriptor {
             label: desc.label,
             size: wgpu::Extent3d {
                 width: desc.dimensions.0,
                 height: desc.dimensions.1,
-                depth_or_array_layers: 1,
+                depth_or_array_layers: desc.layers,
             },
             mip_level_count: desc.mipmaps.unwrap_or(0) + 1,
-            sample_count: 1,
+            sample_count: desc.sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: desc.format,
             usage: desc.usage,
             view_formats: &[],
         });
 
-        let view = texture.create_view(&Default::default());
-
-        let bytes_per_pixel = match desc.format {
-            TextureFormat::Rgba8UnormSrgb => 4,
-            TextureFormat::Depth32Float => 4,
-            TextureFormat::Rgba16Float => 8,
-            _ => panic!("Unsupported format {:?}", desc.format),
-        };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(desc.view_dimension),
+            ..Default::default()
+        });
 
         if let Some(data) = desc.initial_data {
+            self.write_texture(&texture, data);
+        }
+
+        Texture {
+            internal: texture,
+            view,
+            label: desc.label.map(String::from),
+            mipmaps: desc.mipmaps,
+            sample_count: desc.sample_count,
+            layers: desc.layers,
+            view_dimension: desc.view_dimension,
+            format: desc.format,
+            usage: desc.usage,
+            depth: match desc.format {
+                TextureFormat::Depth16Unorm
+                | TextureFormat::Depth24Plus
+                | TextureFormat::Depth24PlusStencil8
+                | TextureFormat::Depth32Float
+                | TextureFormat::Depth32FloatStencil8 => true,
+                _ => false,
+            },
+        }
+    }
+
+    /// Uploads data to the whole of mip 0, one layer at a time.
+    fn write_texture(&self, texture: &wgpu::Texture, data: &[u8]) {
+        let format = texture.format();
+        let (bytes_per_row, rows) = block_layout(format, texture.width(), texture.height())
+            .unwrap_or_else(|| panic!("Can't upload to {:?}, no single aspect size", format));
+        let layer_size = (bytes_per_row * rows) as usize;
+        assert_eq!(
+            data.len(),
+            layer_size * texture.depth_or_array_layers() as usize,
+            "Texture data doesn't cover every layer"
+        );
+
+        for (layer, layer_data) in data.chunks(layer_size).enumerate() {
             self.queue.write_texture(
                 wgpu::ImageCopyTexture {
-                    texture: &texture,
+                    texture,
                     mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
                     aspect: wgpu::TextureAspect::All,
                 },
-                data,
+                layer_data,
                 wgpu::ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: Some(bytes_per_pixel * desc.dimensions.0),
-                    rows_per_image: Some(desc.dimensions.1),
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(rows),
                 },
                 wgpu::Extent3d {
-                    width: desc.dimensions.0,
-                    height: desc.dimensions.1,
                     depth_or_array_layers: 1,
+                    ..texture.size()
                 },
             );
         }
+    }
 
-        self.textures.push(Texture {
-            internal: texture,
-            view,
-            depth: match desc.format {
-                TextureFormat::Depth16Unorm
-                | TextureFormat::Depth24Plus
-                | TextureFormat::Depth24PlusStencil8
-                | TextureFormat::Depth32Float
-                | TextureFormat::Depth32FloatStencil8 => true,
-                _ => false,
-            },
-        });
+    pub fn create_texture(&mut self, desc: &TextureDesc) -> Handle {
+        self.try_create_texture(desc)
+            .unwrap_or_else(|err| panic!("Couldn't create texture {:?}: {}", desc.label, err))
+    }
 
-        Handle(self.textures.len() - 1, HandleType::TEXTURE)
+    pub fn try_create_texture(&mut self, desc: &TextureDesc) -> Result<Handle, ResourceError> {
+        if desc.initial_data.is_some() && desc.format.block_size(None).is_none() {
+            return Err(ResourceError::UnsupportedFormat(desc.format));
+        }
+        check_layer_count(desc.view_dimension, desc.layers)?;
+
+        let texture = self.build_texture(desc);
+
+        self.textures.push(texture);
+
+        Ok(Handle(self.textures.len() - 1, HandleType::TEXTURE))
+    }
+
+    /// Overwrites the contents of mip 0, data must cover the whole texture.
+    pub fn update_texture(&self, handle: Handle, data: &[u8]) {
+        self.write_texture(&self.get_texture(handle).internal, data);
+    }
+
+    /// Recreates the texture at new dimensions in place (contents are discarded), then rebuilds
+    /// every bind group that references it so existing handles stay valid.
+    pub fn resize_texture(&mut self, handle: Handle, dimensions: (u32, u32)) {
+        let texture = self.get_texture(handle);
+        let label = texture.label.clone();
+        let desc = TextureDesc {
+            label: label.as_deref(),
+            dimensions,
+            mipmaps: texture.mipmaps,
+            sample_count: texture.sample_count,
+            layers: texture.layers,
+            view_dimension: texture.view_dimension,
+            format: texture.format,
+            usage: texture.usage,
+            initial_data: None,
+        };
+
+        self.textures[handle.0] = self.build_texture(&desc);
+
+        for i in 0..self.bind_groups.len() {
+            let bind_group = &self.bind_groups[i];
+            if bind_group.textures.contains(&handle)
+                || bind_group.storage_textures.contains(&handle)
+            {
+                self.bind_groups[i].internal = self.build_bind_group(&BindGroupDesc {
+                    label: bind_group.label.as_deref(),
+                    visibility: bind_group.layout.visibility,
+                    layout: bind_group.layout.clone(),
+                    buffers: &bind_group.buffers,
+                    textures: &bind_group.textures,
+                    samplers: &bind_group.samplers,
+                    storage_buffers: &bind_group.storage_buffers,
+                    storage_textures: &bind_group.storage_textures,
+                });
+            }
+        }
     }
 
     pub fn create_sampler(&mut self, desc: SamplerDesc) -> Handle {
-        let sampler = self.device.create_sampler(&wgpu::SamplerDescriptor {
-            label: desc.label,
-            address_mode_u: desc.address_mode,
-            address_mode_v: desc.address_mode,
-            address_mode_w: desc.address_mode,
-            mag_filter: desc.mag_min_filter,
-            min_filter: desc.mag_min_filter,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            lod_min_clamp: 0.0,
-            lod_max_clamp: if let Some(mipmaps) = desc.mipmaps {
-                mipmaps as f32
-            } else {
-                0.0
-            },
-            compare: desc.compare,
-            anisotropy_clamp: 1,
-            border_color: None,
-        });
+        let sampler = self.device.create_sampler(&desc.descriptor());
 
         self.samplers.push(Sampler { internal: sampler });
 
         Handle(self.samplers.len() - 1, HandleType::SAMPLER)
     }
 
-    pub fn create_bind_group(&mut self, desc: &BindGroupDesc) -> Handle {
+    fn build_bind_group(&self, desc: &BindGroupDesc) -> wgpu::BindGroup {
         let mut i = 0;
         let mut entries: Vec<wgpu::BindGroupEntry> = vec![];
 
-        for entry in desc.buffers {
+        for (entry, size) in desc.buffers.iter().zip(&desc.layout.buffers) {
+            let buffer = &self.buffers[entry.0].internal;
             entries.push(wgpu::BindGroupEntry {
                 binding: i,
-                resource: self.buffers[entry.0].internal.as_entire_binding(),
+                resource: if desc.layout.dynamic_offset {
+                    wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer,
+                        offset: 0,
+                        size: NonZeroU64::new(*size as u64),
+                    })
+                } else {
+                    buffer.as_entire_binding()
+                },
             });
 
             i += 1;
@@ -508,108 +1046,224 @@ impl ResourceManager {
             i += 1;
         }
 
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: desc.label,
-            layout: &self.get_bind_group_layout(&desc.layout),
-            entries: entries.as_slice(),
-        });
-
-        self.bind_groups.push(BindGroup {
-            internal: bind_group,
-        });
+        for entry in desc.storage_buffers {
+            entries.push(wgpu::BindGroupEntry {
+                binding: i,
+                resource: self.buffers[entry.0].internal.as_entire_binding(),
+            });
 
-        Handle(self.bind_groups.len() - 1, HandleType::BINDGROUP)
-    }
+            i += 1;
+        }
 
-    pub fn create_shader(&mut self, desc: ShaderDesc) -> Handle {
-        let shader = Shader::new(self, desc);
+        for entry in desc.storage_textures {
+            entries.push(wgpu::BindGroupEntry {
+                binding: i,
+                resource: wgpu::BindingResource::TextureView(&self.textures[entry.0].view),
+            });
 
-        self.shaders.push(shader);
+            i += 1;
+        }
 
-        Handle(self.shaders.len() - 1, HandleType::SHADER)
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: desc.label,
+            layout: &self.get_bind_group_layout(&desc.layout),
+            entries: entries.as_slice(),
+        })
     }
 
-    pub fn get_buffer(&self, handle: Handle) -> &Buffer {
-        if handle.1 != HandleType::BUFFER {
-            panic!("Handle type is incorrect.");
+    pub fn create_bind_group(&mut self, desc: &BindGroupDesc) -> Result<Handle, ResourceError> {
+        // wgpu only reports these as an opaque validation error, which panics by default
+        for (i, (texture, (sample_type, view_dimension))) in
+            desc.textures.iter().zip(&desc.layout.textures).enumerate()
+        {
+            let texture = self.get_texture(*texture);
+            let expects_depth = *sample_type == TextureSampleType::Depth;
+            let reason = if texture.view_dimension != *view_dimension {
+                format!(
+                    "layout expects a {:?} view but texture {:?} is {:?}",
+                    view_dimension, texture.label, texture.view_dimension
+                )
+            } else if texture.depth != expects_depth {
+                format!(
+                    "layout expects {:?} but texture {:?} is {}a depth texture",
+                    sample_type,
+                    texture.label,
+                    if texture.depth { "" } else { "not " }
+                )
+            } else {
+                continue;
+            };
+            return Err(ResourceError::IncompatibleTexture {
+                bind_group: desc.label.map(String::from),
+                binding: desc.buffers.len() + i,
+                reason,
+            });
         }
-        &self.buffers[handle.0]
+
+        let bind_group = self.build_bind_group(desc);
+
+        self.bind_groups.push(BindGroup {
+            label: desc.label.map(String::from),
+            layout: desc.layout.clone(),
+            buffers: desc.buffers.to_vec(),
+            textures: desc.textures.to_vec(),
+            samplers: desc.samplers.to_vec(),
+            storage_buffers: desc.storage_buffers.to_vec(),
+            storage_textures: desc.storage_textures.to_vec(),
+            internal: bind_group,
+        });
+
+        Ok(Handle(self.bind_groups.len() - 1, HandleType::BINDGROUP))
     }
 
-    pub fn get_texture(&self, handle: Handle) -> &Texture {
-        if handle.1 != HandleType::TEXTURE {
-            panic!("Handle type is incorrect.");
+    /// Errors are also shown in the resource UI, the same as failed reloads.
+    pub fn create_shader(&mut self, desc: ShaderDesc) -> Result<Handle, ResourceError> {
+        let path = desc.vs.path.clone();
+        let shader = Shader::new(self, desc).map_err(|err| self.shader_error(&path, err))?;
+
+        if let Some(watcher) = &mut self.shader_watcher {
+            for path in shader.paths() {
+                watcher.watch(path);
+            }
         }
-        &self.textures[handle.0]
+
+        self.shaders.push(shader);
+
+        Ok(Handle(self.shaders.len() - 1, HandleType::SHADER))
     }
 
-    pub fn get_shader(&self, handle: Handle) -> &Shader {
-        if handle.1 != HandleType::SHADER {
-            panic!("Handle type is incorrect.");
+    pub fn create_compute_shader(
+        &mut self,
+        desc: ComputeShaderDesc,
+    ) -> Result<Handle, ResourceError> {
+        let path = desc.module.path.clone();
+        let shader = ComputeShader::new(self, desc).map_err(|err| self.shader_error(&path, err))?;
+
+        if let Some(watcher) = &mut self.shader_watcher {
+            for path in shader.paths() {
+                watcher.watch(path);
+            }
         }
-        &self.shaders[handle.0]
+
+        self.compute_shaders.push(shader);
+
+        Ok(Handle(self.compute_shaders.len() - 1, HandleType::COMPUTE))
     }
 
-    fn get_bind_group_layout(&self, desc: &BindGroupLayoutDesc) -> wgpu::BindGroupLayout {
-        let mut i = 0;
-        let mut entries: Vec<wgpu::BindGroupLayoutEntry> = vec![];
+    fn shader_error(&mut self, path: &str, err: ShaderError) -> ShaderError {
+        self.shader_compilation_error = format!("{}: {}", path, err);
+        err
+    }
 
-        for entry in &desc.buffers {
-            entries.push(wgpu::BindGroupLayoutEntry {
-                binding: i,
-                visibility: desc.visibility,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: NonZeroU64::new(*entry as u64),
-                },
-                count: None,
+    fn check_handle(&self, handle: Handle, expected: HandleType) -> Result<(), ResourceError> {
+        if handle.1 != expected {
+            return Err(ResourceError::WrongHandleType {
+                expected,
+                got: handle.1,
             });
-
-            i += 1;
         }
+        if !self.is_valid(handle) {
+            return Err(ResourceError::InvalidHandle(handle.1, handle.0));
+        }
+        Ok(())
+    }
 
-        for entry in &desc.textures {
-            entries.push(wgpu::BindGroupLayoutEntry {
-                binding: i,
-                visibility: desc.visibility,
-                ty: wgpu::BindingType::Texture {
-                    sample_type: *entry,
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    multisampled: false,
-                },
-                count: None,
-            });
+    pub fn try_get_buffer(&self, handle: Handle) -> Result<&Buffer, ResourceError> {
+        self.check_handle(handle, HandleType::BUFFER)?;
+        Ok(&self.buffers[handle.0])
+    }
 
-            i += 1;
-        }
+    pub fn try_get_texture(&self, handle: Handle) -> Result<&Texture, ResourceError> {
+        self.check_handle(handle, HandleType::TEXTURE)?;
+        Ok(&self.textures[handle.0])
+    }
 
-        for entry in &desc.samplers {
-            entries.push(wgpu::BindGroupLayoutEntry {
-                binding: i,
-                visibility: desc.visibility,
-                ty: wgpu::BindingType::Sampler(*entry),
-                count: None,
-            });
+    pub fn try_get_shader(&self, handle: Handle) -> Result<&Shader, ResourceError> {
+        self.check_handle(handle, HandleType::SHADER)?;
+        Ok(&self.shaders[handle.0])
+    }
 
-            i += 1;
+    pub fn try_get_compute_shader(&self, handle: Handle) -> Result<&ComputeShader, ResourceError> {
+        self.check_handle(handle, HandleType::COMPUTE)?;
+        Ok(&self.compute_shaders[handle.0])
+    }
+
+    pub fn try_get_bind_group(&self, handle: Handle) -> Result<&wgpu::BindGroup, ResourceError> {
+        self.check_handle(handle, HandleType::BINDGROUP)?;
+        Ok(&self.bind_groups[handle.0].internal)
+    }
+
+    pub fn get_buffer(&self, handle: Handle) -> &Buffer {
+        self.try_get_buffer(handle)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn get_texture(&self, handle: Handle) -> &Texture {
+        self.try_get_texture(handle)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn get_shader(&self, handle: Handle) -> &Shader {
+        self.try_get_shader(handle)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn get_compute_shader(&self, handle: Handle) -> &ComputeShader {
+        self.try_get_compute_shader(handle)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Whether the handle points at an existing resource of its type. Resources are never
+    /// freed yet, so this only catches handles from another resource manager; once deletion
+    /// lands it should also compare generations.
+    pub fn is_valid(&self, handle: Handle) -> bool {
+        let len = match handle.1 {
+            HandleType::BUFFER => self.buffers.len(),
+            HandleType::TEXTURE => self.textures.len(),
+            HandleType::SAMPLER => self.samplers.len(),
+            HandleType::BINDGROUP => self.bind_groups.len(),
+            HandleType::SHADER => self.shaders.len(),
+            HandleType::COMPUTE => self.compute_shaders.len(),
+        };
+        handle.0 < len
+    }
+
+    fn get_bind_group_layout(&self, desc: &BindGroupLayoutDesc) -> Rc<wgpu::BindGroupLayout> {
+        if let Some(layout) = self.bind_group_layouts.borrow().get(desc) {
+            self.bind_group_layout_cache_hits
+                .set(self.bind_group_layout_cache_hits.get() + 1);
+            return layout.clone();
         }
 
-        let bind_group_layout =
-            self.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: desc.label.as_deref(),
-                    entries: entries.as_slice(),
-                });
+        let entries = bind_group_layout_entries(desc);
+
+        let bind_group_layout = Rc::new(self.device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: desc.label.as_deref(),
+                entries: entries.as_slice(),
+            },
+        ));
+        self.bind_group_layouts
+            .borrow_mut()
+            .insert(desc.clone(), bind_group_layout.clone());
 
         bind_group_layout
     }
 
+    /// How many layouts were shared instead of created again.
+    pub fn bind_group_layout_cache_hits(&self) -> usize {
+        self.bind_group_layout_cache_hits.get()
+    }
+
+    pub fn supports_wireframe(&self) -> bool {
+        self.device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+    }
+
     pub fn get_bind_group(&self, handle: Handle) -> &wgpu::BindGroup {
-        if handle.1 != HandleType::BINDGROUP {
-            panic!("Expected handle type bindgroup, got {:?}", handle.1);
-        }
-        &self.bind_groups[handle.0].internal
+        self.try_get_bind_group(handle)
+            .unwrap_or_else(|err| panic!("{}", err))
     }
 
     pub fn update_buffer(&self, handle: Handle, data: &[u8]) {
@@ -617,28 +1271,233 @@ impl ResourceManager {
             .write_buffer(&self.buffers[handle.0].internal, 0, data);
     }
 
-    pub fn recompile(&mut self, handle: Handle) {
-        let shader = &self.shaders[handle.0];
+    /// Like `update_buffer`, but the copy is recorded into `encoder` from the staging belt, for data
+    /// written every frame. Call `finish_staging` before submitting the encoder and
+    /// `recall_staging` after.
+    pub fn stage_buffer_update(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        handle: Handle,
+        data: &[u8],
+    ) {
+        let size = NonZeroU64::new(data.len() as u64).expect("Staged an empty buffer update");
+        self.staging_belt
+            .borrow_mut()
+            .write_buffer(
+                encoder,
+                &self.buffers[handle.0].internal,
+                0,
+                size,
+                &self.device,
+            )
+            .copy_from_slice(data);
+    }
+
+    /// Unmaps this frame's staging chunks so the copies recorded by `stage_buffer_update` can run.
+    pub fn finish_staging(&self) {
+        self.staging_belt.borrow_mut().finish();
+    }
+
+    /// Makes staging chunks reusable once the GPU is done with them.
+    pub fn recall_staging(&self) {
+        self.staging_belt.borrow_mut().recall();
+    }
+
+    /// Copies the buffer's contents back to the CPU. Blocks until the GPU is idle, debugging only.
+    pub fn read_buffer(&self, handle: Handle) -> Vec<u8> {
+        let buffer = &self.get_buffer(handle).internal;
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Read back staging buffer"),
+            size: buffer.size(),
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Read back"),
+            });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        match receiver.recv() {
+            Ok(Ok(())) => slice.get_mapped_range().to_vec(),
+            _ => panic!("Couldn't map buffer for reading"),
+        }
+    }
 
-        let source = std::fs::read_to_string(shader.desc.vs.path.clone()).unwrap();
+    /// Reads back the first mip level of an uncompressed texture, rows tightly packed. The texture
+    /// must allow `COPY_SRC`.
+    pub fn read_texture(&self, handle: Handle) -> Vec<u8> {
+        let texture = self.get_texture(handle);
+        let size = texture.size();
+        let block_size = texture
+            .format
+            .block_size(None)
+            .unwrap_or_else(|| panic!("Can't read back {:?}", texture.format));
+        let bytes_per_row = size.width * block_size;
+        // Buffer copies need each row to start on a 256 byte boundary
+        let padded_bytes_per_row =
+            wgpu::util::align_to(bytes_per_row, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Texture read back staging buffer"),
+            size: (padded_bytes_per_row * size.height) as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
-        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
-        _ = self
+        let mut encoder = self
             .device
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: shader.desc.label.as_deref(),
-                source: wgpu::ShaderSource::Wgsl(Cow::from(source.as_str())),
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Texture read back"),
             });
-        let result = self.device.pop_error_scope();
-        match block_on(result) {
-            Some(err) => {
-                self.shader_compilation_error = err.to_string();
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &staging,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        match receiver.recv() {
+            Ok(Ok(())) => remove_row_padding(
+                &slice.get_mapped_range(),
+                bytes_per_row as usize,
+                padded_bytes_per_row as usize,
+            ),
+            _ => panic!("Couldn't map texture for reading"),
+        }
+    }
+
+    /// Rebuilds a render or compute shader from its source file, keeping the old pipeline on error.
+    pub fn recompile(&mut self, handle: Handle) {
+        self.recompile_many(&[handle]);
+    }
+
+    /// Recompiles every shader, useful after editing a shared include.
+    pub fn recompile_all(&mut self) {
+        let handles: Vec<Handle> = (0..self.shaders.len())
+            .map(|i| Handle(i, HandleType::SHADER))
+            .chain((0..self.compute_shaders.len()).map(|i| Handle(i, HandleType::COMPUTE)))
+            .collect();
+        self.recompile_many(&handles);
+    }
+
+    /// Attempts every shader even after a failure, showing all of the errors together.
+    fn recompile_many(&mut self, handles: &[Handle]) {
+        let errors: Vec<String> = handles
+            .iter()
+            .filter_map(|handle| {
+                self.rebuild_shader(*handle)
+                    .err()
+                    .map(|(path, err)| format!("{}: {}", path, err))
+            })
+            .collect();
+        self.shader_compilation_error = errors.join("\n");
+    }
+
+    fn rebuild_shader(&mut self, handle: Handle) -> Result<(), (String, ShaderError)> {
+        let (path, result) = match handle.1 {
+            HandleType::SHADER => {
+                let desc = self.shaders[handle.0].desc.clone();
+                (
+                    desc.vs.path.clone(),
+                    Shader::new(self, desc).map(|shader| self.shaders[handle.0] = shader),
+                )
+            }
+            HandleType::COMPUTE => {
+                let desc = self.compute_shaders[handle.0].desc.clone();
+                (
+                    desc.module.path.clone(),
+                    ComputeShader::new(self, desc)
+                        .map(|shader| self.compute_shaders[handle.0] = shader),
+                )
+            }
+            _ => panic!("Expected a shader handle, got {:?}", handle.1),
+        };
+
+        result.map_err(|err| (path, err))
+    }
+
+    /// Starts recompiling shaders automatically when their source files change, see poll_reloads.
+    pub fn watch_shaders(&mut self) {
+        let mut watcher = match ShaderWatcher::new() {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                println!("Shader hot reload unavailable: {}", err);
+                return;
             }
-            None => {
-                self.shader_compilation_error = String::new();
-                self.shaders[handle.0] = Shader::new(self, shader.desc.clone());
+        };
+
+        for shader in &self.shaders {
+            for path in shader.paths() {
+                watcher.watch(path);
+            }
+        }
+        for shader in &self.compute_shaders {
+            for path in shader.paths() {
+                watcher.watch(path);
             }
         }
+
+        self.shader_watcher = Some(watcher);
+    }
+
+    /// Recompiles every shader whose source changed since the last call. Call once per frame.
+    pub fn poll_reloads(&mut self) {
+        let Some(watcher) = &mut self.shader_watcher else {
+            return;
+        };
+
+        let changed = watcher.changed_files();
+        if changed.is_empty() {
+            return;
+        }
+
+        let is_changed = |path: &str| {
+            std::fs::canonicalize(path)
+                .map(|path| changed.contains(&path))
+                .unwrap_or(false)
+        };
+
+        let handles: Vec<Handle> = self
+            .shaders
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.paths().any(|path| is_changed(path)))
+            .map(|(i, _)| Handle(i, HandleType::SHADER))
+            .chain(
+                self.compute_shaders
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, x)| x.paths().any(|path| is_changed(path)))
+                    .map(|(i, _)| Handle(i, HandleType::COMPUTE)),
+            )
+            .collect();
+
+        self.recompile_many(&handles);
     }
 
     pub fn egui(&mut self, ui: &mut egui::Ui) {
@@ -646,20 +1505,39 @@ impl ResourceManager {
         ui.label(format!("Textures created: {}", self.textures.len()));
         ui.label(format!("Samplers created: {}", self.samplers.len()));
         ui.label(format!("BindGroups created: {}", self.bind_groups.len()));
+        ui.label(format!(
+            "BindGroupLayouts created: {}",
+            self.bind_group_layouts.borrow().len()
+        ));
         ui.label(format!("Shaders created: {}", self.shaders.len()));
-
-        ui.label(egui::RichText::new("Shaders").strong());
+        ui.label(format!(
+            "Compute shaders created: {}",
+            self.compute_shaders.len()
+        ));
+
+        ui.horizontal(|ui| {
+            ui.label(egui::RichText::new("Shaders").strong());
+            if ui.button("Reload all").clicked() {
+                self.recompile_all();
+            }
+        });
         egui::Grid::new("shaders").show(ui, |ui| {
-            let paths: Vec<String> = self
-                .shaders
-                .iter()
-                .map(|x| x.desc.vs.path.clone())
-                .collect();
+            let paths: Vec<(String, Handle)> =
+                self.shaders
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| (x.desc.vs.path.clone(), Handle(i, HandleType::SHADER)))
+                    .chain(
+                        self.compute_shaders.iter().enumerate().map(|(i, x)| {
+                            (x.desc.module.path.clone(), Handle(i, HandleType::COMPUTE))
+                        }),
+                    )
+                    .collect();
 
-            for (i, path) in paths.iter().enumerate() {
+            for (path, handle) in paths {
                 ui.label(path);
                 if ui.button("Reload").clicked() {
-                    self.recompile(Handle(i, HandleType::SHADER));
+                    self.recompile(handle);
                 }
                 ui.end_row();
             }
@@ -668,3 +1546,759 @@ impl ResourceManager {
         ui.label(egui::RichText::new(&self.shader_compilation_error).color(Color32::RED));
     }
 }
+
+#[cfg(test)]
+impl Texture {
+    pub fn usage(&self) -> TextureUsages {
+        self.usage
+    }
+}
+
+#[cfg(test)]
+impl ResourceManager {
+    /// A device without a surface for tests, None when there's no adapter to create it on.
+    /// `surface_configuration` still sizes the screen-sized targets.
+    pub fn headless() -> Option<Self> {
+        Self::headless_with(|_| true)
+    }
+
+    /// Like `headless`, but None on the GL backend too. Its shader translation can't `textureLoad`
+    /// from depth textures, which the AO techniques and the renderer do.
+    pub fn headless_loading_depth() -> Option<Self> {
+        Self::headless_with(|adapter| adapter.get_info().backend != wgpu::Backend::Gl)
+    }
+
+    /// The textures a bind group was created with, in binding order.
+    pub fn bind_group_textures(&self, handle: Handle) -> &[Handle] {
+        &self.bind_groups[handle.0].textures
+    }
+
+    fn headless_with(usable: impl Fn(&wgpu::Adapter) -> bool) -> Option<Self> {
+        let instance = wgpu::Instance::default();
+        let Some(adapter) = block_on(instance.request_adapter(&Default::default())) else {
+            println!("No adapter, skipping the GPU part of the test");
+            return None;
+        };
+        if !usable(&adapter) {
+            println!("Adapter can't run this test, skipping the GPU part of it");
+            return None;
+        }
+        let (device, queue) = block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Test device"),
+                features: adapter.features() & crate::OPTIONAL_FEATURES,
+                limits: adapter.limits(),
+            },
+            None,
+        ))
+        .ok()?;
+
+        let surface_configuration = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: TextureFormat::Rgba8UnormSrgb,
+            width: 64,
+            height: 64,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+        Some(Self::new(
+            device,
+            queue,
+            None,
+            surface_configuration,
+            vec![],
+        ))
+    }
+}
+
+/// Bytes per row and number of rows of a `width` x `height` image. Block compressed formats are
+/// laid out in rows of blocks rather than texels. None for formats without a single aspect size,
+/// like combined depth stencil.
+fn block_layout(format: TextureFormat, width: u32, height: u32) -> Option<(u32, u32)> {
+    let block_size = format.block_size(None)?;
+    let (block_width, block_height) = format.block_dimensions();
+    Some((
+        block_size * width.div_ceil(block_width),
+        height.div_ceil(block_height),
+    ))
+}
+
+/// Entries numbered in the order buffers, textures, samplers, storage buffers, storage textures.
+fn bind_group_layout_entries(desc: &BindGroupLayoutDesc) -> Vec<wgpu::BindGroupLayoutEntry> {
+    let mut i = 0;
+    let mut entries: Vec<wgpu::BindGroupLayoutEntry> = vec![];
+
+    for entry in &desc.buffers {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: i,
+            visibility: desc.visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: desc.dynamic_offset,
+                min_binding_size: NonZeroU64::new(*entry as u64),
+            },
+            count: None,
+        });
+
+        i += 1;
+    }
+
+    for (sample_type, view_dimension) in &desc.textures {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: i,
+            visibility: desc.visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type: *sample_type,
+                view_dimension: *view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        });
+
+        i += 1;
+    }
+
+    for entry in &desc.samplers {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: i,
+            visibility: desc.visibility,
+            ty: wgpu::BindingType::Sampler(*entry),
+            count: None,
+        });
+
+        i += 1;
+    }
+
+    for entry in &desc.storage_buffers {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: i,
+            visibility: desc.visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: NonZeroU64::new(*entry as u64),
+            },
+            count: None,
+        });
+
+        i += 1;
+    }
+
+    for (format, access) in &desc.storage_textures {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: i,
+            visibility: desc.visibility,
+            ty: wgpu::BindingType::StorageTexture {
+                access: *access,
+                format: *format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        });
+
+        i += 1;
+    }
+
+    entries
+}
+
+/// Whether a view of `view_dimension` can be made from `layers` array layers.
+fn check_layer_count(
+    view_dimension: TextureViewDimension,
+    layers: u32,
+) -> Result<(), ResourceError> {
+    let layers_valid = match view_dimension {
+        TextureViewDimension::D2 => layers == 1,
+        TextureViewDimension::D2Array => layers >= 1,
+        TextureViewDimension::Cube => layers == 6,
+        _ => false,
+    };
+    if layers_valid {
+        Ok(())
+    } else {
+        Err(ResourceError::InvalidLayerCount(view_dimension, layers))
+    }
+}
+
+/// `present_mode` if the surface supports it, FIFO otherwise.
+fn supported_present_mode(
+    supported: &[wgpu::PresentMode],
+    present_mode: wgpu::PresentMode,
+) -> wgpu::PresentMode {
+    if supported.contains(&present_mode) {
+        present_mode
+    } else {
+        wgpu::PresentMode::Fifo
+    }
+}
+
+/// Drops the padding at the end of every `padded_bytes_per_row` long row.
+fn remove_row_padding(data: &[u8], bytes_per_row: usize, padded_bytes_per_row: usize) -> Vec<u8> {
+    data.chunks(padded_bytes_per_row)
+        .flat_map(|row| &row[..bytes_per_row])
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_layout_of_uncompressed_formats() {
+        assert_eq!(block_layout(TextureFormat::R8Unorm, 3, 2), Some((3, 2)));
+        assert_eq!(
+            block_layout(TextureFormat::Rgba8UnormSrgb, 3, 2),
+            Some((12, 2))
+        );
+        assert_eq!(
+            block_layout(TextureFormat::Rgba16Float, 3, 2),
+            Some((24, 2))
+        );
+    }
+
+    #[test]
+    fn block_layout_of_compressed_formats() {
+        // 4x4 blocks of 8 bytes, partial blocks round up
+        assert_eq!(
+            block_layout(TextureFormat::Bc1RgbaUnorm, 6, 5),
+            Some((16, 2))
+        );
+        assert_eq!(
+            block_layout(TextureFormat::Bc7RgbaUnorm, 8, 8),
+            Some((32, 2))
+        );
+    }
+
+    #[test]
+    fn block_layout_needs_a_single_aspect() {
+        assert_eq!(block_layout(TextureFormat::Depth24PlusStencil8, 4, 4), None);
+    }
+
+    #[test]
+    fn remove_row_padding_round_trips() {
+        // 3x3 RGBA8, every byte distinct
+        let pixels: Vec<u8> = (0..3 * 3 * 4).collect();
+        let bytes_per_row = 3 * 4;
+        let padded_bytes_per_row =
+            wgpu::util::align_to(bytes_per_row, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize);
+
+        let mut padded = vec![0xff; padded_bytes_per_row * 3];
+        for (row, pixels) in padded
+            .chunks_mut(padded_bytes_per_row)
+            .zip(pixels.chunks(bytes_per_row))
+        {
+            row[..bytes_per_row].copy_from_slice(pixels);
+        }
+
+        assert_eq!(
+            remove_row_padding(&padded, bytes_per_row, padded_bytes_per_row),
+            pixels
+        );
+    }
+
+    #[test]
+    fn remove_row_padding_without_padding_is_a_copy() {
+        let pixels: Vec<u8> = (0..64 * 2 * 4).map(|i| i as u8).collect();
+        assert_eq!(remove_row_padding(&pixels, 256, 256), pixels);
+    }
+
+    #[test]
+    fn cube_textures_need_six_layers() {
+        assert!(check_layer_count(TextureViewDimension::Cube, 6).is_ok());
+        assert!(check_layer_count(TextureViewDimension::Cube, 1).is_err());
+        assert!(check_layer_count(TextureViewDimension::D2, 6).is_err());
+        assert!(check_layer_count(TextureViewDimension::D2Array, 6).is_ok());
+    }
+
+    #[test]
+    fn layout_entries_keep_the_texture_view_dimension() {
+        let desc = BindGroupLayoutDesc {
+            buffers: vec![64],
+            textures: vec![
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::Cube,
+                ),
+                (TextureSampleType::Depth, TextureViewDimension::D2Array),
+            ],
+            samplers: vec![SamplerBindingType::Filtering],
+            ..Default::default()
+        };
+        let entries = bind_group_layout_entries(&desc);
+
+        let view_dimensions = entries
+            .iter()
+            .map(|entry| match entry.ty {
+                wgpu::BindingType::Texture { view_dimension, .. } => Some(view_dimension),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            view_dimensions,
+            [
+                None,
+                Some(TextureViewDimension::Cube),
+                Some(TextureViewDimension::D2Array),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn targets_without_a_blend_state_replace() {
+        let desc = ShaderPipelineDesc {
+            targets: vec![TextureFormat::Rgba16Float, TextureFormat::Rgba8Unorm],
+            blends: vec![Some(ADDITIVE_BLEND)],
+            ..Default::default()
+        };
+        let targets = desc.color_targets();
+
+        assert_eq!(targets[0].as_ref().unwrap().blend, Some(ADDITIVE_BLEND));
+        assert_eq!(
+            targets[1].as_ref().unwrap().blend,
+            Some(BlendState::REPLACE)
+        );
+    }
+
+    #[test]
+    fn culling_reaches_the_primitive_state() {
+        let primitive = ShaderPipelineDesc {
+            cull_mode: Some(Face::Back),
+            front_face: FrontFace::Cw,
+            ..Default::default()
+        }
+        .primitive_state();
+        assert_eq!(primitive.cull_mode, Some(Face::Back));
+        assert_eq!(primitive.front_face, FrontFace::Cw);
+
+        // Defaults draw both faces, as before culling was configurable
+        let primitive = ShaderPipelineDesc::default().primitive_state();
+        assert_eq!(primitive.cull_mode, None);
+        assert_eq!(primitive.front_face, FrontFace::Ccw);
+    }
+
+    #[test]
+    fn wireframe_reaches_the_primitive_state() {
+        let primitive = ShaderPipelineDesc {
+            polygon_mode: PolygonMode::Line,
+            ..Default::default()
+        }
+        .primitive_state();
+        assert_eq!(primitive.polygon_mode, PolygonMode::Line);
+    }
+
+    #[test]
+    fn sample_count_reaches_the_multisample_state() {
+        let multisample = ShaderPipelineDesc {
+            sample_count: 4,
+            ..Default::default()
+        }
+        .multisample_state();
+        assert_eq!(multisample.count, 4);
+        assert_eq!(multisample.mask, !0);
+        assert!(!multisample.alpha_to_coverage_enabled);
+    }
+
+    #[test]
+    fn unsupported_present_modes_fall_back_to_fifo() {
+        let supported = [wgpu::PresentMode::Fifo, wgpu::PresentMode::Mailbox];
+        assert_eq!(
+            supported_present_mode(&supported, wgpu::PresentMode::Mailbox),
+            wgpu::PresentMode::Mailbox
+        );
+        assert_eq!(
+            supported_present_mode(&supported, wgpu::PresentMode::Immediate),
+            wgpu::PresentMode::Fifo
+        );
+    }
+
+    #[test]
+    fn push_constant_ranges_reach_the_pipeline_layout() {
+        let desc = ShaderDesc {
+            push_constant_ranges: vec![
+                (ShaderStages::VERTEX, 0..64),
+                (ShaderStages::FRAGMENT, 64..80),
+            ],
+            ..Default::default()
+        };
+        let ranges = desc.wgpu_push_constant_ranges();
+
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].stages, ShaderStages::VERTEX);
+        assert_eq!(ranges[0].range, 0..64);
+        assert_eq!(ranges[1].stages, ShaderStages::FRAGMENT);
+        assert_eq!(ranges[1].range, 64..80);
+    }
+
+    #[test]
+    fn filters_reach_the_sampler_descriptor() {
+        let descriptor = SamplerDesc {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        }
+        .descriptor();
+        assert_eq!(descriptor.mag_filter, FilterMode::Linear);
+        assert_eq!(descriptor.min_filter, FilterMode::Nearest);
+    }
+
+    #[test]
+    fn anisotropy_needs_linear_filters() {
+        let linear = SamplerDesc {
+            anisotropy: 16,
+            ..Default::default()
+        };
+        assert_eq!(linear.anisotropy_clamp(), 16);
+
+        let nearest = SamplerDesc {
+            mipmap_filter: FilterMode::Nearest,
+            anisotropy: 16,
+            ..Default::default()
+        };
+        assert_eq!(nearest.anisotropy_clamp(), 1);
+
+        let too_high = SamplerDesc {
+            anisotropy: 64,
+            ..Default::default()
+        };
+        assert_eq!(too_high.anisotropy_clamp(), 16);
+    }
+
+    #[test]
+    fn storage_textures_are_bound_after_the_samplers() {
+        let desc = BindGroupLayoutDesc {
+            buffers: vec![64],
+            textures: vec![(
+                TextureSampleType::Float { filterable: true },
+                TextureViewDimension::D2,
+            )],
+            samplers: vec![SamplerBindingType::Filtering],
+            storage_textures: vec![(TextureFormat::Rgba16Float, StorageTextureAccess::WriteOnly)],
+            ..Default::default()
+        };
+        let entries = bind_group_layout_entries(&desc);
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[3].binding, 3);
+        assert!(matches!(
+            entries[3].ty,
+            wgpu::BindingType::StorageTexture {
+                access: StorageTextureAccess::WriteOnly,
+                format: TextureFormat::Rgba16Float,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn equal_bind_group_layouts_are_shared() {
+        let Some(rm) = ResourceManager::headless() else {
+            return;
+        };
+        let desc = BindGroupLayoutDesc {
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![16],
+            ..Default::default()
+        };
+
+        let first = rm.get_bind_group_layout(&desc);
+        let second = rm.get_bind_group_layout(&desc.clone());
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(rm.bind_group_layout_cache_hits(), 1);
+
+        let other = rm.get_bind_group_layout(&BindGroupLayoutDesc {
+            buffers: vec![32],
+            ..desc
+        });
+        assert!(!Rc::ptr_eq(&first, &other));
+        assert_eq!(rm.bind_group_layout_cache_hits(), 1);
+    }
+
+    #[test]
+    fn invalid_wgsl_is_shown_with_its_path() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let path = std::env::temp_dir().join(format!("invalid_{}.wgsl", std::process::id()));
+        std::fs::write(
+            &path,
+            "@vertex\nfn vs_main( -> @builtin(position) vec4<f32> {}\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let result = rm.create_shader(ShaderDesc {
+            vs: ShaderModuleDesc {
+                path: path.to_owned(),
+                entry_func: String::from("vs_main"),
+            },
+            ..Default::default()
+        });
+        assert!(matches!(
+            result,
+            Err(ResourceError::Shader(ShaderError::Validation(_)))
+        ));
+        assert!(rm.shader_compilation_error.starts_with(path));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn missing_shader_files_are_io_errors() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+
+        let result = rm.create_shader(ShaderDesc {
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/missing.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ..Default::default()
+        });
+        assert!(matches!(
+            result,
+            Err(ResourceError::Shader(ShaderError::Io(_)))
+        ));
+    }
+
+    #[test]
+    fn recompiling_reports_every_failure_and_keeps_the_old_shaders() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let temp_path = |name: &str| {
+            let path = std::env::temp_dir().join(format!(
+                "recompile_{}_{}.wgsl",
+                name,
+                std::process::id()
+            ));
+            path.to_str().unwrap().to_owned()
+        };
+        // Whether a pipeline was rebuilt shows in its includes
+        let include = temp_path("include");
+        std::fs::write(&include, "fn helper() {}\n").unwrap();
+        let with_include = format!(
+            "//!include \"{}\"\n@compute @workgroup_size(1)\nfn main() {{}}\n",
+            include
+        );
+        let valid = "@compute @workgroup_size(1)\nfn main() {}\n";
+        let invalid = "@compute @workgroup_size(1)\nfn main( {}\n";
+
+        let paths = [temp_path("first"), temp_path("second")];
+        let handles = paths.clone().map(|path| {
+            std::fs::write(&path, &with_include).unwrap();
+            rm.create_compute_shader(ComputeShaderDesc {
+                label: None,
+                module: ShaderModuleDesc {
+                    path,
+                    entry_func: String::from("main"),
+                },
+                bind_group_layouts: vec![],
+            })
+            .unwrap()
+        });
+
+        // Both failures are listed, not just the first
+        std::fs::write(&paths[0], invalid).unwrap();
+        std::fs::write(&paths[1], invalid).unwrap();
+        rm.recompile_all();
+        let errors: Vec<&str> = rm.shader_compilation_error.lines().collect();
+        assert!(errors.iter().any(|error| error.starts_with(&paths[0])));
+        assert!(errors.iter().any(|error| error.starts_with(&paths[1])));
+
+        // A fixed shader is rebuilt while the broken one keeps its last good pipeline
+        std::fs::write(&paths[1], valid).unwrap();
+        rm.recompile_many(&handles);
+        assert!(rm.shader_compilation_error.starts_with(&paths[0]));
+        assert!(!rm.shader_compilation_error.contains(&paths[1]));
+        assert_eq!(
+            rm.get_compute_shader(handles[0]).includes,
+            [include.as_str()]
+        );
+        assert!(rm.get_compute_shader(handles[1]).includes.is_empty());
+
+        for path in paths.iter().chain([&include]) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn only_new_dimensions_need_reallocation() {
+        let size = wgpu::Extent3d {
+            width: 16,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        assert!(!size_changed(size, (16, 1)));
+        assert!(size_changed(size, (32, 1)));
+        assert!(size_changed(size, (16, 2)));
+
+        // A cube map keeps its six layers through a resize
+        let cube = wgpu::Extent3d {
+            depth_or_array_layers: 6,
+            ..size
+        };
+        assert!(!size_changed(cube, (16, 1)));
+    }
+
+    #[test]
+    fn staged_updates_land_after_submitting() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let buffer = rm.create_buffer(&BufferDesc {
+            label: None,
+            byte_size: 64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
+        });
+
+        // Two frames, so the second write goes through a recalled chunk
+        for frame in 0..2u8 {
+            let data: Vec<u8> = (0..64).map(|i| i + frame).collect();
+            let mut encoder = rm
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            rm.stage_buffer_update(&mut encoder, buffer, &data);
+            rm.finish_staging();
+            rm.queue.submit(std::iter::once(encoder.finish()));
+            rm.recall_staging();
+
+            assert_eq!(rm.read_buffer(buffer), data);
+        }
+    }
+
+    #[test]
+    fn stages_from_separate_files_share_a_pipeline() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let temp_path = |name: &str| {
+            let path =
+                std::env::temp_dir().join(format!("separate_{}_{}.wgsl", name, std::process::id()));
+            path.to_str().unwrap().to_owned()
+        };
+        let vs_path = temp_path("vs");
+        let ps_path = temp_path("ps");
+        // A triangle covering the whole target
+        std::fs::write(
+            &vs_path,
+            "@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+\tlet uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+\treturn vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+",
+        )
+        .unwrap();
+        std::fs::write(
+            &ps_path,
+            "@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+\treturn vec4<f32>(1.0, 0.0, 0.0, 1.0);
+}
+",
+        )
+        .unwrap();
+
+        let shader = rm
+            .create_shader(ShaderDesc {
+                vs: ShaderModuleDesc {
+                    path: vs_path.clone(),
+                    entry_func: String::from("vs_main"),
+                },
+                ps: Some(ShaderModuleDesc {
+                    path: ps_path.clone(),
+                    entry_func: String::from("fs_main"),
+                }),
+                pipeline_state: ShaderPipelineDesc {
+                    targets: vec![TextureFormat::Rgba8Unorm],
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .unwrap();
+        // Editing either file recompiles the pipeline
+        let paths: Vec<&String> = rm.get_shader(shader).paths().collect();
+        assert_eq!(paths, [&vs_path, &ps_path]);
+
+        let target = rm.create_texture(&TextureDesc {
+            dimensions: (4, 4),
+            format: TextureFormat::Rgba8Unorm,
+            ..Default::default()
+        });
+        let mut encoder = rm
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: rm.get_texture(target).view(),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(rm.get_shader(shader).pipeline());
+            pass.draw(0..3, 0..1);
+        }
+        rm.queue.submit(std::iter::once(encoder.finish()));
+
+        let pixels = rm.read_texture(target);
+        assert!(pixels
+            .chunks_exact(4)
+            .all(|pixel| pixel == [255, 0, 0, 255]));
+
+        let _ = std::fs::remove_file(vs_path);
+        let _ = std::fs::remove_file(ps_path);
+    }
+
+    #[test]
+    fn read_buffer_returns_what_was_written() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        // Not created with COPY_SRC, read_buffer relies on create_buffer adding it
+        let buffer = rm.create_buffer(&BufferDesc {
+            label: None,
+            byte_size: 256,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
+        });
+        let data: Vec<u8> = (0..=255).collect();
+        rm.update_buffer(buffer, &data);
+
+        assert_eq!(rm.read_buffer(buffer), data);
+    }
+
+    #[test]
+    fn compute_shaders_get_compute_handles() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let path = std::env::temp_dir().join(format!("compute_{}.wgsl", std::process::id()));
+        std::fs::write(&path, "@compute @workgroup_size(1)\nfn cs_main() {}\n").unwrap();
+
+        let handle = rm
+            .create_compute_shader(ComputeShaderDesc {
+                label: None,
+                module: ShaderModuleDesc {
+                    path: path.to_str().unwrap().to_owned(),
+                    entry_func: String::from("cs_main"),
+                },
+                bind_group_layouts: vec![],
+            })
+            .unwrap();
+        assert_eq!(handle.1, HandleType::COMPUTE);
+        assert!(rm.try_get_compute_shader(handle).is_ok());
+        // Not interchangeable with render pipeline handles
+        assert!(rm.try_get_shader(handle).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
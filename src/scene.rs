@@ -1,12 +1,15 @@
-use std::path::Path;
+use std::{collections::HashSet, path::Path};
 
 use glam::{vec4, Mat4, Quat, Vec3, Vec4};
-use gltf::buffer::Data;
+use gltf::{buffer::Data, material::AlphaMode};
 use rand::Rng;
-use wgpu::ShaderStages;
+use wgpu::{vertex_attr_array, ShaderStages};
 
+use crate::frustum::{Aabb, Frustum};
 use crate::resource_manager::{
-    BindGroupDesc, BindGroupLayoutDesc, BufferDesc, BufferUsages, Handle, ResourceManager,
+    AddressMode, BindGroupDesc, BindGroupLayoutDesc, BufferDesc, BufferUsages, Handle, IndexFormat,
+    ResourceError, ResourceManager, SamplerBindingType, SamplerDesc, TextureDesc, TextureFormat,
+    TextureSampleType, TextureUsages, TextureViewDimension, VertexBufferLayout, VertexStepMode,
 };
 
 macro_rules! bytemuck_impl {
@@ -15,6 +18,7 @@ macro_rules! bytemuck_impl {
         unsafe impl bytemuck::Zeroable for $struct_name {}
     };
 }
+pub(crate) use bytemuck_impl;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -25,6 +29,16 @@ pub struct SceneUniformData {
     pub inverse_view: Mat4,
     pub camera_position: Vec3,
     pub aspect_ratio: f32,
+    pub z_near: f32,
+    pub z_far: f32,
+    /// Nonzero lets AO darken unlit materials, which are otherwise shown as is
+    pub occlude_unlit: u32,
+    pub _padding: f32,
+    /// Towards the light, world space
+    pub light_direction: Vec3,
+    pub light_intensity: f32,
+    pub light_color: Vec3,
+    pub ambient_intensity: f32,
 }
 bytemuck_impl!(SceneUniformData);
 
@@ -37,57 +51,169 @@ impl Default for SceneUniformData {
             inverse_view: Mat4::IDENTITY,
             camera_position: Vec3::ONE,
             aspect_ratio: 0.0,
+            z_near: 0.0,
+            z_far: 0.0,
+            occlude_unlit: 0,
+            _padding: 0.0,
+            light_direction: Vec3::Y,
+            light_intensity: 0.0,
+            light_color: Vec3::ONE,
+            ambient_intensity: 1.0,
         }
     }
 }
 
+impl SceneUniformData {
+    /// World to clip space, also what the temporal accumulator reprojects the next frame with.
+    pub fn view_projection(&self) -> Mat4 {
+        self.perspective * self.view
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct VertexAttributes {
     pub position: [f32; 3],
     pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    /// xyz along increasing u, w the sign of the bitangent, cross(normal, tangent) * w
+    pub tangent: [f32; 4],
 }
 bytemuck_impl!(VertexAttributes);
 
+impl VertexAttributes {
+    pub fn vertex_buffer_layout() -> VertexBufferLayout {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<VertexAttributes>() as u64,
+            step_mode: VertexStepMode::Vertex,
+            attributes: Vec::from(
+                vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x2, 3 => Float32x4],
+            ),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct MeshUniformData {
     pub model: Mat4,
-    pub random_color: Vec4,
 }
 bytemuck_impl!(MeshUniformData);
 
+/// Distance between meshes in the mesh uniform buffer, the uniforms rounded up to the offset
+/// alignment.
+fn mesh_uniform_stride(alignment: u32) -> usize {
+    let alignment = alignment as usize;
+    std::mem::size_of::<MeshUniformData>().div_ceil(alignment) * alignment
+}
+
+/// Index buffer contents for a mesh of `vertex_count` vertices. Uses 16 bit indices whenever
+/// every vertex is addressable with them, regardless of the source accessor type. Buffer writes
+/// must be 4 byte aligned, so odd u16 counts are padded.
+fn index_data(vertex_count: usize, indices: &[u32]) -> (IndexFormat, Vec<u8>) {
+    if vertex_count <= u16::MAX as usize {
+        let mut narrowed = indices.iter().map(|x| *x as u16).collect::<Vec<_>>();
+        if narrowed.len() % 2 != 0 {
+            narrowed.push(0);
+        }
+        (
+            IndexFormat::Uint16,
+            bytemuck::cast_slice::<u16, u8>(&narrowed).to_vec(),
+        )
+    } else {
+        (
+            IndexFormat::Uint32,
+            bytemuck::cast_slice::<u32, u8>(indices).to_vec(),
+        )
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialUniformData {
+    pub base_color_factor: Vec4,
+    /// Fragments with less base color alpha are discarded, 0 keeps every fragment
+    pub alpha_cutoff: f32,
+    /// Nonzero for KHR_materials_unlit, shown as the base color without lighting
+    pub unlit: u32,
+    /// Scales the X and Y of sampled normals
+    pub normal_scale: f32,
+    /// Zero leaves the vertex normals as they are
+    pub has_normal_map: u32,
+}
+bytemuck_impl!(MaterialUniformData);
+
+impl MaterialUniformData {
+    /// Just a base color, for materials that aren't read from glTF.
+    fn untextured(base_color_factor: Vec4) -> Self {
+        Self {
+            base_color_factor,
+            alpha_cutoff: 0.0,
+            unlit: 0,
+            normal_scale: 1.0,
+            has_normal_map: 0,
+        }
+    }
+}
+
 pub struct Mesh {
-    pub uniform_buffer: Handle,
-    pub bind_group: Handle,
+    pub uniforms: MeshUniformData,
+    /// Into the scene's mesh uniform buffer, set when the scene uploads it
+    pub uniform_offset: u32,
     pub vertex_buffer: Handle,
     pub index_buffer: Handle,
     pub index_count: u32,
+    pub index_format: IndexFormat,
+    pub material_bind_group: Handle,
+    /// World space bounds
+    pub aabb: Aabb,
+    /// Cleared by frustum culling, invisible meshes are skipped at draw time
+    pub visible: bool,
 }
 
 impl Mesh {
     pub fn new(
-        rm: &mut ResourceManager,
-        uniform_buffer: Handle,
+        uniforms: MeshUniformData,
         vertex_buffer: Handle,
         index_buffer: Handle,
         index_count: u32,
+        index_format: IndexFormat,
+        material_bind_group: Handle,
+        aabb: Aabb,
     ) -> Self {
-        let bind_group = rm.create_bind_group(&BindGroupDesc {
-            label: None,
-            visibility: ShaderStages::all(),
-            layout: Mesh::bind_group_layout(),
-            buffers: &[uniform_buffer],
-            textures: &[],
-            samplers: &[],
-        });
-
         Self {
-            uniform_buffer,
-            bind_group,
+            uniforms,
+            uniform_offset: 0,
             vertex_buffer,
             index_buffer,
             index_count,
+            index_format,
+            material_bind_group,
+            aabb,
+            visible: true,
+        }
+    }
+
+    /// Material uniforms, base color and normal textures and the sampler for both.
+    pub fn material_bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![std::mem::size_of::<MaterialUniformData>()],
+            textures: vec![
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+            ],
+            samplers: vec![SamplerBindingType::Filtering],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
         }
     }
 
@@ -98,150 +224,611 @@ impl Mesh {
             buffers: vec![std::mem::size_of::<MeshUniformData>()],
             textures: vec![],
             samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            // Every mesh's uniforms live in one buffer
+            dynamic_offset: true,
         }
     }
 }
 
+#[derive(Debug)]
+pub enum SceneError {
+    Obj(tobj::LoadError),
+    Gltf(gltf::Error),
+    /// A glTF primitive without the POSITION attribute
+    MissingPositions,
+    Resource(ResourceError),
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::Obj(err) => write!(f, "{}", err),
+            SceneError::Gltf(err) => write!(f, "{}", err),
+            SceneError::MissingPositions => write!(f, "A primitive has no positions"),
+            SceneError::Resource(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<tobj::LoadError> for SceneError {
+    fn from(err: tobj::LoadError) -> Self {
+        SceneError::Obj(err)
+    }
+}
+
+impl From<ResourceError> for SceneError {
+    fn from(err: ResourceError) -> Self {
+        SceneError::Resource(err)
+    }
+}
+
+impl From<gltf::Error> for SceneError {
+    fn from(err: gltf::Error) -> Self {
+        SceneError::Gltf(err)
+    }
+}
+
 pub struct Scene {
     pub scene_uniform_buffer: Handle,
     pub scene_uniform_bind_group: Handle,
     pub meshes: Vec<Mesh>,
+    /// World space bounds of every mesh, None for an empty scene
+    pub bounds: Option<Aabb>,
+    // None until there are meshes to upload
+    mesh_uniform_bind_group: Option<Handle>,
 }
 
 impl Scene {
-    fn walk_gltf(
-        rm: &mut ResourceManager,
-        node: &gltf::Node,
-        original_transform: Mat4,
-        buffers: &Vec<Data>,
-    ) -> Vec<Mesh> {
+    /// Vertices in left-handed space and indices of a glTF primitive, generating whatever
+    /// attributes besides the positions are missing.
+    fn read_primitive(
+        primitive: &gltf::Primitive,
+        buffers: &[Data],
+    ) -> Result<(Vec<VertexAttributes>, Vec<u32>), SceneError> {
+        let reader = primitive.reader(|buffer| {
+            if buffer.index() < buffers.len() {
+                Some(buffers[buffer.index()].0.as_slice())
+            } else {
+                None
+            }
+        });
+
+        // Right-handed to left-handed, mirrors z. Tangent frames flip handedness with it,
+        // so the bitangent sign flips too.
+        let positions = reader
+            .read_positions()
+            .ok_or(SceneError::MissingPositions)?
+            .map(|pos| [pos[0], pos[1], -pos[2]])
+            .collect::<Vec<_>>();
+        // Non-indexed primitives get a sequential index buffer so every mesh draws the same way
+        let indices = reader
+            .read_indices()
+            .map(|indices| indices.into_u32().collect::<Vec<_>>())
+            .unwrap_or_else(|| (0..positions.len() as u32).collect());
+        let normals = match reader.read_normals() {
+            Some(normals) => normals
+                .map(|normal| [normal[0], normal[1], -normal[2]])
+                .collect::<Vec<_>>(),
+            None => Scene::compute_normals(&positions, &indices),
+        };
+        let uvs = match reader.read_tex_coords(0) {
+            Some(uvs) => uvs.into_f32().collect::<Vec<_>>(),
+            None => vec![[0.0, 0.0]; positions.len()],
+        };
+        let tangents = match reader.read_tangents() {
+            Some(tangents) => tangents
+                .map(|tangent| [tangent[0], tangent[1], -tangent[2], -tangent[3]])
+                .collect::<Vec<_>>(),
+            None => Scene::compute_tangents(&positions, &normals, &uvs, &indices),
+        };
+
+        let vertices = positions
+            .iter()
+            .zip(normals.iter())
+            .zip(uvs.iter())
+            .zip(tangents.iter())
+            .map(|(((position, normal), uv), tangent)| VertexAttributes {
+                position: *position,
+                normal: *normal,
+                uv: *uv,
+                tangent: *tangent,
+            })
+            .collect::<Vec<_>>();
+
+        Ok((vertices, indices))
+    }
+
+    /// World transform of `node`, whose parent's world transform is `parent_transform`.
+    fn node_transform(parent_transform: Mat4, node: &gltf::Node) -> Mat4 {
         let (translation, rotation, scale) = node.transform().decomposed();
 
         let rotation_fixed = [rotation[0], rotation[1], rotation[2], rotation[3]];
         let translation_fixed = [translation[0], translation[1], translation[2]];
-        // TODO: shouldn't this be backwards? test with some scenes
-        let transform = original_transform
+        // Parent first, so the node's own transform applies to its vertices before its parents'
+        parent_transform
             * Mat4::from_scale_rotation_translation(
                 scale.into(),
                 Quat::from_array(rotation_fixed),
                 translation_fixed.into(),
-            );
+            )
+    }
+
+    fn walk_gltf(
+        rm: &mut ResourceManager,
+        node: &gltf::Node,
+        original_transform: Mat4,
+        buffers: &Vec<Data>,
+        materials: &[Handle],
+        default_material: Handle,
+    ) -> Result<Vec<Mesh>, SceneError> {
+        let transform = Scene::node_transform(original_transform, node);
 
         let mut meshes: Vec<Mesh> = Vec::new();
 
         if let Some(mesh) = node.mesh() {
             for primitive in mesh.primitives() {
-                let reader = primitive.reader(|buffer| {
-                    if buffer.index() < buffers.len() {
-                        Some(buffers[buffer.index()].0.as_slice())
-                    } else {
-                        None
-                    }
-                });
-
-                let indices = reader
-                    .read_indices()
-                    .expect("Couldn't read indices")
-                    .into_u32()
-                    .collect::<Vec<_>>();
-                let positions = reader
-                    .read_positions()
-                    .expect("Couldn't read positions")
-                    .map(|pos| [pos[0], pos[1], pos[2] * -1.0]);
-                let normals = reader
-                    .read_normals()
-                    .expect("Couldn't read normals")
-                    .map(|pos| [pos[0], pos[1], pos[2]]);
-
-                let vertices = positions
-                    .zip(normals)
-                    .map(|(position, normal)| VertexAttributes { position, normal })
-                    .collect::<Vec<_>>();
-
-                let uniform_buffer = rm.create_buffer(&BufferDesc {
-                    label: None,
-                    byte_size: std::mem::size_of::<MeshUniformData>(),
-                    usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
-                    initial_data: Some(bytemuck::cast_slice(&[MeshUniformData {
-                        model: transform,
-                        random_color: vec4(
-                            rand::thread_rng().gen_range(0.0..1.0),
-                            rand::thread_rng().gen_range(0.0..1.0),
-                            rand::thread_rng().gen_range(0.0..1.0),
-                            1.0,
-                        ),
-                    }])),
-                });
-
-                let vertex_buffer = rm.create_buffer(&BufferDesc {
-                    label: None,
-                    byte_size: vertices.len() * std::mem::size_of::<VertexAttributes>(),
-                    usage: BufferUsages::COPY_DST | BufferUsages::VERTEX,
-                    initial_data: Some(bytemuck::cast_slice(vertices.as_slice())),
-                });
+                let (vertices, indices) = Scene::read_primitive(&primitive, buffers)?;
 
-                let index_buffer = rm.create_buffer(&BufferDesc {
-                    label: None,
-                    byte_size: indices.len() * std::mem::size_of::<u32>(),
-                    usage: BufferUsages::COPY_DST | BufferUsages::INDEX,
-                    initial_data: Some(bytemuck::cast_slice(indices.as_slice())),
-                });
+                let material_bind_group = primitive
+                    .material()
+                    .index()
+                    .map_or(default_material, |index| materials[index]);
 
-                meshes.push(Mesh::new(
+                meshes.push(Scene::create_mesh(
                     rm,
-                    uniform_buffer,
-                    vertex_buffer,
-                    index_buffer,
-                    indices.len() as u32,
+                    transform,
+                    material_bind_group,
+                    &vertices,
+                    &indices,
                 ));
             }
         }
 
         for child in node.children() {
-            meshes.append(&mut Scene::walk_gltf(rm, &child, transform, buffers));
+            meshes.append(&mut Scene::walk_gltf(
+                rm,
+                &child,
+                transform,
+                buffers,
+                materials,
+                default_material,
+            )?);
         }
 
-        meshes
+        Ok(meshes)
     }
 
-    pub fn load_gltf(rm: &mut ResourceManager, path: &String) -> Self {
-        let gltf = gltf::Gltf::open(path).expect("Gltf loading failed");
-        let buffers = gltf::import_buffers(
-            &gltf.document,
-            Some(&Path::new(path).parent().unwrap_or_else(|| Path::new("./"))),
-            None,
+    fn random_color() -> Vec4 {
+        vec4(
+            rand::thread_rng().gen_range(0.0..1.0),
+            rand::thread_rng().gen_range(0.0..1.0),
+            rand::thread_rng().gen_range(0.0..1.0),
+            1.0,
         )
-        .expect("Buffer loading failed");
-        let mut meshes: Vec<Mesh> = Vec::new();
+    }
+
+    fn create_mesh(
+        rm: &mut ResourceManager,
+        transform: Mat4,
+        material_bind_group: Handle,
+        vertices: &[VertexAttributes],
+        indices: &[u32],
+    ) -> Mesh {
+        let vertex_buffer = rm.create_buffer(&BufferDesc {
+            label: None,
+            byte_size: vertices.len() * std::mem::size_of::<VertexAttributes>(),
+            usage: BufferUsages::COPY_DST | BufferUsages::VERTEX,
+            initial_data: Some(bytemuck::cast_slice(vertices)),
+        });
+
+        let (index_format, index_data) = index_data(vertices.len(), indices);
+
+        let index_buffer = rm.create_buffer(&BufferDesc {
+            label: None,
+            byte_size: index_data.len(),
+            usage: BufferUsages::COPY_DST | BufferUsages::INDEX,
+            initial_data: Some(index_data.as_slice()),
+        });
+
+        Mesh::new(
+            MeshUniformData { model: transform },
+            vertex_buffer,
+            index_buffer,
+            indices.len() as u32,
+            index_format,
+            material_bind_group,
+            Aabb::from_points(vertices.iter().map(|vertex| Vec3::from(vertex.position)))
+                .transformed(transform),
+        )
+    }
+
+    /// 1x1 textures for materials without a base color texture (white) or normal map (flat), and
+    /// the sampler every material uses.
+    fn material_defaults(rm: &mut ResourceManager) -> (Handle, Handle, Handle) {
+        let white = rm.create_texture(&TextureDesc {
+            label: Some("White"),
+            dimensions: (1, 1),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            initial_data: Some(&[255, 255, 255, 255]),
+        });
+
+        let flat_normal = rm.create_texture(&TextureDesc {
+            label: Some("Flat normal"),
+            dimensions: (1, 1),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            initial_data: Some(&[128, 128, 255, 255]),
+        });
+
+        let sampler = rm.create_sampler(SamplerDesc {
+            label: Some("Material sampler"),
+            address_mode: AddressMode::Repeat,
+            // Keeps textures sharp on surfaces seen at glancing angles
+            anisotropy: 16,
+            ..Default::default()
+        });
+
+        (white, flat_normal, sampler)
+    }
 
-        for node in gltf.nodes() {
-            meshes.append(&mut Scene::walk_gltf(rm, &node, Mat4::IDENTITY, &buffers));
+    /// `MaterialUniformData::alpha_cutoff` of a glTF material. Blended materials are drawn
+    /// opaque, there's no sorted transparent pass.
+    fn alpha_cutoff(material: &gltf::Material) -> f32 {
+        match material.alpha_mode() {
+            AlphaMode::Mask => material.alpha_cutoff().unwrap_or(0.5),
+            AlphaMode::Opaque | AlphaMode::Blend => 0.0,
         }
+    }
 
-        let scene_uniform_buffer = rm.create_buffer(&BufferDesc {
-            label: Some("Scene uniform buffer"),
-            byte_size: std::mem::size_of::<SceneUniformData>(),
+    /// `MaterialUniformData` of a glTF material, whose textures are bound alongside.
+    fn material_uniform_data(material: &gltf::Material) -> MaterialUniformData {
+        let normal_texture = material.normal_texture();
+        MaterialUniformData {
+            base_color_factor: Vec4::from_array(
+                material.pbr_metallic_roughness().base_color_factor(),
+            ),
+            alpha_cutoff: Scene::alpha_cutoff(material),
+            unlit: material.unlit() as u32,
+            normal_scale: normal_texture.as_ref().map_or(1.0, |normal| normal.scale()),
+            has_normal_map: normal_texture.is_some() as u32,
+        }
+    }
+
+    fn create_material(
+        rm: &mut ResourceManager,
+        uniforms: MaterialUniformData,
+        base_color_texture: Handle,
+        normal_texture: Handle,
+        sampler: Handle,
+    ) -> Result<Handle, ResourceError> {
+        let uniform_buffer = rm.create_buffer(&BufferDesc {
+            label: Some("Material uniform buffer"),
+            byte_size: std::mem::size_of::<MaterialUniformData>(),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            initial_data: Some(bytemuck::cast_slice(&[SceneUniformData::default()])),
+            initial_data: Some(bytemuck::cast_slice(&[uniforms])),
         });
 
-        let scene_uniform_bind_group = rm.create_bind_group(&BindGroupDesc {
+        rm.create_bind_group(&BindGroupDesc {
             label: None,
-            visibility: ShaderStages::VERTEX_FRAGMENT,
-            layout: Scene::scene_bind_group_layout(),
-            buffers: &[scene_uniform_buffer],
-            textures: &[],
-            samplers: &[],
-        });
+            visibility: ShaderStages::FRAGMENT,
+            layout: Mesh::material_bind_group_layout(),
+            buffers: &[uniform_buffer],
+            textures: &[base_color_texture, normal_texture],
+            samplers: &[sampler],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })
+    }
 
-        Self {
-            scene_uniform_buffer,
-            scene_uniform_bind_group,
-            meshes,
+    /// Expands 8 bit glTF images to RGBA, None for formats that aren't handled.
+    fn upload_image(
+        rm: &mut ResourceManager,
+        image: &gltf::image::Data,
+        srgb: bool,
+    ) -> Option<Handle> {
+        use gltf::image::Format;
+
+        let pixels: Vec<u8> = match image.format {
+            Format::R8G8B8A8 => image.pixels.clone(),
+            Format::R8G8B8 => image
+                .pixels
+                .chunks_exact(3)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+                .collect(),
+            Format::R8G8 => image
+                .pixels
+                .chunks_exact(2)
+                .flat_map(|pixel| [pixel[0], pixel[1], 0, 255])
+                .collect(),
+            Format::R8 => image
+                .pixels
+                .iter()
+                .flat_map(|value| [*value, *value, *value, 255])
+                .collect(),
+            _ => return None,
+        };
+
+        Some(rm.create_texture(&TextureDesc {
+            label: None,
+            dimensions: (image.width, image.height),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            // Base color is authored in sRGB, normal maps are linear
+            format: if srgb {
+                TextureFormat::Rgba8UnormSrgb
+            } else {
+                TextureFormat::Rgba8Unorm
+            },
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            initial_data: Some(&pixels),
+        }))
+    }
+
+    /// Averages the face normals of every triangle touching a vertex. Zero-area triangles are skipped.
+    /// Takes the already mirrored positions, whose counter-clockwise triangles now wind clockwise.
+    fn compute_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+        let mut normals = vec![Vec3::ZERO; positions.len()];
+
+        for triangle in indices.chunks_exact(3) {
+            let a = Vec3::from(positions[triangle[0] as usize]);
+            let b = Vec3::from(positions[triangle[1] as usize]);
+            let c = Vec3::from(positions[triangle[2] as usize]);
+
+            let face_normal = (c - a).cross(b - a);
+            if face_normal.length_squared() <= f32::EPSILON {
+                continue;
+            }
+
+            for index in triangle {
+                normals[*index as usize] += face_normal;
+            }
+        }
+
+        normals
+            .iter()
+            .map(|normal| normal.normalize_or_zero().to_array())
+            .collect()
+    }
+
+    /// Per vertex tangents from the UV gradients of the triangles touching it, orthogonalized
+    /// against the normal. Vertices without usable UVs get any tangent perpendicular to the normal.
+    fn compute_tangents(
+        positions: &[[f32; 3]],
+        normals: &[[f32; 3]],
+        uvs: &[[f32; 2]],
+        indices: &[u32],
+    ) -> Vec<[f32; 4]> {
+        let mut tangents = vec![Vec3::ZERO; positions.len()];
+        let mut bitangents = vec![Vec3::ZERO; positions.len()];
+
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            let edge_1 = Vec3::from(positions[b]) - Vec3::from(positions[a]);
+            let edge_2 = Vec3::from(positions[c]) - Vec3::from(positions[a]);
+            let (du_1, dv_1) = (uvs[b][0] - uvs[a][0], uvs[b][1] - uvs[a][1]);
+            let (du_2, dv_2) = (uvs[c][0] - uvs[a][0], uvs[c][1] - uvs[a][1]);
+
+            let determinant = du_1 * dv_2 - du_2 * dv_1;
+            if determinant.abs() <= f32::EPSILON {
+                continue;
+            }
+
+            let tangent = (edge_1 * dv_2 - edge_2 * dv_1) / determinant;
+            let bitangent = (edge_2 * du_1 - edge_1 * du_2) / determinant;
+            for index in triangle {
+                tangents[*index as usize] += tangent;
+                bitangents[*index as usize] += bitangent;
+            }
+        }
+
+        normals
+            .iter()
+            .zip(tangents.iter().zip(bitangents.iter()))
+            .map(|(normal, (tangent, bitangent))| {
+                let normal = Vec3::from(*normal);
+                let tangent = (*tangent - normal * normal.dot(*tangent))
+                    .try_normalize()
+                    .unwrap_or_else(|| normal.any_orthonormal_vector());
+                let sign = if normal.cross(tangent).dot(*bitangent) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                tangent.extend(sign).to_array()
+            })
+            .collect()
+    }
+
+    /// Loads a scene by dispatching on the file extension.
+    pub fn load(rm: &mut ResourceManager, path: &Path) -> Result<Self, SceneError> {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("obj") => Scene::load_obj(rm, path),
+            _ => Scene::load_gltf(rm, path),
+        }
+    }
+
+    pub fn load_obj(rm: &mut ResourceManager, path: &Path) -> Result<Self, SceneError> {
+        let (models, _) = tobj::load_obj(
+            path,
+            &tobj::LoadOptions {
+                single_index: true,
+                triangulate: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut scene = Scene::new(rm)?;
+        let (white, flat_normal, sampler) = Scene::material_defaults(rm);
+
+        for model in models {
+            let mesh = &model.mesh;
+
+            // Same right-handed to left-handed flip as the glTF path
+            let positions = mesh
+                .positions
+                .chunks_exact(3)
+                .map(|pos| [pos[0], pos[1], -pos[2]])
+                .collect::<Vec<_>>();
+            let normals = if mesh.normals.len() == mesh.positions.len() {
+                mesh.normals
+                    .chunks_exact(3)
+                    .map(|normal| [normal[0], normal[1], -normal[2]])
+                    .collect::<Vec<_>>()
+            } else {
+                Scene::compute_normals(&positions, &mesh.indices)
+            };
+            // OBJ puts the texture origin at the bottom left
+            let uvs = if mesh.texcoords.len() / 2 == positions.len() {
+                mesh.texcoords
+                    .chunks_exact(2)
+                    .map(|uv| [uv[0], 1.0 - uv[1]])
+                    .collect::<Vec<_>>()
+            } else {
+                vec![[0.0, 0.0]; positions.len()]
+            };
+
+            let vertices = positions
+                .iter()
+                .zip(normals.iter())
+                .zip(uvs.iter())
+                .map(|((position, normal), uv)| VertexAttributes {
+                    position: *position,
+                    normal: *normal,
+                    uv: *uv,
+                    // Unused, OBJ materials have no normal maps
+                    tangent: [1.0, 0.0, 0.0, 1.0],
+                })
+                .collect::<Vec<_>>();
+
+            // Materials aren't read from OBJ files, so they keep a random base color
+            let color = Scene::random_color();
+            let material = Scene::create_material(
+                rm,
+                MaterialUniformData::untextured(color),
+                white,
+                flat_normal,
+                sampler,
+            )?;
+
+            scene.meshes.push(Scene::create_mesh(
+                rm,
+                Mat4::IDENTITY,
+                material,
+                &vertices,
+                &mesh.indices,
+            ));
+        }
+
+        scene.upload_mesh_uniforms(rm)?;
+        scene.update_bounds();
+        Ok(scene)
+    }
+
+    pub fn load_gltf(rm: &mut ResourceManager, path: &Path) -> Result<Self, SceneError> {
+        let mut scene = Scene::new(rm)?;
+        scene.append_gltf(rm, path, Mat4::IDENTITY)?;
+        Ok(scene)
+    }
+
+    /// Adds the meshes of another glTF file, placed by `transform`, keeping the existing ones.
+    /// Nothing is added if any part of the file fails to load.
+    pub fn append_gltf(
+        &mut self,
+        rm: &mut ResourceManager,
+        path: &Path,
+        transform: Mat4,
+    ) -> Result<(), SceneError> {
+        let gltf = gltf::Gltf::open(path)?;
+        let base = path.parent().unwrap_or_else(|| Path::new("./"));
+        let buffers = gltf::import_buffers(&gltf.document, Some(base), None)?;
+        let images = gltf::import_images(&gltf.document, Some(base), &buffers)?;
+
+        let (white, flat_normal, sampler) = Scene::material_defaults(rm);
+        let normal_images = gltf
+            .materials()
+            .filter_map(|material| material.normal_texture())
+            .map(|normal| normal.texture().source().index())
+            .collect::<HashSet<_>>();
+        let textures = images
+            .iter()
+            .enumerate()
+            .map(|(i, image)| {
+                let is_normal_map = normal_images.contains(&i);
+                Scene::upload_image(rm, image, !is_normal_map).unwrap_or_else(|| {
+                    println!("Image {} is {:?}, using a default", i, image.format);
+                    if is_normal_map {
+                        flat_normal
+                    } else {
+                        white
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let materials = gltf
+            .materials()
+            .map(|material| {
+                let base_color_texture = material
+                    .pbr_metallic_roughness()
+                    .base_color_texture()
+                    .map_or(white, |info| textures[info.texture().source().index()]);
+                let normal_texture = material.normal_texture().map_or(flat_normal, |normal| {
+                    textures[normal.texture().source().index()]
+                });
+                Scene::create_material(
+                    rm,
+                    Scene::material_uniform_data(&material),
+                    base_color_texture,
+                    normal_texture,
+                    sampler,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let default_material = Scene::create_material(
+            rm,
+            MaterialUniformData::untextured(Vec4::ONE),
+            white,
+            flat_normal,
+            sampler,
+        )?;
+
+        // Only the roots, walk_gltf visits their children
+        let Some(gltf_scene) = gltf.default_scene().or_else(|| gltf.scenes().next()) else {
+            println!("{} has no scenes", path.display());
+            return Ok(());
+        };
+        let mut meshes = Vec::new();
+        for node in gltf_scene.nodes() {
+            meshes.append(&mut Scene::walk_gltf(
+                rm,
+                &node,
+                transform,
+                &buffers,
+                &materials,
+                default_material,
+            )?);
         }
+
+        self.meshes.append(&mut meshes);
+        self.upload_mesh_uniforms(rm)?;
+        self.update_bounds();
+        Ok(())
     }
 
-    pub fn new(rm: &mut ResourceManager) -> Self {
+    pub fn new(rm: &mut ResourceManager) -> Result<Self, ResourceError> {
         let scene_uniform_buffer = rm.create_buffer(&BufferDesc {
             label: Some("Scene uniform buffer"),
             byte_size: std::mem::size_of::<SceneUniformData>(),
@@ -251,27 +838,427 @@ impl Scene {
 
         let scene_uniform_bind_group = rm.create_bind_group(&BindGroupDesc {
             label: None,
-            visibility: ShaderStages::VERTEX_FRAGMENT,
+            visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
             layout: Scene::scene_bind_group_layout(),
             buffers: &[scene_uniform_buffer],
             textures: &[],
             samplers: &[],
-        });
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
 
-        Self {
+        Ok(Self {
             scene_uniform_buffer,
             scene_uniform_bind_group,
             meshes: vec![],
+            bounds: None,
+            mesh_uniform_bind_group: None,
+        })
+    }
+
+    /// Packs every mesh's uniforms into one buffer, each at an offset aligned for dynamic
+    /// binding.
+    fn upload_mesh_uniforms(&mut self, rm: &mut ResourceManager) -> Result<(), ResourceError> {
+        if self.meshes.is_empty() {
+            return Ok(());
+        }
+
+        let stride = mesh_uniform_stride(rm.device.limits().min_uniform_buffer_offset_alignment);
+        let mut data = vec![0u8; stride * self.meshes.len()];
+        for (i, mesh) in self.meshes.iter_mut().enumerate() {
+            let offset = i * stride;
+            data[offset..offset + std::mem::size_of::<MeshUniformData>()]
+                .copy_from_slice(bytemuck::cast_slice(&[mesh.uniforms]));
+            mesh.uniform_offset = offset as u32;
+        }
+
+        let buffer = rm.create_buffer(&BufferDesc {
+            label: Some("Mesh uniform buffer"),
+            byte_size: data.len(),
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            initial_data: Some(&data),
+        });
+
+        self.mesh_uniform_bind_group = Some(rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::all(),
+            layout: Mesh::bind_group_layout(),
+            buffers: &[buffer],
+            textures: &[],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?);
+        Ok(())
+    }
+
+    fn update_bounds(&mut self) {
+        self.bounds = self
+            .meshes
+            .iter()
+            .map(|mesh| mesh.aabb)
+            .reduce(|bounds, aabb| bounds.union(&aabb));
+    }
+
+    /// Hides meshes outside the view projection's frustum, returning how many were hidden.
+    pub fn cull(&mut self, view_projection: Mat4) -> usize {
+        let frustum = Frustum::from_view_projection(view_projection);
+        for mesh in &mut self.meshes {
+            mesh.visible = frustum.intersects(&mesh.aabb);
+        }
+        self.meshes.iter().filter(|mesh| !mesh.visible).count()
+    }
+
+    pub fn show_all(&mut self) {
+        for mesh in &mut self.meshes {
+            mesh.visible = true;
+        }
+    }
+
+    /// Binds the scene uniforms to group 0, each mesh to group 1 and its material to group 2, then
+    /// draws every mesh.
+    /// The caller sets the pipeline.
+    pub fn draw<'a>(&'a self, rm: &'a ResourceManager, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(0, rm.get_bind_group(self.scene_uniform_bind_group), &[]);
+
+        let Some(mesh_uniform_bind_group) = self.mesh_uniform_bind_group else {
+            return;
+        };
+
+        for mesh in &self.meshes {
+            if mesh.index_count == 0 || !mesh.visible {
+                continue;
+            }
+
+            pass.set_bind_group(
+                1,
+                rm.get_bind_group(mesh_uniform_bind_group),
+                &[mesh.uniform_offset],
+            );
+            pass.set_bind_group(2, rm.get_bind_group(mesh.material_bind_group), &[]);
+            pass.set_vertex_buffer(0, rm.get_buffer(mesh.vertex_buffer).slice());
+            pass.set_index_buffer(rm.get_buffer(mesh.index_buffer).slice(), mesh.index_format);
+            pass.draw_indexed(0..mesh.index_count, 0, 0..1);
         }
     }
 
     pub fn scene_bind_group_layout() -> BindGroupLayoutDesc {
         BindGroupLayoutDesc {
             label: None,
-            visibility: ShaderStages::VERTEX_FRAGMENT,
+            // Compute for the Crytek SSAO compute path
+            visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
             buffers: vec![std::mem::size_of::<SceneUniformData>()],
             textures: vec![],
             samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A unit quad facing +Z with glTF UVs, v increasing downwards
+    const QUAD_POSITIONS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+    const QUAD_UVS: [[f32; 2]; 4] = [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+    const QUAD_INDICES: [u32; 6] = [0, 1, 2, 0, 2, 3];
+
+    // A single triangle primitive with only positions, PRIMITIVE is filled in by triangle_gltf
+    const TRIANGLE_GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "byteLength": 42 }],
+        "bufferViews": [
+            { "buffer": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "accessors": [
+            {
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ],
+        "meshes": [{ "primitives": [PRIMITIVE] }]
+    }"#;
+
+    // A unit cube from the origin, corner i is at (i & 1, i >> 1 & 1, i >> 2)
+    const CUBE_POSITIONS: [[f32; 3]; 8] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [1.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [1.0, 1.0, 1.0],
+    ];
+    // Two triangles per face, outward facing with counter-clockwise winding
+    const CUBE_INDICES: [u16; 36] = [
+        0, 2, 3, 0, 3, 1, // -Z
+        4, 5, 7, 4, 7, 6, // +Z
+        0, 4, 6, 0, 6, 2, // -X
+        1, 3, 7, 1, 7, 5, // +X
+        0, 1, 5, 0, 5, 4, // -Y
+        2, 6, 7, 2, 7, 3, // +Y
+    ];
+    // One node with the cube, its buffer is cube.bin next to the file
+    const CUBE_GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "uri": "cube.bin", "byteLength": 168 }],
+        "bufferViews": [
+            { "buffer": 0, "byteLength": 96 },
+            { "buffer": 0, "byteOffset": 96, "byteLength": 72 }
+        ],
+        "accessors": [
+            {
+                "bufferView": 0, "componentType": 5126, "count": 8, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 1]
+            },
+            { "bufferView": 1, "componentType": 5123, "count": 36, "type": "SCALAR" }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+        "nodes": [{ "mesh": 0 }],
+        "scenes": [{ "nodes": [0] }],
+        "scene": 0
+    }"#;
+
+    /// Writes the cube to a fresh directory for `test`, returning the path of the .gltf file.
+    fn write_cube_gltf(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("scene_{}_{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut buffer = bytemuck::cast_slice::<[f32; 3], u8>(&CUBE_POSITIONS).to_vec();
+        buffer.extend_from_slice(bytemuck::cast_slice::<u16, u8>(&CUBE_INDICES));
+        std::fs::write(dir.join("cube.bin"), buffer).unwrap();
+        std::fs::write(dir.join("cube.gltf"), CUBE_GLTF).unwrap();
+        dir.join("cube.gltf")
+    }
+
+    /// The triangle (0, 0, 0), (1, 0, 0), (0, 1, 0), drawn with u16 indices when `indexed`.
+    fn triangle_gltf(indexed: bool) -> (gltf::Gltf, Vec<Data>) {
+        let primitive = if indexed {
+            r#"{ "attributes": { "POSITION": 0 }, "indices": 1 }"#
+        } else {
+            r#"{ "attributes": { "POSITION": 0 } }"#
+        };
+        let gltf = gltf::Gltf::from_slice(TRIANGLE_GLTF.replace("PRIMITIVE", primitive).as_bytes())
+            .unwrap();
+
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let mut buffer = bytemuck::cast_slice::<[f32; 3], u8>(&positions).to_vec();
+        buffer.extend_from_slice(bytemuck::cast_slice::<u16, u8>(&[0, 1, 2]));
+
+        (gltf, vec![Data(buffer)])
+    }
+
+    #[test]
+    fn mesh_uniforms_are_aligned_up_to_the_offset_alignment() {
+        let size = std::mem::size_of::<MeshUniformData>();
+        assert_eq!(size, 64);
+
+        assert_eq!(mesh_uniform_stride(256), 256);
+        assert_eq!(mesh_uniform_stride(64), 64);
+        assert_eq!(mesh_uniform_stride(32), 64);
+        for alignment in [16, 32, 64, 256] {
+            let stride = mesh_uniform_stride(alignment);
+            assert_eq!(stride % alignment as usize, 0);
+            assert!(stride >= size);
+        }
+    }
+
+    #[test]
+    fn small_meshes_use_16_bit_indices() {
+        let (format, data) = index_data(QUAD_POSITIONS.len(), &QUAD_INDICES);
+        assert_eq!(format, IndexFormat::Uint16);
+        assert_eq!(data, bytemuck::cast_slice::<u16, u8>(&[0, 1, 2, 0, 2, 3]));
+
+        // Padded to 4 bytes
+        let (_, data) = index_data(3, &[0, 1, 2]);
+        assert_eq!(data, bytemuck::cast_slice::<u16, u8>(&[0, 1, 2, 0]));
+    }
+
+    #[test]
+    fn large_meshes_keep_32_bit_indices() {
+        let (format, data) = index_data(u16::MAX as usize + 1, &[0, 1, 65535]);
+        assert_eq!(format, IndexFormat::Uint32);
+        assert_eq!(data, bytemuck::cast_slice::<u32, u8>(&[0, 1, 65535]));
+    }
+
+    #[test]
+    fn generated_normals_face_out_of_mirrored_quads() {
+        // Counter-clockwise towards +Z before the flip, so the face normal ends up along -Z
+        let mirrored = QUAD_POSITIONS.map(|pos| [pos[0], pos[1], -pos[2]]);
+        for normal in Scene::compute_normals(&mirrored, &QUAD_INDICES) {
+            assert!((Vec3::from(normal) - Vec3::NEG_Z).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles_are_skipped() {
+        let positions = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [2.0, 0.0, 0.0],
+        ];
+        // The second triangle is collinear and the only one touching the last vertex
+        let indices = [0, 1, 2, 0, 1, 3];
+        let normals = Scene::compute_normals(&positions, &indices);
+
+        assert!((Vec3::from(normals[0]) - Vec3::NEG_Z).length() < 1e-5);
+        assert_eq!(normals[3], [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn tangents_follow_u_with_the_bitangent_sign() {
+        let normals = [[0.0, 0.0, 1.0]; 4];
+        let tangents = Scene::compute_tangents(&QUAD_POSITIONS, &normals, &QUAD_UVS, &QUAD_INDICES);
+
+        for tangent in tangents {
+            // cross(normal, tangent) is +Y, but v increases towards -Y
+            assert!((Vec4::from(tangent) - vec4(1.0, 0.0, 0.0, -1.0)).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn tangents_without_uvs_are_perpendicular() {
+        let normals = [[0.0, 0.0, 1.0]; 4];
+        let uvs = [[0.0, 0.0]; 4];
+        let tangents = Scene::compute_tangents(&QUAD_POSITIONS, &normals, &uvs, &QUAD_INDICES);
+
+        for tangent in tangents {
+            let tangent = Vec4::from(tangent);
+            assert!(tangent.truncate().dot(Vec3::Z).abs() < 1e-5);
+            assert!((tangent.truncate().length() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn primitives_without_uvs_get_zeroed_uvs() {
+        let (gltf, buffers) = triangle_gltf(true);
+        let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+        let (vertices, indices) = Scene::read_primitive(&primitive, &buffers).unwrap();
+
+        assert_eq!(indices, [0, 1, 2]);
+        for vertex in vertices {
+            assert_eq!(vertex.uv, [0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn non_indexed_primitives_get_sequential_indices() {
+        let (gltf, buffers) = triangle_gltf(false);
+        let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+        let (vertices, indices) = Scene::read_primitive(&primitive, &buffers).unwrap();
+
+        assert_eq!(vertices.len(), 3);
+        assert_eq!(indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn masked_materials_keep_their_cutoff() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "materials": [
+                    { "alphaMode": "MASK", "alphaCutoff": 0.25 },
+                    { "alphaMode": "MASK" },
+                    { "alphaMode": "BLEND", "alphaCutoff": 0.25 },
+                    {}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let cutoffs = gltf
+            .materials()
+            .map(|material| Scene::alpha_cutoff(&material))
+            .collect::<Vec<_>>();
+
+        assert_eq!(cutoffs, [0.25, 0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn base_color_factor_reaches_the_material() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "materials": [
+                    { "pbrMetallicRoughness": { "baseColorFactor": [0.5, 0.25, 1, 0.75] } },
+                    {}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let factors = gltf
+            .materials()
+            .map(|material| Scene::material_uniform_data(&material).base_color_factor)
+            .collect::<Vec<_>>();
+
+        assert_eq!(factors, [Vec4::new(0.5, 0.25, 1.0, 0.75), Vec4::ONE]);
+    }
+
+    #[test]
+    fn child_nodes_apply_their_transform_before_the_parents() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "nodes": [
+                    { "children": [1], "translation": [1, 0, 0] },
+                    { "scale": [2, 2, 2] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let parent = gltf.nodes().next().unwrap();
+        let child = parent.children().next().unwrap();
+
+        let parent_transform = Scene::node_transform(Mat4::IDENTITY, &parent);
+        let child_transform = Scene::node_transform(parent_transform, &child);
+
+        assert_eq!(
+            parent_transform.transform_point3(Vec3::X),
+            Vec3::new(2.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            child_transform.transform_point3(Vec3::X),
+            Vec3::new(3.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn meshes_count_their_indices() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let scene = Scene::load_gltf(&mut rm, &write_cube_gltf("index_count")).unwrap();
+
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].index_count, 36);
+    }
+
+    #[test]
+    fn appending_a_gltf_adds_its_meshes() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let path = write_cube_gltf("append");
+        let mut scene = Scene::load_gltf(&mut rm, &path).unwrap();
+        scene
+            .append_gltf(&mut rm, &path, Mat4::from_translation(Vec3::X * 10.0))
+            .unwrap();
+
+        assert_eq!(scene.meshes.len(), 2);
+        assert_eq!(scene.meshes[1].index_count, 36);
+        // The second cube is placed by the transform, so the bounds grow to cover it
+        assert!(scene.bounds.unwrap().max.x > 10.0);
+    }
+}
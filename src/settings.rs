@@ -0,0 +1,225 @@
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::AoTechniqueKind;
+
+/// Read from the working directory at startup.
+pub const SETTINGS_PATH: &str = "settings.json";
+
+const USAGE: &str = "Usage: ssao-techniques [--scene <path>] [--technique <name>] \
+                         [--width <pixels>] [--height <pixels>] [--backend <name>]";
+
+/// Startup options, any field missing from the file keeps its default. Command line arguments
+/// take precedence over the file.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub width: u32,
+    pub height: u32,
+    /// dx12, vulkan, metal, gl or primary. When unset the SSAO_BACKEND environment variable picks
+    /// it, then primary.
+    pub backend: Option<String>,
+    pub vsync: bool,
+    /// glTF or OBJ file loaded once the renderer is created
+    pub scene: Option<String>,
+    pub ao_technique: AoTechniqueKind,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            width: 1600,
+            height: 900,
+            backend: None,
+            vsync: true,
+            scene: None,
+            ao_technique: AoTechniqueKind::None,
+        }
+    }
+}
+
+impl Settings {
+    /// Falls back to the defaults when the file is missing or can't be parsed.
+    pub fn load(path: &str) -> Self {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Settings::default(),
+            Err(err) => {
+                println!("Couldn't read {}, using default settings: {}", path, err);
+                return Settings::default();
+            }
+        };
+
+        match serde_json::from_str::<Settings>(&json) {
+            Ok(settings) => settings.validated(),
+            Err(err) => {
+                println!("Couldn't parse {}, using default settings: {}", path, err);
+                Settings::default()
+            }
+        }
+    }
+
+    /// Overrides fields with `--scene`, `--technique`, `--width`, `--height` and `--backend`
+    /// from the command line arguments, not including the program name.
+    pub fn apply_args(mut self, args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--scene" => self.scene = Some(value()?),
+                "--technique" => {
+                    let name = value()?;
+                    self.ao_technique = AoTechniqueKind::ALL
+                        .into_iter()
+                        .find(|technique| technique.id() == name)
+                        .ok_or_else(|| {
+                            let valid = AoTechniqueKind::ALL.map(AoTechniqueKind::id);
+                            format!(
+                                "Unknown technique {}, expected one of: {}",
+                                name,
+                                valid.join(", ")
+                            )
+                        })?;
+                }
+                "--width" => self.width = parse_size(&arg, &value()?)?,
+                "--height" => self.height = parse_size(&arg, &value()?)?,
+                "--backend" => self.backend = Some(value()?),
+                _ => return Err(format!("Unknown argument {}\n{}", arg, USAGE)),
+            }
+        }
+        Ok(self.validated())
+    }
+
+    fn validated(mut self) -> Self {
+        if self.width == 0 || self.height == 0 {
+            let default = Settings::default();
+            println!(
+                "Window size {}x{} is empty, using {}x{}",
+                self.width, self.height, default.width, default.height
+            );
+            self.width = default.width;
+            self.height = default.height;
+        }
+        self
+    }
+}
+
+fn parse_size(arg: &str, value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a size in pixels, got {}", arg, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `json` through a settings file in the temp directory.
+    fn load_json(test: &str, json: &str) -> Settings {
+        let path = std::env::temp_dir().join(format!(
+            "ssao_settings_{}_{}.json",
+            test,
+            std::process::id()
+        ));
+        std::fs::write(&path, json).unwrap();
+        let settings = Settings::load(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        settings
+    }
+
+    #[test]
+    fn partial_file_merges_over_defaults() {
+        let settings = load_json(
+            "partial",
+            r#"{ "width": 1280, "vsync": false, "ao_technique": "crytek" }"#,
+        );
+        let default = Settings::default();
+
+        assert_eq!(settings.width, 1280);
+        assert!(!settings.vsync);
+        assert!(settings.ao_technique == AoTechniqueKind::Crytek);
+
+        assert_eq!(settings.height, default.height);
+        assert_eq!(settings.backend, default.backend);
+        assert_eq!(settings.scene, default.scene);
+    }
+
+    #[test]
+    fn malformed_file_uses_defaults() {
+        let settings = load_json("malformed", r#"{ "width": "wide" }"#);
+        assert_eq!(settings.width, Settings::default().width);
+    }
+
+    #[test]
+    fn missing_file_uses_defaults() {
+        let settings = Settings::load("this/settings/file/does/not/exist.json");
+        assert_eq!(settings.width, Settings::default().width);
+        assert!(settings.vsync);
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn args_override_settings() {
+        let settings = Settings::default()
+            .apply_args(args(&[
+                "--scene",
+                "models/sponza.gltf",
+                "--technique",
+                "visibility_bitmask",
+                "--width",
+                "800",
+                "--height",
+                "600",
+                "--backend",
+                "vulkan",
+            ]))
+            .unwrap();
+
+        assert_eq!(settings.scene.as_deref(), Some("models/sponza.gltf"));
+        assert!(settings.ao_technique == AoTechniqueKind::VisibilityBitmask);
+        assert_eq!((settings.width, settings.height), (800, 600));
+        assert_eq!(settings.backend.as_deref(), Some("vulkan"));
+    }
+
+    #[test]
+    fn no_args_keep_settings() {
+        let settings = Settings::default().apply_args(args(&[])).unwrap();
+        let default = Settings::default();
+        assert_eq!(settings.width, default.width);
+        assert_eq!(settings.scene, default.scene);
+        assert!(settings.ao_technique == default.ao_technique);
+    }
+
+    #[test]
+    fn unknown_technique_lists_the_valid_ones() {
+        let Err(err) = Settings::default().apply_args(args(&["--technique", "hbao"])) else {
+            panic!("hbao isn't a technique");
+        };
+        for technique in AoTechniqueKind::ALL {
+            assert!(err.contains(technique.id()), "{}", err);
+        }
+    }
+
+    #[test]
+    fn bad_args_are_errors() {
+        assert!(Settings::default().apply_args(args(&["--width"])).is_err());
+        assert!(Settings::default()
+            .apply_args(args(&["--width", "wide"]))
+            .is_err());
+        assert!(Settings::default()
+            .apply_args(args(&["--fullscreen"]))
+            .is_err());
+    }
+
+    #[test]
+    fn empty_window_size_uses_the_default_size() {
+        let settings = load_json("empty_size", r#"{ "width": 0, "height": 600 }"#);
+        let default = Settings::default();
+        assert_eq!(
+            (settings.width, settings.height),
+            (default.width, default.height)
+        );
+    }
+}
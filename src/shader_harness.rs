@@ -0,0 +1,130 @@
+//! Runs functions from the shader files on the GPU, so tests check the WGSL that ships rather than
+//! a Rust copy of it.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use glam::{Mat4, Vec4};
+use wgpu::ShaderStages;
+
+use crate::resource_manager::{
+    BindGroupDesc, BindGroupLayoutDesc, BufferDesc, BufferUsages, ComputeShaderDesc,
+    ResourceManager, ShaderModuleDesc,
+};
+
+// Includes the shader under test, whose own bindings are left unbound since only harness_main is
+// compiled into the pipeline. Group 3 is past every group the shaders use.
+const HARNESS: &str = "//!include \"SHADER\"
+
+@group(3) @binding(0) var<storage, read_write> harness_inputs: array<vec4<f32>>;
+@group(3) @binding(1) var<storage, read_write> harness_outputs: array<vec4<f32>>;
+
+@compute @workgroup_size(1)
+fn harness_main(@builtin(global_invocation_id) id: vec3<u32>) {
+	let input = harness_inputs[id.x];
+	harness_outputs[id.x] = EXPRESSION;
+}
+";
+
+/// Evaluates `expression` once per input, in a compute shader including the shader at `path`.
+/// The expression sees its input as `input: vec4<f32>` and has to be a `vec4<f32>`.
+pub fn eval(rm: &mut ResourceManager, path: &str, expression: &str, inputs: &[Vec4]) -> Vec<Vec4> {
+    static HARNESS_COUNT: AtomicUsize = AtomicUsize::new(0);
+    let harness_path = std::env::temp_dir().join(format!(
+        "shader_harness_{}_{}.wgsl",
+        std::process::id(),
+        HARNESS_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let shader_path = std::fs::canonicalize(path).unwrap();
+    std::fs::write(
+        &harness_path,
+        HARNESS
+            .replace("SHADER", shader_path.to_str().unwrap())
+            .replace("EXPRESSION", expression),
+    )
+    .unwrap();
+
+    let empty_layout = BindGroupLayoutDesc {
+        visibility: ShaderStages::COMPUTE,
+        ..Default::default()
+    };
+    let harness_layout = BindGroupLayoutDesc {
+        visibility: ShaderStages::COMPUTE,
+        storage_buffers: vec![0, 0],
+        ..Default::default()
+    };
+    let shader = rm
+        .create_compute_shader(ComputeShaderDesc {
+            label: Some(String::from("Shader harness")),
+            module: ShaderModuleDesc {
+                path: harness_path.to_str().unwrap().to_owned(),
+                entry_func: String::from("harness_main"),
+            },
+            bind_group_layouts: vec![
+                empty_layout.clone(),
+                empty_layout.clone(),
+                empty_layout.clone(),
+                harness_layout.clone(),
+            ],
+        })
+        .unwrap_or_else(|err| panic!("Couldn't build the harness for {}: {}", path, err));
+
+    let inputs: Vec<[f32; 4]> = inputs.iter().map(|input| input.to_array()).collect();
+    let byte_size = std::mem::size_of_val(inputs.as_slice());
+    let input_buffer = rm.create_buffer(&BufferDesc {
+        label: Some("Harness inputs"),
+        byte_size,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        initial_data: Some(bytemuck::cast_slice(&inputs)),
+    });
+    let output_buffer = rm.create_buffer(&BufferDesc {
+        label: Some("Harness outputs"),
+        byte_size,
+        usage: BufferUsages::STORAGE,
+        initial_data: None,
+    });
+    let empty_bind_group = rm
+        .create_bind_group(&BindGroupDesc {
+            visibility: ShaderStages::COMPUTE,
+            layout: empty_layout,
+            ..Default::default()
+        })
+        .unwrap();
+    let harness_bind_group = rm
+        .create_bind_group(&BindGroupDesc {
+            visibility: ShaderStages::COMPUTE,
+            layout: harness_layout,
+            storage_buffers: &[input_buffer, output_buffer],
+            ..Default::default()
+        })
+        .unwrap();
+
+    let mut encoder = rm
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        pass.set_pipeline(rm.get_compute_shader(shader).pipeline());
+        for group in 0..3 {
+            pass.set_bind_group(group, rm.get_bind_group(empty_bind_group), &[]);
+        }
+        pass.set_bind_group(3, rm.get_bind_group(harness_bind_group), &[]);
+        pass.dispatch_workgroups(inputs.len() as u32, 1, 1);
+    }
+    rm.queue.submit(std::iter::once(encoder.finish()));
+    let _ = std::fs::remove_file(&harness_path);
+
+    rm.read_buffer(output_buffer)
+        .chunks_exact(std::mem::size_of::<Vec4>())
+        .map(|output| Vec4::from(bytemuck::pod_read_unaligned::<[f32; 4]>(output)))
+        .collect()
+}
+
+/// `matrix` as a WGSL expression, for passing matrices to functions in `eval`'s expression.
+pub fn mat4(matrix: Mat4) -> String {
+    let columns: Vec<String> = matrix
+        .to_cols_array()
+        .iter()
+        .map(|x| format!("{:?}", x))
+        .collect();
+    format!("mat4x4<f32>({})", columns.join(", "))
+}
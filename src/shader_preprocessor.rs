@@ -0,0 +1,166 @@
+use std::path::{Path, PathBuf};
+
+use crate::resource_manager::ShaderError;
+
+const INCLUDE_DIRECTIVE: &str = "//!include";
+
+/// WGSL source with `//!include "file.wgsl"` lines replaced by the file's contents.
+pub struct PreprocessedShader {
+    pub source: String,
+    /// Every file pulled in by an include, for hot reloading
+    pub includes: Vec<String>,
+}
+
+/// Include paths are relative to the including file. Each file is only inlined the first time
+/// it's included, so shared files can be included from several places.
+pub fn preprocess(path: &str) -> Result<PreprocessedShader, ShaderError> {
+    let mut source = String::new();
+    let mut stack = vec![];
+    let mut included = vec![];
+    inline_file(Path::new(path), &mut stack, &mut included, &mut source)?;
+
+    Ok(PreprocessedShader {
+        source,
+        includes: included
+            .into_iter()
+            .skip(1)
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect(),
+    })
+}
+
+fn inline_file(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    included: &mut Vec<PathBuf>,
+    output: &mut String,
+) -> Result<(), ShaderError> {
+    let canonical = std::fs::canonicalize(path).map_err(ShaderError::Io)?;
+    if stack.contains(&canonical) {
+        let cycle = stack
+            .iter()
+            .skip_while(|file| **file != canonical)
+            .chain(std::iter::once(&canonical))
+            .map(|file| file.display().to_string())
+            .collect::<Vec<_>>();
+        return Err(ShaderError::IncludeCycle(cycle));
+    }
+    if included.contains(&canonical) {
+        return Ok(());
+    }
+
+    let source = std::fs::read_to_string(path).map_err(ShaderError::Io)?;
+    stack.push(canonical.clone());
+    included.push(canonical);
+
+    for line in source.lines() {
+        match line.trim().strip_prefix(INCLUDE_DIRECTIVE) {
+            Some(include) => {
+                let include = include.trim().trim_matches('"');
+                let include_path = path.parent().unwrap_or(Path::new("")).join(include);
+                inline_file(&include_path, stack, included, output)?;
+            }
+            None => {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+    }
+
+    stack.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding `files`, as (name, contents).
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "shader_preprocessor_{}_{}",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn inlines_included_functions() {
+        let dir = write_files(
+            "inline",
+            &[
+                (
+                    "main.wgsl",
+                    "//!include \"common.wgsl\"\nfn main() -> f32 {\n\treturn half(1.0);\n}\n",
+                ),
+                (
+                    "common.wgsl",
+                    "fn half(x: f32) -> f32 {\n\treturn x * 0.5;\n}\n",
+                ),
+            ],
+        );
+
+        let shader = preprocess(dir.join("main.wgsl").to_str().unwrap()).unwrap();
+        assert_eq!(
+            shader.source,
+            "fn half(x: f32) -> f32 {\n\treturn x * 0.5;\n}\nfn main() -> f32 {\n\treturn half(1.0);\n}\n"
+        );
+        assert_eq!(shader.includes.len(), 1);
+        assert!(shader.includes[0].ends_with("common.wgsl"));
+    }
+
+    #[test]
+    fn includes_shared_files_once() {
+        let dir = write_files(
+            "once",
+            &[
+                (
+                    "main.wgsl",
+                    "//!include \"a.wgsl\"\n//!include \"common.wgsl\"\n",
+                ),
+                ("a.wgsl", "//!include \"common.wgsl\"\nfn a() {}\n"),
+                ("common.wgsl", "fn common() {}\n"),
+            ],
+        );
+
+        let shader = preprocess(dir.join("main.wgsl").to_str().unwrap()).unwrap();
+        assert_eq!(shader.source, "fn common() {}\nfn a() {}\n");
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("a.wgsl", "//!include \"b.wgsl\"\n"),
+                ("b.wgsl", "//!include \"a.wgsl\"\n"),
+            ],
+        );
+
+        match preprocess(dir.join("a.wgsl").to_str().unwrap()) {
+            Err(ShaderError::IncludeCycle(cycle)) => {
+                assert_eq!(cycle.len(), 3);
+                assert!(cycle[0].ends_with("a.wgsl"));
+                assert!(cycle[1].ends_with("b.wgsl"));
+                assert!(cycle[2].ends_with("a.wgsl"));
+            }
+            _ => panic!("expected an include cycle"),
+        }
+    }
+
+    #[test]
+    fn missing_files_are_io_errors() {
+        let dir = write_files("missing", &[]);
+
+        assert!(matches!(
+            preprocess(dir.join("missing.wgsl").to_str().unwrap()),
+            Err(ShaderError::Io(_))
+        ));
+    }
+}
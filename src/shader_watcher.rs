@@ -0,0 +1,87 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    time::{Duration, Instant},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Editors often write a file several times per save, wait this long after the last write
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Watches the directories containing shader files and reports which files changed.
+pub struct ShaderWatcher {
+    watcher: RecommendedWatcher,
+    receiver: Receiver<PathBuf>,
+    // Directories rather than files, so editors that save by replacing the file still trigger
+    watched_dirs: HashSet<PathBuf>,
+    // Last write time of every changed file not yet reported
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let (sender, receiver) = channel();
+        let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            if let Ok(event) = result {
+                if matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_)) {
+                    for path in event.paths {
+                        _ = sender.send(path);
+                    }
+                }
+            }
+        })?;
+
+        Ok(Self {
+            watcher,
+            receiver,
+            watched_dirs: HashSet::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn watch(&mut self, path: &str) {
+        let Some(dir) = std::fs::canonicalize(path)
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+        else {
+            return;
+        };
+
+        if self.watched_dirs.contains(&dir) {
+            return;
+        }
+
+        match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+            Ok(()) => {
+                self.watched_dirs.insert(dir);
+            }
+            Err(err) => println!("Couldn't watch {}: {}", dir.display(), err),
+        }
+    }
+
+    /// Canonical paths of files that changed and have since settled.
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        for path in self.receiver.try_iter() {
+            self.pending.insert(path, now);
+        }
+
+        let settled: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, last_write)| now - **last_write >= DEBOUNCE)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in &settled {
+            self.pending.remove(path);
+        }
+
+        settled
+            .into_iter()
+            .filter_map(|path| std::fs::canonicalize(path).ok())
+            .collect()
+    }
+}
@@ -0,0 +1,22 @@
+// Adds the ambient lighting, occluded by the AO technique if any, onto the directly lit color
+@group(0) @binding(0) var ambient: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	// Alpha replaces the coverage alpha to coverage left behind, so the viewport stays opaque
+	return vec4<f32>(textureLoad(ambient, vec2<i32>(floor(position.xy)), 0).rgb, 1.0);
+}
@@ -0,0 +1,26 @@
+// Shared by the shaders that bind the scene uniforms, pulled in with //!include "common.wgsl"
+
+struct SceneUniforms {
+	perspective: mat4x4<f32>,
+	view: mat4x4<f32>,
+	inverse_perspective: mat4x4<f32>,
+	inverse_view: mat4x4<f32>,
+	camera_position: vec3<f32>,
+	aspect_ratio: f32,
+	z_near: f32,
+	z_far: f32,
+	// Nonzero lets AO darken unlit materials
+	occlude_unlit: u32,
+	// Towards the light, world space
+	light_direction: vec3<f32>,
+	light_intensity: f32,
+	light_color: vec3<f32>,
+	ambient_intensity: f32,
+}
+
+// uv has its origin at the top left, depth is the hardware depth in either Z convention. Mirrored
+// by camera::view_position_from_depth on the CPU.
+fn view_position_from_depth(uv: vec2<f32>, depth: f32, inverse_perspective: mat4x4<f32>) -> vec3<f32> {
+	let position = inverse_perspective * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+	return position.xyz / position.w;
+}
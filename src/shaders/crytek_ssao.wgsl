@@ -1,3 +1,30 @@
+//!include "common.wgsl"
+
+struct SSAOUniforms {
+	normal_bias: f32,
+	flip_normals: u32,
+	noise_scale: vec2<f32>,
+	radius: f32,
+	range_check: f32,
+	// Texels in the samples texture
+	sample_count: u32,
+	// 0 reads the noise texture, 1 uses interleaved_gradient_noise
+	rotation_noise: u32,
+}
+
+@group(0) @binding(0) var<uniform> scene: SceneUniforms;
+@group(1) @binding(0) var<uniform> params: SSAOUniforms;
+@group(1) @binding(1) var samples: texture_2d<f32>;
+@group(1) @binding(2) var noise: texture_2d<f32>;
+@group(1) @binding(3) var depth_buffer: texture_depth_2d;
+@group(1) @binding(4) var depth_sampler: sampler;
+@group(1) @binding(5) var noise_sampler: sampler;
+// Only bound for cs_main
+@group(1) @binding(6) var output: texture_storage_2d<rgba8unorm, write>;
+
+const DEPTH_BIAS: f32 = 0.025;
+const PI: f32 = 3.14159265;
+
 @vertex
 fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
 	var vertex_positions = array<vec2<f32>, 6>(
@@ -12,9 +39,136 @@ fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
 	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
 }
 
+fn view_position(pixel: vec2<i32>) -> vec3<f32> {
+	let dimensions = vec2<f32>(textureDimensions(depth_buffer));
+	let uv = (vec2<f32>(pixel) + 0.5) / dimensions;
+	let depth = textureLoad(depth_buffer, pixel, 0);
+
+	return view_position_from_depth(uv, depth, scene.inverse_perspective);
+}
+
+// With the left-handed view space and y-down pixels this faces the camera, flip_normals is for
+// conventions where the derivative cross product comes out the other way around
+fn reconstruct_normal(origin: vec3<f32>) -> vec3<f32> {
+	let normal = normalize(cross(dpdx(origin), dpdy(origin)));
+	if (params.flip_normals != 0u) {
+		return -normal;
+	}
+	return normal;
+}
+
+// Compute shaders have no derivatives, so the neighbours are read directly. Same orientation as
+// reconstruct_normal.
+fn reconstruct_normal_from_neighbours(pixel: vec2<i32>, origin: vec3<f32>) -> vec3<f32> {
+	let dimensions = vec2<i32>(textureDimensions(depth_buffer));
+	// Backward differences at the right and bottom edges, negated to keep the same direction
+	var dx = view_position(min(pixel + vec2<i32>(1, 0), dimensions - 1)) - origin;
+	if (pixel.x == dimensions.x - 1) {
+		dx = origin - view_position(pixel - vec2<i32>(1, 0));
+	}
+	var dy = view_position(min(pixel + vec2<i32>(0, 1), dimensions - 1)) - origin;
+	if (pixel.y == dimensions.y - 1) {
+		dy = origin - view_position(pixel - vec2<i32>(0, 1));
+	}
+
+	let normal = normalize(cross(dx, dy));
+	if (params.flip_normals != 0u) {
+		return -normal;
+	}
+	return normal;
+}
+
+// Green where the reconstructed normal faces the camera, red where it faces away
+@fragment
+fn fs_facing(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let origin = view_position(vec2<i32>(floor(position.xy)));
+	let normal = reconstruct_normal(origin);
+
+	if (dot(normal, -origin) > 0.0) {
+		return vec4<f32>(0.0, 1.0, 0.0, 1.0);
+	}
+	return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+}
+
+// Jimenez 2014, "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+fn interleaved_gradient_noise(pixel: vec2<f32>) -> f32 {
+	return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// AO at pixel, rotating the kernel by rotation, a direction in the tangent plane
+fn ambient_occlusion(pixel: vec2<i32>, origin: vec3<f32>, normal: vec3<f32>, rotation: vec3<f32>) -> f32 {
+	let dimensions = vec2<i32>(textureDimensions(depth_buffer));
+
+	let tangent = normalize(rotation - normal * dot(rotation, normal));
+	let bitangent = cross(normal, tangent);
+	let tbn = mat3x3<f32>(tangent, bitangent, normal);
+
+	var occlusion = 0.0;
+	for (var i = 0; i < i32(params.sample_count); i++) {
+		var direction = tbn * textureLoad(samples, vec2<i32>(i, 0), 0).xyz;
+		// Pull samples toward the normal itself
+		direction = mix(direction, normal, params.normal_bias);
+
+		let sample_position = origin + direction * params.radius;
+		let clip = scene.perspective * vec4<f32>(sample_position, 1.0);
+		let ndc = clip.xy / clip.w;
+		let sample_pixel = vec2<i32>(vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * vec2<f32>(dimensions));
+
+		if (any(sample_pixel < vec2<i32>(0)) || any(sample_pixel >= dimensions)) {
+			continue;
+		}
+
+		let sample_depth = view_position(sample_pixel).z;
+		if (sample_depth < sample_position.z - DEPTH_BIAS) {
+			// Fades out occluders far in front of the origin
+			occlusion += smoothstep(0.0, 1.0, params.range_check / abs(origin.z - sample_depth));
+		}
+	}
+
+	return 1.0 - occlusion / f32(params.sample_count);
+}
+
+fn gradient_rotation(pixel: vec2<i32>) -> vec3<f32> {
+	let angle = 2.0 * PI * interleaved_gradient_noise(vec2<f32>(pixel));
+	return vec3<f32>(cos(angle), sin(angle), 0.0);
+}
+
 @fragment
 fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let dimensions = vec2<i32>(textureDimensions(depth_buffer));
+	let pixel = vec2<i32>(floor(position.xy));
+	let origin = view_position(pixel);
+	let normal = reconstruct_normal(origin);
+
+	// Per-pixel random rotation around the normal, tiled across the screen
+	let uv = position.xy / vec2<f32>(dimensions);
+	var rotation = textureSample(noise, noise_sampler, uv * params.noise_scale).xyz;
+	if (params.rotation_noise == 1u) {
+		rotation = gradient_rotation(pixel);
+	}
 
-	return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+	let ao = ambient_occlusion(pixel, origin, normal, rotation);
+	return vec4<f32>(ao, ao, ao, 1.0);
 }
 
+// Same as fs_main, writing to output instead of a render target
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+	let dimensions = vec2<i32>(textureDimensions(output));
+	let pixel = vec2<i32>(id.xy);
+	if (any(pixel >= dimensions)) {
+		return;
+	}
+
+	let origin = view_position(pixel);
+	let normal = reconstruct_normal_from_neighbours(pixel, origin);
+
+	let uv = (vec2<f32>(pixel) + 0.5) / vec2<f32>(dimensions);
+	var rotation = textureSampleLevel(noise, noise_sampler, uv * params.noise_scale, 0.0).xyz;
+	if (params.rotation_noise == 1u) {
+		rotation = gradient_rotation(pixel);
+	}
+
+	let ao = ambient_occlusion(pixel, origin, normal, rotation);
+	textureStore(output, pixel, vec4<f32>(ao, ao, ao, 1.0));
+}
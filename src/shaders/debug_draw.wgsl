@@ -1,39 +1,156 @@
-struct SceneUniforms {
-	perspective: mat4x4<f32>,
-	view: mat4x4<f32>,
-    inverse_perspective: mat4x4<f32>,
-    inverse_view: mat4x4<f32>,
-    camera_position: vec3<f32>,
-    aspect_ratio: f32,
-}
+//!include "common.wgsl"
 
 struct MeshUniforms {
 	model: mat4x4<f32>,
-	random_color: vec4<f32>
+}
+
+struct MaterialUniforms {
+	base_color_factor: vec4<f32>,
+	// Fragments with less base color alpha are discarded, 0 for materials that aren't masked
+	alpha_cutoff: f32,
+	// Nonzero for KHR_materials_unlit
+	unlit: u32,
+	// Scales the X and Y of sampled normals
+	normal_scale: f32,
+	// Zero leaves the vertex normals as they are
+	has_normal_map: u32,
 }
 
 @group(0) @binding(0) var<uniform> scene: SceneUniforms;
 @group(1) @binding(0) var<uniform> mesh: MeshUniforms;
+@group(2) @binding(0) var<uniform> material: MaterialUniforms;
+@group(2) @binding(1) var base_color_texture: texture_2d<f32>;
+@group(2) @binding(2) var normal_texture: texture_2d<f32>;
+@group(2) @binding(3) var material_sampler: sampler;
 
 struct VertexInput {
 	@location(0) position: vec3<f32>,
 	@location(1) normal: vec3<f32>,
+	@location(2) uv: vec2<f32>,
+	// w is the sign of the bitangent
+	@location(3) tangent: vec4<f32>,
 }
 
 struct VertexOutput {
-	@builtin(position) position_clip: vec4<f32>
+	// The depth prepass only matches if both passes compute exactly the same position
+	@builtin(position) @invariant position_clip: vec4<f32>,
+	@location(0) normal_view: vec3<f32>,
+	@location(1) uv: vec2<f32>,
+	@location(2) tangent_view: vec4<f32>,
+}
+
+struct FragmentOutput {
+	@location(0) color: vec4<f32>,
+	@location(1) normal: vec4<f32>,
+	// Added onto color after the AO technique has darkened it
+	@location(2) ambient: vec4<f32>,
+	// For the multi-bounce AO approximation
+	@location(3) albedo: vec4<f32>,
+}
+
+// Lambert diffuse from the directional light, for a white surface. Ambient is written separately
+// so that AO only occludes the ambient term.
+fn direct_lighting(normal_view: vec3<f32>) -> vec3<f32> {
+	let light_view = normalize((scene.view * vec4<f32>(scene.light_direction, 0.0)).xyz);
+	let n_dot_l = max(dot(normalize(normal_view), light_view), 0.0);
+	return scene.light_color * scene.light_intensity * n_dot_l;
+}
+
+fn base_color(uv: vec2<f32>) -> vec4<f32> {
+	return material.base_color_factor * textureSample(base_color_texture, material_sampler, uv);
+}
+
+// Vertex normal perturbed by the normal map, view space
+fn surface_normal(in: VertexOutput) -> vec3<f32> {
+	let n = normalize(in.normal_view);
+	if (material.has_normal_map == 0u) {
+		return n;
+	}
+	// Interpolation skews the tangent, orthogonalize it again
+	let t = normalize(in.tangent_view.xyz - n * dot(n, in.tangent_view.xyz));
+	let b = cross(n, t) * in.tangent_view.w;
+	let sampled = textureSample(normal_texture, material_sampler, in.uv).xyz * 2.0 - 1.0;
+	let scaled = vec3<f32>(sampled.xy * material.normal_scale, sampled.z);
+	return normalize(mat3x3<f32>(t, b, n) * scaled);
+}
+
+// Coverage for alpha to coverage, sharpened around the cutoff so only the edge is softened.
+// Has to be called before alpha_test, derivatives need every fragment in the quad.
+fn alpha_coverage(alpha: f32) -> f32 {
+	if (material.alpha_cutoff <= 0.0) {
+		return 1.0;
+	}
+	return saturate((alpha - material.alpha_cutoff) / max(fwidth(alpha), 0.0001) + 0.5);
+}
+
+fn alpha_test(alpha: f32) {
+	if (alpha < material.alpha_cutoff) {
+		discard;
+	}
+}
+
+// Unlit surfaces show albedo as is. AO only darkens the ambient term, so their albedo goes there
+// instead when scene.occlude_unlit asks for them to be occluded.
+fn shade(albedo: vec3<f32>, coverage: f32, normal_view: vec3<f32>, unlit: bool) -> FragmentOutput {
+	var out: FragmentOutput;
+	if (unlit && scene.occlude_unlit != 0u) {
+		out.color = vec4<f32>(0.0, 0.0, 0.0, coverage);
+		out.ambient = vec4<f32>(albedo, 1.0);
+	} else if (unlit) {
+		out.color = vec4<f32>(albedo, coverage);
+		out.ambient = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+	} else {
+		out.color = vec4<f32>(albedo * direct_lighting(normal_view), coverage);
+		out.ambient = vec4<f32>(albedo * scene.ambient_intensity, 1.0);
+	}
+	out.normal = vec4<f32>(normalize(normal_view), 1.0);
+	out.albedo = vec4<f32>(albedo, 1.0);
+	return out;
 }
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
 	var out: VertexOutput;
 	out.position_clip = scene.perspective * scene.view * mesh.model * vec4<f32>(in.position, 1.0);
+	// Assumes no non-uniform scale in the model matrix
+	out.normal_view = (scene.view * mesh.model * vec4<f32>(in.normal, 0.0)).xyz;
+	out.tangent_view = vec4<f32>(
+		(scene.view * mesh.model * vec4<f32>(in.tangent.xyz, 0.0)).xyz,
+		in.tangent.w,
+	);
+	out.uv = in.uv;
 	return out;
 }
 
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+	let base_color = base_color(in.uv);
+	let coverage = alpha_coverage(base_color.a);
+	let normal_view = surface_normal(in);
+	alpha_test(base_color.a);
+	return shade(base_color.rgb, coverage, normal_view, material.unlit != 0u);
+}
+
+@fragment
+fn fs_gray(in: VertexOutput) -> FragmentOutput {
+	let alpha = base_color(in.uv).a;
+	let coverage = alpha_coverage(alpha);
+	let normal_view = surface_normal(in);
+	alpha_test(alpha);
+	return shade(vec3<f32>(0.5), coverage, normal_view, false);
+}
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-	return vec4<f32>(mesh.random_color.rgb, 1.0);
+fn fs_ao_only(in: VertexOutput) -> FragmentOutput {
+	let alpha = base_color(in.uv).a;
+	let coverage = alpha_coverage(alpha);
+	let normal_view = surface_normal(in);
+	alpha_test(alpha);
+	return shade(vec3<f32>(1.0), coverage, normal_view, false);
 }
 
+// For the depth only passes, which have no color targets
+@fragment
+fn fs_alpha_test(in: VertexOutput) {
+	alpha_test(base_color(in.uv).a);
+}
@@ -0,0 +1,51 @@
+//!include "common.wgsl"
+
+@group(0) @binding(0) var<uniform> scene: SceneUniforms;
+@group(1) @binding(0) var color: texture_2d<f32>;
+@group(1) @binding(1) var normals: texture_2d<f32>;
+@group(1) @binding(2) var depth_buffer: texture_depth_2d;
+@group(1) @binding(3) var ao: texture_2d<f32>;
+@group(1) @binding(4) var input_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+// Tiles, row by row: color, normals, depth, AO
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let dimensions = textureDimensions(depth_buffer);
+	let grid_uv = position.xy / vec2<f32>(dimensions) * 2.0;
+	let tile = vec2<i32>(floor(grid_uv));
+	let uv = fract(grid_uv);
+
+	// Sampled in uniform control flow, only one of them is used
+	let color_sample = textureSample(color, input_sampler, uv);
+	let normal_sample = textureSample(normals, input_sampler, uv);
+	let ao_sample = textureSample(ao, input_sampler, uv);
+
+	if (tile.x == 0 && tile.y == 0) {
+		return vec4<f32>(color_sample.rgb, 1.0);
+	}
+	if (tile.x == 1 && tile.y == 0) {
+		return vec4<f32>(normal_sample.xyz * 0.5 + 0.5, 1.0) * normal_sample.w;
+	}
+	if (tile.x == 0 && tile.y == 1) {
+		let depth = textureLoad(depth_buffer, vec2<i32>(uv * vec2<f32>(dimensions)), 0);
+		// Unprojected rather than linearized by formula so reversed Z works too
+		let linear = view_position_from_depth(vec2<f32>(0.5, 0.5), depth, scene.inverse_perspective).z;
+		let gray = (linear - scene.z_near) / (scene.z_far - scene.z_near);
+		return vec4<f32>(gray, gray, gray, 1.0);
+	}
+	return vec4<f32>(ao_sample.rrr, 1.0);
+}
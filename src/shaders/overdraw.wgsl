@@ -0,0 +1,24 @@
+//!include "common.wgsl"
+
+struct MeshUniforms {
+	model: mat4x4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> scene: SceneUniforms;
+@group(1) @binding(0) var<uniform> mesh: MeshUniforms;
+
+struct VertexInput {
+	@location(0) position: vec3<f32>,
+	@location(1) normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> @builtin(position) vec4<f32> {
+	return scene.perspective * scene.view * mesh.model * vec4<f32>(in.position, 1.0);
+}
+
+// Additively blended, so each target texel ends up holding the number of fragments shaded there
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	return vec4<f32>(1.0, 0.0, 0.0, 0.0);
+}
@@ -0,0 +1,41 @@
+@group(0) @binding(0) var input: texture_2d<f32>;
+
+// Fragment count at which the heatmap saturates to red
+const MAX_OVERDRAW: f32 = 8.0;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+	
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+fn heat(t: f32) -> vec3<f32> {
+	return clamp(
+		vec3<f32>(1.5) - abs(4.0 * t - vec3<f32>(3.0, 2.0, 1.0)),
+		vec3<f32>(0.0),
+		vec3<f32>(1.0)
+	);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let count = textureLoad(
+		input,
+		vec2<i32>(floor(position.xy)),
+		0 
+	).r;
+
+	if (count < 0.5) {
+		return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+	}
+
+	return vec4<f32>(heat(clamp(count / MAX_OVERDRAW, 0.0, 1.0)), 1.0);
+}
@@ -0,0 +1,38 @@
+//!include "common.wgsl"
+
+// View-space normals from the depth buffer's screen space derivatives, shown like the normal
+// buffer in the G-buffer view so the two can be compared
+
+@group(0) @binding(0) var<uniform> scene: SceneUniforms;
+@group(1) @binding(0) var depth_buffer: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let dimensions = vec2<f32>(textureDimensions(depth_buffer));
+	let pixel = vec2<i32>(floor(position.xy));
+	let depth = textureLoad(depth_buffer, pixel, 0);
+	let origin = view_position_from_depth(position.xy / dimensions, depth, scene.inverse_perspective);
+
+	// Same as reconstruct_normal in crytek_ssao.wgsl, derivatives are taken before any branch
+	let normal = normalize(cross(dpdx(origin), dpdy(origin)));
+
+	// Nothing was drawn here, the far plane has no meaningful normal
+	if (origin.z >= scene.z_far * 0.999) {
+		return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+	}
+	return vec4<f32>(normal * 0.5 + 0.5, 1.0);
+}
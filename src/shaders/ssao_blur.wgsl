@@ -0,0 +1,35 @@
+@group(0) @binding(0) var input: texture_2d<f32>;
+
+// Matches the size of the tiled noise texture, so the rotation pattern averages out
+const BLUR_SIZE: i32 = 4;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let dimensions = vec2<i32>(textureDimensions(input));
+	let pixel = vec2<i32>(floor(position.xy));
+
+	var total = 0.0;
+	for (var x = -BLUR_SIZE / 2; x < BLUR_SIZE / 2; x++) {
+		for (var y = -BLUR_SIZE / 2; y < BLUR_SIZE / 2; y++) {
+			let sample_pixel = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), dimensions - 1);
+			total += textureLoad(input, sample_pixel, 0).r;
+		}
+	}
+
+	let ao = total / f32(BLUR_SIZE * BLUR_SIZE);
+	return vec4<f32>(ao, ao, ao, 1.0);
+}
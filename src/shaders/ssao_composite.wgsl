@@ -0,0 +1,69 @@
+struct CompositeUniforms {
+	// 0 blurred, 1 raw, 2 raw on the left half and blurred on the right
+	mode: u32,
+	// Applied as pow(ao, power) * intensity
+	power: f32,
+	intensity: f32,
+	// Nonzero applies multi_bounce with the albedo buffer
+	multi_bounce: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: CompositeUniforms;
+@group(0) @binding(1) var raw: texture_2d<f32>;
+@group(0) @binding(2) var blurred: texture_2d<f32>;
+@group(0) @binding(3) var albedo: texture_2d<f32>;
+
+// GTAO's fit of AO with interreflections for a surface of the given albedo (Jimenez et al. 2016),
+// lighter surfaces get more light bounced back into their cavities
+fn multi_bounce(ao: f32, albedo: vec3<f32>) -> vec3<f32> {
+	let a = 2.0404 * albedo - 0.3324;
+	let b = -4.7951 * albedo + 0.6417;
+	let c = 2.7552 * albedo + 0.6903;
+	return max(vec3<f32>(ao), ((ao * a + b) * ao + c) * ao);
+}
+
+// Clamped so intensity can't brighten past unoccluded
+fn adjust(ao: f32, power: f32, intensity: f32) -> f32 {
+	return clamp(pow(ao, power) * intensity, 0.0, 1.0);
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let pixel = vec2<i32>(floor(position.xy));
+	let half_width = i32(textureDimensions(blurred).x) / 2;
+
+	var use_raw = params.mode == 1u;
+	if (params.mode == 2u) {
+		use_raw = pixel.x < half_width;
+		// Divider between the halves
+		if (abs(pixel.x - half_width) < 1) {
+			return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+		}
+	}
+
+	var ao = textureLoad(blurred, pixel, 0).r;
+	if (use_raw) {
+		// Raw AO is smaller when rendered below full resolution
+		let raw_pixel = pixel * vec2<i32>(textureDimensions(raw)) / vec2<i32>(textureDimensions(blurred));
+		ao = textureLoad(raw, raw_pixel, 0).r;
+	}
+	ao = adjust(ao, params.power, params.intensity);
+	if (params.multi_bounce != 0u) {
+		return vec4<f32>(multi_bounce(ao, textureLoad(albedo, pixel, 0).rgb), 1.0);
+	}
+	return vec4<f32>(ao, ao, ao, 1.0);
+}
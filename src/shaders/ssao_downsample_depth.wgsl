@@ -0,0 +1,31 @@
+struct DownsampleUniforms {
+	// Full resolution pixels per low resolution pixel, along each axis
+	divisor: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: DownsampleUniforms;
+@group(0) @binding(1) var depth_buffer: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+// Point samples the middle of each block rather than averaging, so depths across an edge never
+// blend into a surface that isn't there
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+	let divisor = i32(params.divisor);
+	let dimensions = vec2<i32>(textureDimensions(depth_buffer));
+	let pixel = vec2<i32>(floor(position.xy)) * divisor + divisor / 2;
+	return textureLoad(depth_buffer, min(pixel, dimensions - 1), 0);
+}
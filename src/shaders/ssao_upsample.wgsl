@@ -0,0 +1,57 @@
+//!include "common.wgsl"
+
+@group(0) @binding(0) var<uniform> scene: SceneUniforms;
+@group(1) @binding(0) var low_ao: texture_2d<f32>;
+@group(1) @binding(1) var low_depth: texture_depth_2d;
+@group(1) @binding(2) var depth_buffer: texture_depth_2d;
+
+// Relative depth difference where a low resolution texel's weight has halved
+const DEPTH_TOLERANCE: f32 = 0.02;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+// Joint bilateral: bilinear weights over the four nearest low resolution texels, scaled down for
+// texels whose depth differs from this pixel's so AO doesn't bleed across silhouettes
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let pixel = vec2<i32>(floor(position.xy));
+	let uv = position.xy / vec2<f32>(textureDimensions(depth_buffer));
+	let z = view_position_from_depth(uv, textureLoad(depth_buffer, pixel, 0), scene.inverse_perspective).z;
+
+	let low_dimensions = vec2<i32>(textureDimensions(low_ao));
+	let low_position = uv * vec2<f32>(low_dimensions) - 0.5;
+	let base = vec2<i32>(floor(low_position));
+	let f = fract(low_position);
+
+	var total = 0.0;
+	var total_weight = 0.0;
+	for (var y = 0; y < 2; y++) {
+		for (var x = 0; x < 2; x++) {
+			let texel = clamp(base + vec2<i32>(x, y), vec2<i32>(0), low_dimensions - 1);
+			let low_uv = (vec2<f32>(texel) + 0.5) / vec2<f32>(low_dimensions);
+			let low_z = view_position_from_depth(low_uv, textureLoad(low_depth, texel, 0), scene.inverse_perspective).z;
+
+			let bilinear = select(1.0 - f.x, f.x, x == 1) * select(1.0 - f.y, f.y, y == 1);
+			let depth_weight = 1.0 / (1.0 + abs(low_z - z) / (abs(z) * DEPTH_TOLERANCE));
+			let weight = max(bilinear, 0.001) * depth_weight;
+
+			total += textureLoad(low_ao, texel, 0).r * weight;
+			total_weight += weight;
+		}
+	}
+
+	let ao = total / total_weight;
+	return vec4<f32>(ao, ao, ao, 1.0);
+}
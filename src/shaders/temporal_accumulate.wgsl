@@ -0,0 +1,82 @@
+//!include "common.wgsl"
+
+struct TemporalUniforms {
+	previous_view_projection: mat4x4<f32>,
+	alpha: f32,
+	depth_threshold: f32,
+}
+
+@group(0) @binding(0) var<uniform> scene: SceneUniforms;
+@group(1) @binding(0) var<uniform> params: TemporalUniforms;
+@group(1) @binding(1) var current: texture_2d<f32>;
+@group(1) @binding(2) var history: texture_2d<f32>;
+@group(1) @binding(3) var depth_buffer: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+fn view_position(pixel: vec2<i32>) -> vec3<f32> {
+	let dimensions = vec2<f32>(textureDimensions(depth_buffer));
+	let uv = (vec2<f32>(pixel) + 0.5) / dimensions;
+	let depth = textureLoad(depth_buffer, pixel, 0);
+
+	return view_position_from_depth(uv, depth, scene.inverse_perspective);
+}
+
+// Where the view space position was in clip space last frame
+fn previous_clip_position(view: vec3<f32>, inverse_view: mat4x4<f32>, previous_view_projection: mat4x4<f32>) -> vec4<f32> {
+	let world = inverse_view * vec4<f32>(view, 1.0);
+	return previous_view_projection * world;
+}
+
+// The pixel of a target with the given dimensions that clip lands in
+fn clip_to_pixel(clip: vec4<f32>, dimensions: vec2<i32>) -> vec2<i32> {
+	let ndc = clip.xy / clip.w;
+	return vec2<i32>(vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * vec2<f32>(dimensions));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let dimensions = vec2<i32>(textureDimensions(current));
+	let pixel = vec2<i32>(floor(position.xy));
+	let ao = textureLoad(current, pixel, 0).r;
+
+	// History outside the current neighborhood's range is most likely stale, clamping it cuts ghosting
+	var low = ao;
+	var high = ao;
+	for (var x = -1; x <= 1; x++) {
+		for (var y = -1; y <= 1; y++) {
+			let neighbor_pixel = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), dimensions - 1);
+			let neighbor = textureLoad(current, neighbor_pixel, 0).r;
+			low = min(low, neighbor);
+			high = max(high, neighbor);
+		}
+	}
+
+	let view = view_position(pixel);
+	let previous_clip = previous_clip_position(view, scene.inverse_view, params.previous_view_projection);
+	let previous_pixel = clip_to_pixel(previous_clip, dimensions);
+
+	var result = ao;
+	let on_screen = all(previous_pixel >= vec2<i32>(0)) && all(previous_pixel < dimensions);
+	if (on_screen && previous_clip.w > 0.0) {
+		let previous = textureLoad(history, previous_pixel, 0);
+		// For a perspective projection clip w is the view-space depth
+		if (abs(previous.g - previous_clip.w) <= params.depth_threshold * previous_clip.w) {
+			result = mix(clamp(previous.r, low, high), ao, params.alpha);
+		}
+	}
+
+	return vec4<f32>(result, view.z, 0.0, 1.0);
+}
@@ -1,4 +1,24 @@
-@group(0) @binding(0) var input: texture_depth_2d;
+struct DepthDebugUniforms {
+	z_near: f32,
+	z_far: f32,
+	reverse_z: u32,
+	range_min: f32,
+	range_max: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: DepthDebugUniforms;
+@group(0) @binding(1) var input: texture_depth_2d;
+
+// Maps hardware depth back to view space distance. Reverse-Z is mirrored into the standard
+// convention first so the same perspective_lh inverse applies to both.
+fn linearize_depth(depth: f32, z_near: f32, z_far: f32, reverse_z: u32) -> f32 {
+	var d = depth;
+	if (reverse_z != 0u) {
+		d = 1.0 - d;
+	}
+
+	return z_near * z_far / (z_far - d * (z_far - z_near));
+}
 
 @vertex
 fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
@@ -16,11 +36,14 @@ fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
 
 @fragment
 fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
-	let color = textureLoad(
+	let depth = textureLoad(
 		input,
 		vec2<i32>(floor(position.xy)),
 		0 
 	);
 
+	let linear = (linearize_depth(depth, params.z_near, params.z_far, params.reverse_z) - params.z_near) / (params.z_far - params.z_near);
+	// Most of a typical scene sits in a small slice of the range, this stretches it for contrast
+	let color = saturate((linear - params.range_min) / max(params.range_max - params.range_min, 1e-5));
 	return vec4<f32>(color, color, color, 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,59 @@
+// Maps the linear HDR scene color into [0, 1] for the display target
+
+struct ToneMapUniforms {
+	exposure: f32,
+	// 0 clamps, 1 is Reinhard, 2 is ACES
+	curve: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: ToneMapUniforms;
+@group(0) @binding(1) var scene_color: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+	return color / (1.0 + color);
+}
+
+// Narkowicz 2015, a fit of the ACES reference rendering and output transforms
+fn aces(color: vec3<f32>) -> vec3<f32> {
+	let a = 2.51;
+	let b = 0.03;
+	let c = 2.43;
+	let d = 0.59;
+	let e = 0.14;
+	return saturate((color * (a * color + b)) / (color * (c * color + d) + e));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let sample = textureLoad(scene_color, vec2<i32>(floor(position.xy)), 0);
+	let color = max(sample.rgb * params.exposure, vec3<f32>(0.0));
+
+	var mapped: vec3<f32>;
+	switch params.curve {
+		case 1u: {
+			mapped = reinhard(color);
+		}
+		case 2u: {
+			mapped = aces(color);
+		}
+		default: {
+			mapped = saturate(color);
+		}
+	}
+
+	return vec4<f32>(mapped, sample.a);
+}
@@ -0,0 +1,131 @@
+//!include "common.wgsl"
+
+// Visibility bitmask AO (Therrien et al. 2023). Each slice through the view vector is split into
+// 32 sectors, samples along the slice mark the sectors they cover as occluded, and AO is the
+// fraction of sectors left unmarked. Unlike a single horizon angle per side, occluders only cover
+// their own thickness, so light can pass behind thin objects.
+
+struct VisibilityBitmaskUniforms {
+	// View space distance the slices are marched over
+	radius: f32,
+	// Assumed view space depth of every surface
+	thickness: f32,
+	slice_count: u32,
+	// Per side of the slice
+	step_count: u32,
+}
+
+@group(0) @binding(0) var<uniform> scene: SceneUniforms;
+@group(1) @binding(0) var<uniform> params: VisibilityBitmaskUniforms;
+@group(1) @binding(1) var depth_buffer: texture_depth_2d;
+@group(1) @binding(2) var normal_buffer: texture_2d<f32>;
+
+const PI: f32 = 3.14159265;
+const HALF_PI: f32 = 1.57079633;
+const SECTOR_COUNT: u32 = 32u;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	var vertex_positions = array<vec2<f32>, 6>(
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, 1.0),
+		vec2<f32>(-1.0, 1.0),
+		vec2<f32>(-1.0, -1.0),
+		vec2<f32>(1.0, -1.0),
+		vec2<f32>(1.0, 1.0)
+	);
+
+	return vec4<f32>(vertex_positions[index], 0.0, 1.0);
+}
+
+fn view_position(pixel: vec2<i32>) -> vec3<f32> {
+	let dimensions = vec2<f32>(textureDimensions(depth_buffer));
+	let uv = (vec2<f32>(pixel) + 0.5) / dimensions;
+	let depth = textureLoad(depth_buffer, pixel, 0);
+
+	return view_position_from_depth(uv, depth, scene.inverse_perspective);
+}
+
+// 4x4 ordered dither, so the 4x4 blur averages every slice rotation exactly once
+fn dither(pixel: vec2<i32>) -> f32 {
+	var bayer = array<u32, 16>(0u, 8u, 2u, 10u, 12u, 4u, 14u, 6u, 3u, 11u, 1u, 9u, 15u, 7u, 13u, 5u);
+	let p = vec2<u32>(pixel) & vec2<u32>(3u);
+	return (f32(bayer[p.y * 4u + p.x]) + 0.5) / 16.0;
+}
+
+// Sectors covered between min_horizon and max_horizon, both in [0, 1] across the hemisphere
+fn occlusion_bits(min_horizon: f32, max_horizon: f32) -> u32 {
+	let start = u32(floor(min_horizon * f32(SECTOR_COUNT)));
+	let count = u32(ceil((max_horizon - min_horizon) * f32(SECTOR_COUNT)));
+	if (count >= SECTOR_COUNT) {
+		return 0xffffffffu << min(start, SECTOR_COUNT - 1u);
+	}
+	// Shifting by 32 isn't defined, so count < 32 here
+	return ((1u << count) - 1u) << min(start, SECTOR_COUNT - 1u);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let dimensions = vec2<i32>(textureDimensions(depth_buffer));
+	let pixel = vec2<i32>(floor(position.xy));
+
+	let normal_sample = textureLoad(normal_buffer, pixel, 0);
+	// Nothing was drawn here
+	if (normal_sample.w == 0.0) {
+		return vec4<f32>(1.0);
+	}
+
+	let origin = view_position(pixel);
+	let normal = normalize(normal_sample.xyz);
+	let view_direction = normalize(-origin);
+
+	// Pixels covered by radius at this depth, along y, which perspective[1][1] scales
+	let pixel_radius = params.radius * scene.perspective[1][1] * 0.5 * f32(dimensions.y) / origin.z;
+	let rotation = dither(pixel);
+	let jitter = dither(pixel.yx);
+
+	var visibility = 0.0;
+	for (var slice = 0u; slice < params.slice_count; slice++) {
+		let angle = (f32(slice) + rotation) * PI / f32(params.slice_count);
+		// Pixels are y down, view space is y up
+		let screen_direction = vec2<f32>(cos(angle), sin(angle));
+		let direction = vec3<f32>(screen_direction.x, -screen_direction.y, 0.0);
+
+		// The slice plane holds the view direction and this tangent
+		let tangent = normalize(direction - view_direction * dot(direction, view_direction));
+		let axis = cross(tangent, view_direction);
+		let projected_normal = normal - axis * dot(normal, axis);
+		let cos_n = clamp(dot(normalize(projected_normal), view_direction), -1.0, 1.0);
+		// Angle of the normal from the view direction, positive towards tangent
+		let n = sign(dot(projected_normal, tangent)) * acos(cos_n);
+
+		var bits = 0u;
+		for (var side = -1.0; side <= 1.0; side += 2.0) {
+			for (var step = 0u; step < params.step_count; step++) {
+				let t = (f32(step) + jitter) / f32(params.step_count);
+				// At least a pixel away, so the first step doesn't sample the origin itself
+				let offset = max(t * pixel_radius, 1.0) * side * screen_direction;
+				let sample_pixel = pixel + vec2<i32>(round(offset));
+				if (any(sample_pixel < vec2<i32>(0)) || any(sample_pixel >= dimensions)) {
+					break;
+				}
+
+				let front = view_position(sample_pixel) - origin;
+				let back = front - view_direction * params.thickness;
+				// Angles from the view direction, signed by side, mapped so the hemisphere around
+				// the normal covers [0, 1]
+				let horizons = side * acos(vec2<f32>(
+					dot(normalize(front), view_direction),
+					dot(normalize(back), view_direction),
+				));
+				let mapped = saturate((horizons - n + HALF_PI) / PI);
+				bits |= occlusion_bits(min(mapped.x, mapped.y), max(mapped.x, mapped.y));
+			}
+		}
+
+		visibility += 1.0 - f32(countOneBits(bits)) / f32(SECTOR_COUNT);
+	}
+
+	let ao = visibility / f32(params.slice_count);
+	return vec4<f32>(ao, ao, ao, 1.0);
+}
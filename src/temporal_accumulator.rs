@@ -0,0 +1,364 @@
+use glam::Mat4;
+use wgpu::{CommandEncoder, ShaderStages, TextureFormat, TextureSampleType};
+
+use crate::{
+    resource_manager::{
+        BindGroupDesc, BindGroupLayoutDesc, BufferDesc, BufferUsages, Handle, ResourceError,
+        ResourceManager, ShaderDesc, ShaderModuleDesc, ShaderPipelineDesc, TextureDesc,
+        TextureUsages, TextureViewDimension,
+    },
+    scene::{bytemuck_impl, Scene},
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TemporalUniformData {
+    pub previous_view_projection: Mat4,
+    pub alpha: f32,
+    pub depth_threshold: f32,
+    pub _padding: [f32; 2],
+}
+bytemuck_impl!(TemporalUniformData);
+
+/// Blends AO with the previous frames' by reprojecting every pixel into the last frame.
+pub struct TemporalAccumulator {
+    // AO in r, view-space depth in g so reprojected history can be rejected on disocclusion
+    history: [Handle; 2],
+    uniform_buffer: Handle,
+    // Indexed by the history texture being written
+    accumulate_bind_groups: [Handle; 2],
+    resolve_bind_groups: [Handle; 2],
+    accumulate_shader: Handle,
+    resolve_shader: Handle,
+    ao: Handle,
+
+    frame: usize,
+    history_valid: bool,
+
+    pub enabled: bool,
+    /// Weight of the current frame, lower is smoother but slower to react
+    pub alpha: f32,
+    /// Relative depth difference past which history is thrown away
+    pub depth_threshold: f32,
+}
+
+impl TemporalAccumulator {
+    fn accumulate_bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![std::mem::size_of::<TemporalUniformData>()],
+            textures: vec![
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+                (TextureSampleType::Depth, TextureViewDimension::D2),
+            ],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    fn resolve_bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![],
+            textures: vec![(
+                TextureSampleType::Float { filterable: true },
+                TextureViewDimension::D2,
+            )],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    /// `ao` is denoised in place, it must be a render target the size of `depth_buffer`.
+    pub fn new(
+        rm: &mut ResourceManager,
+        ao: Handle,
+        depth_buffer: Handle,
+    ) -> Result<Self, ResourceError> {
+        let size = rm.get_texture(ao).size();
+        let history_desc = TextureDesc {
+            label: Some("AO history"),
+            dimensions: (size.width, size.height),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: TextureFormat::Rgba16Float,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        };
+        let history = [
+            rm.create_texture(&history_desc),
+            rm.create_texture(&history_desc),
+        ];
+
+        let uniform_buffer = rm.create_buffer(&BufferDesc {
+            label: Some("Temporal accumulation uniform buffer"),
+            byte_size: std::mem::size_of::<TemporalUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
+        });
+
+        let mut create_accumulate_bind_group = |i: usize| {
+            rm.create_bind_group(&BindGroupDesc {
+                label: None,
+                visibility: ShaderStages::FRAGMENT,
+                layout: TemporalAccumulator::accumulate_bind_group_layout(),
+                buffers: &[uniform_buffer],
+                textures: &[ao, history[1 - i], depth_buffer],
+                samplers: &[],
+                storage_buffers: &[],
+                storage_textures: &[],
+            })
+        };
+        let accumulate_bind_groups = [
+            create_accumulate_bind_group(0)?,
+            create_accumulate_bind_group(1)?,
+        ];
+
+        let mut create_resolve_bind_group = |i: usize| {
+            rm.create_bind_group(&BindGroupDesc {
+                label: None,
+                visibility: ShaderStages::FRAGMENT,
+                layout: TemporalAccumulator::resolve_bind_group_layout(),
+                buffers: &[],
+                textures: &[history[i]],
+                samplers: &[],
+                storage_buffers: &[],
+                storage_textures: &[],
+            })
+        };
+        let resolve_bind_groups = [create_resolve_bind_group(0)?, create_resolve_bind_group(1)?];
+
+        let accumulate_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Temporal accumulate shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/temporal_accumulate.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/temporal_accumulate.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![
+                Scene::scene_bind_group_layout(),
+                TemporalAccumulator::accumulate_bind_group_layout(),
+            ],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![TextureFormat::Rgba16Float],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let resolve_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Temporal resolve shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/temporal_resolve.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/temporal_resolve.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![TemporalAccumulator::resolve_bind_group_layout()],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![rm.get_texture(ao).format()],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        Ok(Self {
+            history,
+            uniform_buffer,
+            accumulate_bind_groups,
+            resolve_bind_groups,
+            accumulate_shader,
+            resolve_shader,
+            ao,
+            frame: 0,
+            history_valid: false,
+            enabled: false,
+            alpha: 0.1,
+            depth_threshold: 0.05,
+        })
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if ui.checkbox(&mut self.enabled, "Enabled").changed() {
+            self.history_valid = false;
+        }
+        ui.add(
+            egui::Slider::new(&mut self.alpha, 0.01..=1.0)
+                .text("Current frame weight")
+                .show_value(true),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.depth_threshold, 0.001..=0.5)
+                .logarithmic(true)
+                .text("Depth rejection")
+                .show_value(true),
+        );
+    }
+
+    pub fn resize(&mut self, rm: &mut ResourceManager, dimensions: (u32, u32)) {
+        for history in self.history {
+            rm.resize_texture(history, dimensions);
+        }
+        self.history_valid = false;
+    }
+
+    /// Blends the AO with the history and writes the result back to it.
+    /// `previous_view_projection` is the camera the history was rendered with.
+    pub fn pass(
+        &mut self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        previous_view_projection: Mat4,
+    ) {
+        rm.update_buffer(
+            self.uniform_buffer,
+            bytemuck::cast_slice(&[TemporalUniformData {
+                previous_view_projection,
+                // The history is garbage until a frame has been written to it
+                alpha: if self.history_valid { self.alpha } else { 1.0 },
+                depth_threshold: self.depth_threshold,
+                _padding: [0.0; 2],
+            }]),
+        );
+
+        let write = self.frame % 2;
+
+        {
+            let mut accumulate_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Temporal accumulate"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: rm.get_texture(self.history[write]).view(),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            accumulate_pass.set_pipeline(rm.get_shader(self.accumulate_shader).pipeline());
+            accumulate_pass.set_bind_group(
+                0,
+                rm.get_bind_group(scene.scene_uniform_bind_group),
+                &[],
+            );
+            accumulate_pass.set_bind_group(
+                1,
+                rm.get_bind_group(self.accumulate_bind_groups[write]),
+                &[],
+            );
+            accumulate_pass.draw(0..6, 0..1);
+        }
+
+        {
+            let mut resolve_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Temporal resolve"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: rm.get_texture(self.ao).view(),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            resolve_pass.set_pipeline(rm.get_shader(self.resolve_shader).pipeline());
+            resolve_pass.set_bind_group(0, rm.get_bind_group(self.resolve_bind_groups[write]), &[]);
+            resolve_pass.draw(0..6, 0..1);
+        }
+
+        self.frame += 1;
+        self.history_valid = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, vec3, IVec2, Vec4};
+
+    use super::*;
+    use crate::{camera::Camera, scene::SceneUniformData, shader_harness};
+
+    #[test]
+    fn translation_shifts_every_pixel_equally() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let mut camera = Camera::default();
+        camera.set_aspect_ratio(1.0);
+        let uniforms = camera.build_uniforms();
+        let dimensions = IVec2::splat(100);
+
+        // Last frame the camera was far enough to the left for a plane at z to move 10 pixels
+        let z = 4.0;
+        let offset = 10.0 * 2.0 / dimensions.x as f32 * z / uniforms.perspective.x_axis.x;
+        let previous_uniforms = SceneUniformData {
+            view: Mat4::from_translation(vec3(offset, 0.0, 0.0)) * uniforms.view,
+            ..uniforms
+        };
+
+        let pixels = [IVec2::new(0, 0), IVec2::new(37, 52), IVec2::new(89, 99)];
+        // The points on the plane seen through the pixels' centers
+        let views: Vec<Vec4> = pixels
+            .iter()
+            .map(|pixel| {
+                let uv = (pixel.as_vec2() + 0.5) / dimensions.as_vec2();
+                let ndc = vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+                Vec4::new(
+                    ndc.x * z / uniforms.perspective.x_axis.x,
+                    ndc.y * z / uniforms.perspective.y_axis.y,
+                    z,
+                    1.0,
+                )
+            })
+            .collect();
+        let previous_pixels = shader_harness::eval(
+            &mut rm,
+            "src/shaders/temporal_accumulate.wgsl",
+            &format!(
+                "vec4<f32>(vec2<f32>(clip_to_pixel(previous_clip_position(input.xyz, {}, {}), \
+                 vec2<i32>({}, {}))), 0.0, 0.0)",
+                shader_harness::mat4(uniforms.inverse_view),
+                shader_harness::mat4(previous_uniforms.view_projection()),
+                dimensions.x,
+                dimensions.y
+            ),
+            &views,
+        );
+
+        for (pixel, previous_pixel) in pixels.into_iter().zip(previous_pixels) {
+            assert_eq!(
+                previous_pixel.truncate().truncate().as_ivec2(),
+                pixel + IVec2::new(10, 0)
+            );
+        }
+    }
+}
@@ -1,19 +1,31 @@
-use wgpu::{
-    vertex_attr_array, CommandEncoder, CompareFunction, ShaderStages, TextureFormat,
-    TextureSampleType, TextureView,
-};
+use wgpu::{CommandEncoder, ShaderStages, TextureSampleType, TextureView, TextureViewDimension};
 
 use crate::{
     resource_manager::{
-        BindGroupDesc, BindGroupLayoutDesc, Handle, ResourceManager, ShaderDesc, ShaderModuleDesc,
-        ShaderPipelineDesc, VertexBufferLayout,
+        BindGroupDesc, BindGroupLayoutDesc, BufferDesc, BufferUsages, Handle, ResourceError,
+        ResourceManager, ShaderDesc, ShaderModuleDesc, ShaderPipelineDesc, SCENE_COLOR_FORMAT,
     },
-    scene::{Mesh, SceneUniformData, VertexAttributes},
+    scene::bytemuck_impl,
 };
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DepthDebugUniformData {
+    pub z_near: f32,
+    pub z_far: f32,
+    pub reverse_z: u32,
+    /// Linear depth, 0 at the near plane and 1 at the far plane, mapped to black and white
+    pub range_min: f32,
+    pub range_max: f32,
+    pub _padding: [u32; 3],
+}
+bytemuck_impl!(DepthDebugUniformData);
+
 pub struct TextureDebugView {
     shader: Handle,
     bind_group: Handle,
+    // Only present for depth textures
+    uniform_buffer: Option<Handle>,
 }
 
 impl TextureDebugView {
@@ -22,80 +34,110 @@ impl TextureDebugView {
             BindGroupLayoutDesc {
                 label: None,
                 visibility: ShaderStages::FRAGMENT,
-                buffers: vec![],
-                textures: vec![TextureSampleType::Depth],
+                buffers: vec![std::mem::size_of::<DepthDebugUniformData>()],
+                textures: vec![(TextureSampleType::Depth, TextureViewDimension::D2)],
                 samplers: vec![],
+                storage_buffers: vec![],
+                storage_textures: vec![],
+                dynamic_offset: false,
             }
         } else {
             BindGroupLayoutDesc {
                 label: None,
                 visibility: ShaderStages::FRAGMENT,
                 buffers: vec![],
-                textures: vec![TextureSampleType::Float { filterable: true }],
+                textures: vec![(
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                )],
                 samplers: vec![],
+                storage_buffers: vec![],
+                storage_textures: vec![],
+                dynamic_offset: false,
             }
         }
     }
 
-    pub fn new(rm: &mut ResourceManager, texture: Handle) -> Self {
-        if rm.get_texture(texture).depth {
-            println!("path 1");
-            let shader = rm.create_shader(ShaderDesc {
-                label: None,
-                vs: ShaderModuleDesc {
-                    path: String::from("src/shaders/texture_debug_depth.wgsl"),
-                    entry_func: String::from("vs_main"),
-                },
-                ps: Some(ShaderModuleDesc {
-                    path: String::from("src/shaders/texture_debug_depth.wgsl"),
-                    entry_func: String::from("fs_main"),
-                }),
-                bind_group_layouts: vec![TextureDebugView::bind_group_layout(true)],
-                pipeline_state: ShaderPipelineDesc {
-                    depth_test: None,
-                    targets: vec![TextureFormat::Bgra8UnormSrgb],
-                    vertex_buffer_bindings: vec![],
-                },
-            });
-
-            let bind_group = rm.create_bind_group(&BindGroupDesc {
-                label: None,
-                visibility: ShaderStages::FRAGMENT,
-                layout: TextureDebugView::bind_group_layout(true),
-                buffers: &[],
-                textures: &[texture],
-                samplers: &[],
-            });
-            Self { shader, bind_group }
+    /// Depth textures go through the depth path, which linearizes them with its own uniforms.
+    fn shader_desc(depth: bool) -> ShaderDesc {
+        let path = if depth {
+            "src/shaders/texture_debug_depth.wgsl"
         } else {
-            println!("path 2");
-            let shader = rm.create_shader(ShaderDesc {
-                label: None,
-                vs: ShaderModuleDesc {
-                    path: String::from("src/shaders/texture_debug.wgsl"),
-                    entry_func: String::from("vs_main"),
-                },
-                ps: Some(ShaderModuleDesc {
-                    path: String::from("src/shaders/texture_debug.wgsl"),
-                    entry_func: String::from("fs_main"),
-                }),
-                bind_group_layouts: vec![TextureDebugView::bind_group_layout(false)],
-                pipeline_state: ShaderPipelineDesc {
-                    depth_test: None,
-                    targets: vec![TextureFormat::Bgra8UnormSrgb],
-                    vertex_buffer_bindings: vec![],
-                },
-            });
+            "src/shaders/texture_debug.wgsl"
+        };
+        ShaderDesc {
+            label: None,
+            vs: ShaderModuleDesc {
+                path: String::from(path),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from(path),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![TextureDebugView::bind_group_layout(depth)],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![SCENE_COLOR_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        }
+    }
 
-            let bind_group = rm.create_bind_group(&BindGroupDesc {
-                label: None,
-                visibility: ShaderStages::FRAGMENT,
-                layout: TextureDebugView::bind_group_layout(false),
-                buffers: &[],
-                textures: &[texture],
-                samplers: &[],
-            });
-            Self { shader, bind_group }
+    pub fn new(rm: &mut ResourceManager, texture: Handle) -> Result<Self, ResourceError> {
+        let depth = rm.get_texture(texture).depth;
+        let shader = rm.create_shader(TextureDebugView::shader_desc(depth))?;
+
+        let uniform_buffer = depth.then(|| {
+            rm.create_buffer(&BufferDesc {
+                label: Some("Depth debug uniform buffer"),
+                byte_size: std::mem::size_of::<DepthDebugUniformData>(),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                initial_data: Some(bytemuck::cast_slice(&[DepthDebugUniformData::default()])),
+            })
+        });
+
+        let bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: TextureDebugView::bind_group_layout(depth),
+            buffers: uniform_buffer.as_slice(),
+            textures: &[texture],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+        Ok(Self {
+            shader,
+            bind_group,
+            uniform_buffer,
+        })
+    }
+
+    /// Sets the planes and depth convention the depth path linearizes against, and the part of
+    /// the linear depth range stretched over black to white.
+    pub fn update_depth_uniforms(
+        &self,
+        rm: &ResourceManager,
+        z_near: f32,
+        z_far: f32,
+        reverse_z: bool,
+        range: [f32; 2],
+    ) {
+        if let Some(uniform_buffer) = self.uniform_buffer {
+            rm.update_buffer(
+                uniform_buffer,
+                bytemuck::cast_slice(&[DepthDebugUniformData {
+                    z_near,
+                    z_far,
+                    reverse_z: reverse_z as u32,
+                    range_min: range[0],
+                    range_max: range[1],
+                    _padding: [0; 3],
+                }]),
+            );
         }
     }
 
@@ -120,3 +162,119 @@ impl TextureDebugView {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec4, Vec4};
+
+    use wgpu::TextureUsages;
+
+    use super::*;
+    use crate::{
+        camera::Camera,
+        resource_manager::{TextureDesc, DEPTH_FORMAT},
+    };
+
+    /// Runs `linearize_depth` from texture_debug_depth.wgsl on each depth.
+    fn linearize_depth(
+        rm: &mut ResourceManager,
+        depths: &[f32],
+        params: &DepthDebugUniformData,
+    ) -> Vec<f32> {
+        let inputs: Vec<Vec4> = depths.iter().map(|&depth| Vec4::splat(depth)).collect();
+        crate::shader_harness::eval(
+            rm,
+            "src/shaders/texture_debug_depth.wgsl",
+            &format!(
+                "vec4<f32>(linearize_depth(input.x, {:?}, {:?}, {}u))",
+                params.z_near, params.z_far, params.reverse_z
+            ),
+            &inputs,
+        )
+        .iter()
+        .map(|output| output.x)
+        .collect()
+    }
+
+    #[test]
+    fn linearize_maps_the_planes_to_0_and_1() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let mut camera = Camera::default();
+        for reverse_z in [false, true] {
+            camera.reverse_z = reverse_z;
+            let uniforms = camera.build_uniforms();
+            let params = DepthDebugUniformData {
+                z_near: uniforms.z_near,
+                z_far: uniforms.z_far,
+                reverse_z: reverse_z as u32,
+                ..Default::default()
+            };
+            let halfway = (uniforms.z_near + uniforms.z_far) * 0.5;
+            let depths: Vec<f32> = [uniforms.z_near, uniforms.z_far, halfway]
+                .iter()
+                .map(|&z| {
+                    let clip = uniforms.perspective * vec4(0.0, 0.0, z, 1.0);
+                    clip.z / clip.w
+                })
+                .collect();
+            // Normalized the same way as fs_main
+            let linear: Vec<f32> = linearize_depth(&mut rm, &depths, &params)
+                .iter()
+                .map(|depth| (depth - params.z_near) / (params.z_far - params.z_near))
+                .collect();
+
+            // Standard depth is close to 1 at the far plane, where f32 has little precision left
+            assert!(linear[0].abs() < 1e-3);
+            assert!((linear[1] - 1.0).abs() < 1e-3);
+            assert!((linear[2] - 0.5).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn depth_targets_use_the_depth_path() {
+        let depth = TextureDebugView::shader_desc(true);
+        assert_eq!(depth.vs.path, "src/shaders/texture_debug_depth.wgsl");
+        assert_eq!(
+            depth.ps.unwrap().path,
+            "src/shaders/texture_debug_depth.wgsl"
+        );
+        assert_eq!(
+            depth.bind_group_layouts[0].textures[0].0,
+            TextureSampleType::Depth
+        );
+
+        let color = TextureDebugView::shader_desc(false);
+        assert_eq!(color.vs.path, "src/shaders/texture_debug.wgsl");
+        assert!(color.bind_group_layouts[0].buffers.is_empty());
+    }
+
+    #[test]
+    fn selecting_a_depth_target_builds_the_depth_path() {
+        let Some(mut rm) = ResourceManager::headless_loading_depth() else {
+            return;
+        };
+        let depth_buffer = rm.create_texture(&TextureDesc {
+            dimensions: (4, 4),
+            format: DEPTH_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            ..Default::default()
+        });
+        let scene_color = rm.create_texture(&TextureDesc {
+            dimensions: (4, 4),
+            format: SCENE_COLOR_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            ..Default::default()
+        });
+
+        assert!(TextureDebugView::new(&mut rm, depth_buffer)
+            .unwrap()
+            .uniform_buffer
+            .is_some());
+        assert!(TextureDebugView::new(&mut rm, scene_color)
+            .unwrap()
+            .uniform_buffer
+            .is_none());
+    }
+}
@@ -0,0 +1,263 @@
+use wgpu::{CommandEncoder, ShaderStages, TextureSampleType, TextureView, TextureViewDimension};
+
+use crate::{
+    resource_manager::{
+        BindGroupDesc, BindGroupLayoutDesc, BufferDesc, BufferUsages, Handle, ResourceError,
+        ResourceManager, ShaderDesc, ShaderModuleDesc, ShaderPipelineDesc,
+    },
+    scene::bytemuck_impl,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapCurve {
+    /// Clamps to 1, also used for debug output so it's shown as is
+    None = 0,
+    Reinhard = 1,
+    /// Narkowicz's fit of the ACES filmic curve
+    #[default]
+    Aces = 2,
+}
+
+impl ToneMapCurve {
+    const ALL: [ToneMapCurve; 3] = [
+        ToneMapCurve::None,
+        ToneMapCurve::Reinhard,
+        ToneMapCurve::Aces,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ToneMapCurve::None => "None (clamp)",
+            ToneMapCurve::Reinhard => "Reinhard",
+            ToneMapCurve::Aces => "ACES",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ToneMapUniformData {
+    /// Linear scale applied before the curve
+    pub exposure: f32,
+    pub curve: u32,
+    pub _padding: [u32; 2],
+}
+bytemuck_impl!(ToneMapUniformData);
+
+/// Maps the linear HDR scene color into a texture of the surface's format for egui to show.
+pub struct ToneMapping {
+    shader: Handle,
+    bind_group: Handle,
+    uniform_buffer: Handle,
+
+    pub curve: ToneMapCurve,
+    /// In stops
+    pub exposure: f32,
+}
+
+impl ToneMapping {
+    pub fn bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![std::mem::size_of::<ToneMapUniformData>()],
+            textures: vec![(
+                TextureSampleType::Float { filterable: true },
+                TextureViewDimension::D2,
+            )],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    pub fn new(rm: &mut ResourceManager, scene_color: Handle) -> Result<Self, ResourceError> {
+        let shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Tone mapping shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/tone_mapping.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/tone_mapping.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![ToneMapping::bind_group_layout()],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![rm.surface_configuration.format],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let uniform_buffer = rm.create_buffer(&BufferDesc {
+            label: Some("Tone mapping uniform buffer"),
+            byte_size: std::mem::size_of::<ToneMapUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
+        });
+
+        let bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: Some("Tone mapping bind group"),
+            visibility: ShaderStages::FRAGMENT,
+            layout: ToneMapping::bind_group_layout(),
+            buffers: &[uniform_buffer],
+            textures: &[scene_color],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        Ok(Self {
+            shader,
+            bind_group,
+            uniform_buffer,
+            curve: ToneMapCurve::default(),
+            exposure: 0.0,
+        })
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Tone mapping").show(ui, |ui| {
+            egui::ComboBox::from_label("Curve")
+                .selected_text(self.curve.name())
+                .show_ui(ui, |ui| {
+                    for curve in ToneMapCurve::ALL {
+                        ui.selectable_value(&mut self.curve, curve, curve.name());
+                    }
+                });
+            ui.add(
+                egui::Slider::new(&mut self.exposure, -5.0..=5.0)
+                    .text("Exposure")
+                    .suffix(" EV"),
+            );
+        });
+    }
+
+    /// Writes the scene color to `view`, or copies it unchanged if `raw`, for debug output that
+    /// isn't lighting.
+    pub fn pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        raw: bool,
+    ) {
+        let uniforms = if raw {
+            ToneMapUniformData {
+                exposure: 1.0,
+                curve: ToneMapCurve::None as u32,
+                _padding: [0; 2],
+            }
+        } else {
+            ToneMapUniformData {
+                exposure: self.exposure.exp2(),
+                curve: self.curve as u32,
+                _padding: [0; 2],
+            }
+        };
+        rm.stage_buffer_update(
+            encoder,
+            self.uniform_buffer,
+            bytemuck::cast_slice(&[uniforms]),
+        );
+
+        let mut tone_mapping = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone mapping"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        tone_mapping.set_pipeline(rm.get_shader(self.shader).pipeline());
+        tone_mapping.set_bind_group(0, rm.get_bind_group(self.bind_group), &[]);
+        tone_mapping.draw(0..6, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec3, Vec4};
+
+    use super::*;
+    use crate::resource_manager::{TextureDesc, TextureFormat, SCENE_COLOR_FORMAT};
+
+    /// Runs `curve` from tone_mapping.wgsl on 0 and then 0.05 to 50 in steps of 0.05.
+    fn check_curve(curve: &str) {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let inputs: Vec<Vec4> = (0..=1000).map(|i| Vec4::splat(i as f32 * 0.05)).collect();
+        let mapped = crate::shader_harness::eval(
+            &mut rm,
+            "src/shaders/tone_mapping.wgsl",
+            &format!("vec4<f32>({}(input.xyz), 0.0)", curve),
+            &inputs,
+        );
+        assert_eq!(mapped[0].truncate(), Vec3::ZERO);
+
+        let mut previous = 0.0;
+        for (i, mapped) in mapped.iter().enumerate().skip(1) {
+            assert!(mapped.x == mapped.y && mapped.y == mapped.z);
+            assert!(mapped.x >= previous, "not monotonic at {}", i as f32 * 0.05);
+            assert!(mapped.x <= 1.0);
+            previous = mapped.x;
+        }
+    }
+
+    #[test]
+    fn reinhard_is_monotonic_from_zero() {
+        check_curve("reinhard");
+    }
+
+    #[test]
+    fn aces_is_monotonic_from_zero() {
+        check_curve("aces");
+    }
+
+    #[test]
+    fn renders_into_the_surface_format() {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let dimensions = (4, 4);
+        // 1.0 in every channel
+        let white = [half::f16::ONE; 4 * 4 * 4];
+        let scene_color = rm.create_texture(&TextureDesc {
+            dimensions,
+            format: SCENE_COLOR_FORMAT,
+            initial_data: Some(bytemuck::cast_slice(&white)),
+            ..Default::default()
+        });
+        // Not the Bgra8UnormSrgb it used to be hardcoded to
+        assert_eq!(
+            rm.surface_configuration.format,
+            TextureFormat::Rgba8UnormSrgb
+        );
+        let display = rm.create_texture(&TextureDesc {
+            dimensions,
+            format: rm.surface_configuration.format,
+            ..Default::default()
+        });
+        let tone_mapping = ToneMapping::new(&mut rm, scene_color).unwrap();
+
+        let mut encoder = rm
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        tone_mapping.pass(&rm, &mut encoder, rm.get_texture(display).view(), true);
+        rm.finish_staging();
+        rm.queue.submit(std::iter::once(encoder.finish()));
+        rm.recall_staging();
+
+        assert!(rm.read_texture(display).iter().all(|&byte| byte == 255));
+    }
+}
@@ -0,0 +1,529 @@
+use wgpu::{CommandEncoder, ShaderStages, TextureSampleType, TextureUsages, TextureView};
+
+use crate::{
+    ao_technique::AoTechnique,
+    crytek_ssao::{AOOutput, CrytekSSAO, SSAOCompositeUniformData, AO_FORMAT},
+    resource_manager::{
+        BindGroupDesc, BindGroupLayoutDesc, BlendComponent, BlendFactor, BlendOperation,
+        BlendState, BufferDesc, BufferUsages, Handle, ResourceError, ResourceManager, ShaderDesc,
+        ShaderModuleDesc, ShaderPipelineDesc, TextureDesc, TextureViewDimension,
+        SCENE_COLOR_FORMAT,
+    },
+    scene::{bytemuck_impl, Scene},
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct VisibilityBitmaskUniformData {
+    pub radius: f32,
+    pub thickness: f32,
+    pub slice_count: u32,
+    pub step_count: u32,
+}
+bytemuck_impl!(VisibilityBitmaskUniformData);
+
+/// Horizon based AO that tracks which of 32 sectors per slice are occluded instead of a single
+/// horizon angle, so thin occluders don't darken everything behind them. Shares the blur and
+/// composite shaders with `CrytekSSAO`.
+pub struct VisibilityBitmaskAO {
+    uniform_buffer: Handle,
+    bind_group: Handle,
+    shader: Handle,
+
+    raw_ao: Handle,
+    blurred_ao: Handle,
+    blur_bind_group: Handle,
+    blur_shader: Handle,
+    composite_uniform_buffer: Handle,
+    composite_bind_group: Handle,
+    composite_shader: Handle,
+    apply_shader: Handle,
+
+    /// View-space distance marched along each slice
+    pub radius: f32,
+    /// View-space depth assumed behind every sample, larger values occlude more like plain HBAO
+    pub thickness: f32,
+    pub slice_count: u32,
+    /// Samples per side of each slice
+    pub step_count: u32,
+    pub output: AOOutput,
+    pub power: f32,
+    pub intensity: f32,
+    /// Brightens AO on light albedos to approximate interreflections
+    pub multi_bounce: bool,
+}
+
+impl VisibilityBitmaskAO {
+    pub fn new(
+        rm: &mut ResourceManager,
+        depth_buffer: Handle,
+        normal_buffer: Handle,
+        albedo: Handle,
+    ) -> Result<Self, ResourceError> {
+        // Written every pass
+        let uniform_buffer = rm.create_buffer(&BufferDesc {
+            label: Some("Visibility bitmask AO uniform buffer"),
+            byte_size: std::mem::size_of::<VisibilityBitmaskUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
+        });
+
+        let bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: VisibilityBitmaskAO::bind_group_layout(),
+            buffers: &[uniform_buffer],
+            textures: &[depth_buffer, normal_buffer],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        let shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Visibility bitmask AO shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/visibility_bitmask_ao.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/visibility_bitmask_ao.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![
+                Scene::scene_bind_group_layout(),
+                VisibilityBitmaskAO::bind_group_layout(),
+            ],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![AO_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let ao_texture_desc = TextureDesc {
+            label: Some("Visibility bitmask raw AO"),
+            dimensions: (
+                rm.surface_configuration.width,
+                rm.surface_configuration.height,
+            ),
+            mipmaps: None,
+            sample_count: 1,
+            layers: 1,
+            view_dimension: TextureViewDimension::D2,
+            format: AO_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            initial_data: None,
+        };
+        let raw_ao = rm.create_texture(&ao_texture_desc);
+        let blurred_ao = rm.create_texture(&TextureDesc {
+            label: Some("Visibility bitmask blurred AO"),
+            ..ao_texture_desc
+        });
+
+        let blur_bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: CrytekSSAO::blur_bind_group_layout(),
+            buffers: &[],
+            textures: &[raw_ao],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        let blur_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Visibility bitmask AO blur shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_blur.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_blur.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![CrytekSSAO::blur_bind_group_layout()],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![AO_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        })?;
+
+        let composite_uniform_buffer = rm.create_buffer(&BufferDesc {
+            label: Some("Visibility bitmask AO composite uniform buffer"),
+            byte_size: std::mem::size_of::<SSAOCompositeUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            initial_data: None,
+        });
+
+        let composite_bind_group = rm.create_bind_group(&BindGroupDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            layout: CrytekSSAO::composite_bind_group_layout(),
+            buffers: &[composite_uniform_buffer],
+            textures: &[raw_ao, blurred_ao, albedo],
+            samplers: &[],
+            storage_buffers: &[],
+            storage_textures: &[],
+        })?;
+
+        let composite_shader_desc = ShaderDesc {
+            label: Some(String::from("Visibility bitmask AO composite shader")),
+            vs: ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_composite.wgsl"),
+                entry_func: String::from("vs_main"),
+            },
+            ps: Some(ShaderModuleDesc {
+                path: String::from("src/shaders/ssao_composite.wgsl"),
+                entry_func: String::from("fs_main"),
+            }),
+            bind_group_layouts: vec![CrytekSSAO::composite_bind_group_layout()],
+            push_constant_ranges: vec![],
+            pipeline_state: ShaderPipelineDesc {
+                depth_test: None,
+                targets: vec![SCENE_COLOR_FORMAT],
+                vertex_buffer_bindings: vec![],
+                ..Default::default()
+            },
+        };
+        let composite_shader = rm.create_shader(composite_shader_desc.clone())?;
+
+        // Multiplies the AO into what's already in the target
+        let multiply = BlendComponent {
+            src_factor: BlendFactor::Dst,
+            dst_factor: BlendFactor::Zero,
+            operation: BlendOperation::Add,
+        };
+        let apply_shader = rm.create_shader(ShaderDesc {
+            label: Some(String::from("Visibility bitmask AO apply shader")),
+            pipeline_state: ShaderPipelineDesc {
+                blends: vec![Some(BlendState {
+                    color: multiply,
+                    alpha: BlendComponent::OVER,
+                })],
+                ..composite_shader_desc.pipeline_state
+            },
+            ..composite_shader_desc
+        })?;
+
+        Ok(Self {
+            uniform_buffer,
+            bind_group,
+            shader,
+            raw_ao,
+            blurred_ao,
+            blur_bind_group,
+            blur_shader,
+            composite_uniform_buffer,
+            composite_bind_group,
+            composite_shader,
+            apply_shader,
+            radius: 0.5,
+            thickness: 0.25,
+            slice_count: 2,
+            step_count: 8,
+            output: AOOutput::Blurred,
+            power: 1.0,
+            intensity: 1.0,
+            multi_bounce: false,
+        })
+    }
+
+    /// Copies the parameters set through the UI into the uniform buffer.
+    fn write_uniforms(&self, rm: &ResourceManager) {
+        rm.update_buffer(
+            self.uniform_buffer,
+            bytemuck::cast_slice(&[VisibilityBitmaskUniformData {
+                radius: self.radius,
+                thickness: self.thickness,
+                slice_count: self.slice_count,
+                step_count: self.step_count,
+            }]),
+        );
+    }
+
+    pub fn bind_group_layout() -> BindGroupLayoutDesc {
+        BindGroupLayoutDesc {
+            label: None,
+            visibility: ShaderStages::FRAGMENT,
+            buffers: vec![std::mem::size_of::<VisibilityBitmaskUniformData>()],
+            textures: vec![
+                (TextureSampleType::Depth, TextureViewDimension::D2),
+                (
+                    TextureSampleType::Float { filterable: true },
+                    TextureViewDimension::D2,
+                ),
+            ],
+            samplers: vec![],
+            storage_buffers: vec![],
+            storage_textures: vec![],
+            dynamic_offset: false,
+        }
+    }
+
+    fn fullscreen_pass(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        target: Handle,
+        shader: Handle,
+        bind_groups: &[Handle],
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Visibility bitmask AO"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: rm.get_texture(target).view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        pass.set_pipeline(rm.get_shader(shader).pipeline());
+        for (i, bind_group) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(i as u32, rm.get_bind_group(*bind_group), &[]);
+        }
+        pass.draw(0..6, 0..1);
+    }
+}
+
+impl AoTechnique for VisibilityBitmaskAO {
+    fn name(&self) -> &'static str {
+        "Visibility bitmask AO"
+    }
+
+    fn ui(&mut self, _rm: &mut ResourceManager, ui: &mut egui::Ui) {
+        ui.add(
+            egui::Slider::new(&mut self.radius, 0.05..=2.0)
+                .text("Radius")
+                .show_value(true),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.thickness, 0.01..=2.0)
+                .text("Thickness")
+                .show_value(true),
+        );
+        ui.add(egui::Slider::new(&mut self.slice_count, 1..=8).text("Slices"));
+        ui.add(egui::Slider::new(&mut self.step_count, 1..=32).text("Steps per side"));
+        ui.add(
+            egui::Slider::new(&mut self.power, 0.1..=4.0)
+                .text("Power")
+                .show_value(true),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.intensity, 0.0..=2.0)
+                .text("Intensity")
+                .show_value(true),
+        );
+        ui.checkbox(&mut self.multi_bounce, "Multi-bounce");
+
+        ui.horizontal(|ui| {
+            ui.label("Output");
+            ui.selectable_value(&mut self.output, AOOutput::Blurred, "Blurred");
+            ui.selectable_value(&mut self.output, AOOutput::Raw, "Raw");
+            ui.selectable_value(&mut self.output, AOOutput::Split, "Raw | blurred");
+        });
+    }
+
+    fn resize(&self, rm: &mut ResourceManager, dimensions: (u32, u32)) {
+        rm.resize_texture(self.raw_ao, dimensions);
+        rm.resize_texture(self.blurred_ao, dimensions);
+    }
+
+    fn ao_texture(&self) -> Handle {
+        self.blurred_ao
+    }
+
+    fn debug_targets(&self) -> Vec<(&'static str, Handle)> {
+        vec![("Raw AO", self.raw_ao), ("Blurred AO", self.blurred_ao)]
+    }
+
+    fn occlusion(&self, rm: &ResourceManager, encoder: &mut CommandEncoder, scene: &Scene) {
+        self.write_uniforms(rm);
+        self.fullscreen_pass(
+            rm,
+            encoder,
+            self.raw_ao,
+            self.shader,
+            &[scene.scene_uniform_bind_group, self.bind_group],
+        );
+        self.fullscreen_pass(
+            rm,
+            encoder,
+            self.blurred_ao,
+            self.blur_shader,
+            &[self.blur_bind_group],
+        );
+    }
+
+    fn composite(
+        &self,
+        rm: &ResourceManager,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        multiply: bool,
+    ) {
+        rm.update_buffer(
+            self.composite_uniform_buffer,
+            bytemuck::cast_slice(&[SSAOCompositeUniformData {
+                mode: self.output as u32,
+                power: self.power,
+                intensity: self.intensity,
+                multi_bounce: self.multi_bounce as u32,
+            }]),
+        );
+
+        let mut composite_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Visibility bitmask AO composite"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: if multiply {
+                        wgpu::LoadOp::Load
+                    } else {
+                        wgpu::LoadOp::Clear(wgpu::Color::WHITE)
+                    },
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        let shader = if multiply {
+            self.apply_shader
+        } else {
+            self.composite_shader
+        };
+        composite_pass.set_pipeline(rm.get_shader(shader).pipeline());
+        composite_pass.set_bind_group(0, rm.get_bind_group(self.composite_bind_group), &[]);
+        composite_pass.draw(0..6, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+
+    use super::*;
+    use crate::resource_manager::{TextureFormat, DEPTH_FORMAT};
+
+    /// Runs `occlusion_bits` from visibility_bitmask_ao.wgsl on each `(min_horizon, max_horizon)`.
+    fn occlusion_bits(rm: &mut ResourceManager, horizons: &[(f32, f32)]) -> Vec<u32> {
+        let inputs: Vec<Vec4> = horizons
+            .iter()
+            .map(|&(min_horizon, max_horizon)| Vec4::new(min_horizon, max_horizon, 0.0, 0.0))
+            .collect();
+        // Split in halves that f32 holds exactly
+        crate::shader_harness::eval(
+            rm,
+            "src/shaders/visibility_bitmask_ao.wgsl",
+            "vec4<f32>(f32(occlusion_bits(input.x, input.y) & 0xffffu), \
+             f32(occlusion_bits(input.x, input.y) >> 16u), 0.0, 0.0)",
+            &inputs,
+        )
+        .iter()
+        .map(|output| output.x as u32 | (output.y as u32) << 16)
+        .collect()
+    }
+
+    #[test]
+    fn uniforms_match_the_wgsl_struct() {
+        // radius, thickness, slice_count and step_count, 4 bytes each
+        assert_eq!(std::mem::size_of::<VisibilityBitmaskUniformData>(), 16);
+        let uniforms = VisibilityBitmaskUniformData {
+            radius: 0.5,
+            thickness: 1.25,
+            slice_count: 2,
+            step_count: 8,
+        };
+        let bytes = bytemuck::bytes_of(&uniforms);
+        assert_eq!(bytes[4..8], 1.25f32.to_ne_bytes());
+        assert_eq!(bytes[8..12], 2u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn thickness_reaches_the_uniform_buffer() {
+        let Some(mut rm) = ResourceManager::headless_loading_depth() else {
+            return;
+        };
+        let dimensions = (
+            rm.surface_configuration.width,
+            rm.surface_configuration.height,
+        );
+        let mut buffer = |format| {
+            rm.create_texture(&TextureDesc {
+                dimensions,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                ..Default::default()
+            })
+        };
+        let depth_buffer = buffer(DEPTH_FORMAT);
+        let normal_buffer = buffer(TextureFormat::Rgba16Float);
+        let albedo = buffer(TextureFormat::Rgba8UnormSrgb);
+        let mut technique =
+            VisibilityBitmaskAO::new(&mut rm, depth_buffer, normal_buffer, albedo).unwrap();
+
+        technique.thickness = 1.25;
+        technique.write_uniforms(&rm);
+
+        let uniforms: VisibilityBitmaskUniformData =
+            bytemuck::pod_read_unaligned(&rm.read_buffer(technique.uniform_buffer));
+        assert_eq!(uniforms.thickness, 1.25);
+        assert_eq!(uniforms.radius, technique.radius);
+    }
+
+    /// Checks `occlusion_bits` for each `(min_horizon, max_horizon, expected)`.
+    fn check(cases: &[(f32, f32, u32)]) {
+        let Some(mut rm) = ResourceManager::headless() else {
+            return;
+        };
+        let horizons: Vec<_> = cases.iter().map(|&(min, max, _)| (min, max)).collect();
+
+        for (bits, &(min, max, expected)) in occlusion_bits(&mut rm, &horizons).iter().zip(cases) {
+            assert_eq!(*bits, expected, "horizons {} to {}", min, max);
+        }
+    }
+
+    #[test]
+    fn marks_sectors_between_the_horizons() {
+        check(&[
+            (0.0, 0.0, 0),
+            (0.0, 1.0 / 32.0, 0b1),
+            (0.25, 0.5, 0x0000ff00),
+            (0.5, 1.0, 0xffff0000),
+            // Partly covered sectors count as occluded
+            (0.1, 0.2, 0b1111 << 3),
+        ]);
+    }
+
+    #[test]
+    fn full_hemisphere_sets_every_bit() {
+        check(&[
+            (0.0, 1.0, u32::MAX),
+            // count >= 32 takes the branch that avoids shifting by 32
+            (0.0, 1.5, u32::MAX),
+            (0.5, 1.5, 0xffff0000),
+        ]);
+    }
+
+    #[test]
+    fn start_near_the_last_sector() {
+        check(&[
+            (0.97, 1.0, 1 << 31),
+            (31.5 / 32.0, 1.0, 1 << 31),
+            // A horizon at exactly 1 starts past the last sector and is clamped back onto it
+            (1.0, 1.0, 0),
+            // Bits past the last sector are shifted out rather than wrapping around
+            (0.97, 1.0 + 2.0 / 32.0, 1 << 31),
+        ]);
+    }
+}